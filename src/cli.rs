@@ -1,11 +1,227 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
+use crate::log::Level;
+use crate::model::{
+    CHUNK_BYTE_LIMIT, CHUNK_H, CHUNK_W, ChunkLayout, Endianness, MAX_SCRIPT_LEN, MAX_TEXT_BYTES,
+};
+use crate::parser::LayerNames;
+use crate::processor;
+use crate::writer;
+
+/// Which artifacts to write into the output directory.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// C++ headers only
+    C,
+    /// Binary blobs only
+    Bin,
+    /// Headers and binary blobs
+    Both,
+}
+
+impl Format {
+    pub fn c(self) -> bool {
+        matches!(self, Format::C | Format::Both)
+    }
+
+    pub fn bin(self) -> bool {
+        matches!(self, Format::Bin | Format::Both)
+    }
+}
+
+// Debugging aids that look at one script instead of a whole project; not a
+// doc comment, clap would make it the help text of the whole tool.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the tokens of a script, one per line behind their position
+    Tokens(ScriptSource),
+    /// Print the commands of a script as a tree, nested blocks indented
+    Ast(ScriptSource),
+}
+
+/// A single script, given inline or read from a file.
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+pub struct ScriptSource {
+    /// Script source, e.g. `msg {hi};`
+    pub script: Option<String>,
+    /// Read the script from this file instead, `-` for stdin
+    #[arg(long, value_name = "PATH")]
+    pub file: Option<PathBuf>,
+}
+
+impl ScriptSource {
+    pub fn read(&self) -> anyhow::Result<String> {
+        match (&self.script, &self.file) {
+            (Some(script), _) => Ok(script.clone()),
+            (None, Some(path)) => crate::read_input(path, std::io::stdin().lock()),
+            (None, None) => anyhow::bail!("no script given"),
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 pub struct Cli {
-    /// Input .json map / project file
-    pub input: PathBuf,
-    /// Output directory
-    pub output: PathBuf,
-}
\ No newline at end of file
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Input .json map / project file, `-` for stdin
+    #[arg(required = true)]
+    pub input: Option<PathBuf>,
+    /// Output directory, not needed with --validate
+    #[arg(required_unless_present = "validate")]
+    pub output: Option<PathBuf>,
+    /// Name of the Tiled tile layer holding the map
+    #[arg(long, default_value = "map")]
+    pub map_layer: String,
+    /// Name of the Tiled object layer holding the scripts
+    #[arg(long, default_value = "scripts")]
+    pub scripts_layer: String,
+    /// Name of the Tiled object layer holding the locations
+    #[arg(long, default_value = "locations")]
+    pub locations_layer: String,
+    /// Skip scripts that fail to parse and build everything else
+    #[arg(long)]
+    pub keep_going: bool,
+    /// Do not warn about flags that are never read and locations that are
+    /// never referenced
+    #[arg(long)]
+    pub no_warn_unused: bool,
+    /// Fail on scripts that have no commands, e.g. only comments, instead
+    /// of dropping them
+    #[arg(long)]
+    pub no_empty_scripts: bool,
+    /// Artifacts to generate
+    #[arg(short, long, value_enum, default_value_t = Format::Both)]
+    pub format: Format,
+    /// Only check that the project compiles and print a summary; writes
+    /// nothing
+    #[arg(long)]
+    pub validate: bool,
+    /// Print the total script size and the size of every chunk
+    #[arg(long)]
+    pub stats: bool,
+    /// Print progress while loading, processing and writing
+    #[arg(short, long, conflicts_with = "quiet")]
+    pub verbose: bool,
+    /// Print nothing but errors, not even warnings
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Also write a JSON report of chunk sizes, symbol tables and warnings
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+    /// Byte order of every multi-byte value in the output
+    #[arg(long, value_enum, default_value_t = Endianness::Little)]
+    pub endianness: Endianness,
+    /// Name every generated file `<PREFIX>_<file>`, e.g. `town_scripts.bin`,
+    /// so several maps can be built into one directory
+    #[arg(long, value_name = "PREFIX", default_value = "")]
+    pub output_prefix: String,
+    /// Run-length encode map.bin
+    #[arg(long)]
+    pub compress_map: bool,
+    /// End scripts.bin and map.bin in a little-endian CRC-32 of their contents
+    #[arg(long)]
+    pub checksum: bool,
+    /// Declare every generated C++ symbol inside this namespace, `cg` when
+    /// no name is given
+    #[arg(long, value_name = "NAME", num_args = 0..=1, require_equals = true, default_missing_value = "cg")]
+    pub cpp_namespace: Option<String>,
+    /// Language of the generated headers
+    #[arg(long, value_enum, default_value_t = writer::c::Lang::Cpp)]
+    pub lang: writer::c::Lang,
+    /// Device the C++ headers are compiled for
+    #[arg(long, value_enum, default_value_t = writer::c::Target::Generic)]
+    pub target: writer::c::Target,
+    /// Also write scripts.lst, a disassembly of every chunk
+    #[arg(long)]
+    pub emit_listing: bool,
+    /// Chunk width in tiles; must divide the map width
+    #[arg(long, default_value_t = CHUNK_W)]
+    pub chunk_width: i32,
+    /// Chunk height in tiles; must divide the map height
+    #[arg(long, default_value_t = CHUNK_H)]
+    pub chunk_height: i32,
+    /// Script bytes allowed per chunk, terminator included
+    #[arg(long, default_value_t = CHUNK_BYTE_LIMIT)]
+    pub chunk_byte_limit: usize,
+    /// Reject any script whose source is longer than this many bytes
+    #[arg(long, default_value_t = MAX_SCRIPT_LEN)]
+    pub max_script_len: usize,
+    /// Reject any message longer than this many UTF-8 bytes, the size of
+    /// the VM's dialogue buffer
+    #[arg(long, default_value_t = MAX_TEXT_BYTES)]
+    pub max_text_bytes: usize,
+}
+
+impl Cli {
+    pub fn layer_names(&self) -> LayerNames {
+        LayerNames {
+            map: self.map_layer.clone(),
+            scripts: self.scripts_layer.clone(),
+            locations: self.locations_layer.clone(),
+        }
+    }
+
+    pub fn log_level(&self) -> Level {
+        if self.quiet {
+            Level::Quiet
+        } else if self.verbose {
+            Level::Verbose
+        } else {
+            Level::Warn
+        }
+    }
+
+    pub fn bin_options(&self) -> writer::bin::Options {
+        writer::bin::Options {
+            compress_map: self.compress_map,
+            checksum: self.checksum,
+            prefix: self.output_prefix.clone(),
+        }
+    }
+
+    pub fn c_options(&self) -> anyhow::Result<writer::c::Options> {
+        if let Some(ns) = &self.cpp_namespace {
+            if self.lang == writer::c::Lang::C {
+                anyhow::bail!("--cpp-namespace needs --lang cpp, C has no namespaces");
+            }
+            let ident = |part: &str| {
+                part.chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            };
+            if !ns.split("::").all(ident) {
+                anyhow::bail!("--cpp-namespace {ns:?} is not a C++ namespace name");
+            }
+        }
+        Ok(writer::c::Options {
+            namespace: self.cpp_namespace.clone(),
+            target: self.target,
+            lang: self.lang,
+            prefix: self.output_prefix.clone(),
+        })
+    }
+
+    pub fn processor_options(&self) -> anyhow::Result<processor::Options> {
+        let layout = ChunkLayout::new(self.chunk_width, self.chunk_height, self.chunk_byte_limit)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(processor::Options {
+            keep_going: self.keep_going,
+            layout,
+            endianness: self.endianness,
+            allow_unused: self.no_warn_unused,
+            max_script_len: Some(self.max_script_len),
+            max_text_bytes: Some(self.max_text_bytes),
+            reject_empty: self.no_empty_scripts,
+        })
+    }
+}
@@ -0,0 +1,53 @@
+//! Process exit codes, one per failure class.
+//!
+//! | code | meaning                                                  |
+//! |------|----------------------------------------------------------|
+//! | 0    | success                                                  |
+//! | 2    | invalid input: bad JSON, missing layers, bad options     |
+//! | 3    | a script failed to parse                                 |
+//! | 4    | a chunk's scripts exceed the chunk byte limit            |
+//! | 5    | reading the input or writing an artifact failed          |
+//!
+//! Command-line usage errors are reported by `clap`, which also exits
+//! with 2.
+
+use crate::processor::blob::ChunkOverflow;
+use crate::processor::error::{ParseError, ParseErrors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Input,
+    Script,
+    ChunkOverflow,
+    Io,
+}
+
+impl Failure {
+    /// Failure class of an error returned by [`crate::run`].
+    ///
+    /// The class comes from the typed error at the root of the chain;
+    /// anything without one is a validation error in the input.
+    pub fn of(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if cause.is::<ChunkOverflow>() {
+                return Failure::ChunkOverflow;
+            }
+            if cause.is::<ParseErrors>() || cause.is::<ParseError>() {
+                return Failure::Script;
+            }
+            if cause.is::<std::io::Error>() {
+                return Failure::Io;
+            }
+        }
+        Failure::Input
+    }
+
+    pub fn code(self) -> i32 {
+        match self {
+            Failure::Input => 2,
+            Failure::Script => 3,
+            Failure::ChunkOverflow => 4,
+            Failure::Io => 5,
+        }
+    }
+}
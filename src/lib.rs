@@ -1,31 +1,198 @@
+pub mod checksum;
 pub mod cli;
+pub mod exit;
+pub mod log;
 pub mod model;
 pub mod parser;
 pub mod processor;
 pub mod writer;
 use anyhow::Context;
 use clap::Parser;
+use std::io::Read;
+use std::path::Path;
 
 pub fn run() -> anyhow::Result<()> {
-    let args = cli::Cli::parse();
+    run_with(cli::Cli::parse())
+}
 
-    // 1. ── Parse ──────────────────────────────────────────────────────
-    let json = std::fs::read_to_string(&args.input)
-        .with_context(|| format!("Reading {}", args.input.display()))?;
-    let raw_project = parser::load(&json).with_context(|| "Parsing input JSON")?;
+/// Compile a Tiled project JSON in memory with the default layer names
+/// and options; nothing touches the filesystem.
+pub fn compile_str(json: &str) -> anyhow::Result<model::ProcessedProject> {
+    let (_, processed) = compile(json, &parser::LayerNames::default(), &Default::default())?;
+    Ok(processed)
+}
 
-    // 2. ── Process ────────────────────────────────────────────────────
+/// Load and process `json`, returning the raw project alongside the result.
+pub fn compile(
+    json: &str,
+    names: &parser::LayerNames,
+    opts: &processor::Options,
+) -> anyhow::Result<(model::RawProject, model::ProcessedProject)> {
+    let raw_project = parser::load(json, names).with_context(|| "Parsing input JSON")?;
     let processed =
-        processor::run(&raw_project).with_context(|| "Processing / assembling VM scripts")?;
+        processor::run(&raw_project, opts).with_context(|| "Processing / assembling VM scripts")?;
+    Ok((raw_project, processed))
+}
+
+/// Read the project JSON from `input`, or from `stdin` when it is `-`.
+pub fn read_input(input: &Path, mut stdin: impl Read) -> anyhow::Result<String> {
+    if input == Path::new("-") {
+        let mut json = String::new();
+        stdin
+            .read_to_string(&mut json)
+            .with_context(|| "Reading stdin")?;
+        return Ok(json);
+    }
+    std::fs::read_to_string(input).with_context(|| format!("Reading {}", input.display()))
+}
+
+/// One-paragraph overview of a compiled project for `--validate`.
+pub fn summary(raw: &model::RawProject, processed: &model::ProcessedProject) -> String {
+    let largest = processed.largest_chunk().map_or(0, |(_, bytes)| bytes);
+    format!(
+        "{} scripts, {} flags, {} locations, {} texts; largest chunk {} of {} bytes",
+        raw.scripts.objects.len(),
+        processed.flags.len(),
+        processed.locations.len(),
+        processed.texts.len(),
+        largest,
+        processed.layout.byte_limit
+    )
+}
+
+/// Script sizes for `--stats`: the total, the largest chunk and then
+/// every chunk that runs a script.
+pub fn stats(processed: &model::ProcessedProject) -> String {
+    let limit = processed.layout.byte_limit;
+    let mut out = format!(
+        "{} script bytes in {} blobs\n",
+        processed.total_size(),
+        processed.blob.blob.len()
+    );
+    if let Some((chunk, bytes)) = processed.largest_chunk() {
+        out += &format!("largest: chunk {chunk}, {bytes} of {limit} bytes\n");
+    }
+    for (chunk, bytes) in processed.chunk_sizes() {
+        out += &format!("chunk {chunk}: {bytes} bytes\n");
+    }
+    out
+}
+
+/// The tokens of `src`, one per line behind the `line:col` they start
+/// at, for the `tokens` subcommand.
+pub fn tokens(src: &str) -> anyhow::Result<String> {
+    let mut out = String::new();
+    for token in processor::lexer::Lexer::new(src) {
+        let (token, span) = token.map_err(processor::error::ParseError::from)?;
+        out += &format!("{span} {token:?}\n");
+    }
+    Ok(out)
+}
+
+/// The commands of `src`, parsed on their own, as an indented tree for
+/// the `ast` subcommand.
+pub fn ast(src: &str) -> anyhow::Result<String> {
+    let (cmds, _) = processor::script_parser::parse_script(src)?;
+    Ok(processor::ast::tree(&cmds))
+}
 
-    // 3. ── Write outputs ──────────────────────────────────────────────
-    std::fs::create_dir_all(&args.output)
-        .with_context(|| format!("Creating {}", args.output.display()))?;
+/// Run one of the single-script subcommands.
+fn run_command(command: &cli::Command) -> anyhow::Result<()> {
+    match command {
+        cli::Command::Tokens(source) => print!("{}", tokens(&source.read()?)?),
+        cli::Command::Ast(source) => print!("{}", ast(&source.read()?)?),
+    }
+    Ok(())
+}
+
+/// `run` with already parsed arguments.
+pub fn run_with(args: cli::Cli) -> anyhow::Result<()> {
+    if let Some(command) = &args.command {
+        return run_command(command);
+    }
+    log::set_level(args.log_level());
+    // a report only lists this run's warnings
+    log::take_warnings();
+
+    // 1. ── Parse and process ────────────────────────────────────────
+    let input = args
+        .input
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("no input file given"))?;
+    let json = read_input(input, std::io::stdin().lock())?;
+    let (raw_project, processed) = compile(&json, &args.layer_names(), &args.processor_options()?)?;
+
+    if let Some(path) = &args.report {
+        writer::report::emit(&raw_project, &processed, &log::take_warnings(), path)
+            .with_context(|| format!("Writing {}", path.display()))?;
+    }
+
+    if args.stats {
+        print!("{}", stats(&processed));
+    }
+
+    if args.validate {
+        println!("{}", summary(&raw_project, &processed));
+        return Ok(());
+    }
 
-    println!("Writing C headers");
-    writer::c::emit(&processed, &args.output).with_context(|| "Writing C artifacts")?;
-    println!("Writing binary artifacts");
-    writer::bin::emit(&processed, &args.output).with_context(|| "Writing binary artifacts")?;
+    // 2. ── Write outputs ──────────────────────────────────────────────
+    let output = args
+        .output
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("no output directory given"))?;
+    std::fs::create_dir_all(output).with_context(|| format!("Creating {}", output.display()))?;
+
+    if args.format.c() {
+        info!("Writing C headers");
+        writer::c::emit(&processed, output, &args.c_options()?)
+            .with_context(|| "Writing C artifacts")?;
+    }
+    if args.format.bin() {
+        info!("Writing binary artifacts");
+        writer::bin::emit(&processed, output, &args.bin_options())
+            .with_context(|| "Writing binary artifacts")?;
+    }
+    if args.emit_listing {
+        info!("Writing listing");
+        writer::listing::emit(&processed, output, &args.output_prefix)
+            .with_context(|| "Writing scripts.lst")?;
+    }
 
     Ok(())
 }
+
+/// Project JSON for tests: an all-zero map, one script object per
+/// `(x, y, source)` and no locations.
+#[cfg(test)]
+pub(crate) fn project_with_scripts(scripts: &[(f32, f32, &str)]) -> String {
+    project_with_locations(scripts, &[])
+}
+
+/// Like [`project_with_scripts`], plus one location object per
+/// `(name, x, y)`; objects are numbered from 1, scripts first.
+#[cfg(test)]
+pub(crate) fn project_with_locations(
+    scripts: &[(f32, f32, &str)],
+    locations: &[(&str, f32, f32)],
+) -> String {
+    use serde_json::json;
+
+    let scripts: Vec<_> = (1..)
+        .zip(scripts)
+        .map(|(id, (x, y, src))| {
+            json!({ "id": id, "x": x, "y": y,
+                    "properties": [{ "name": "script", "value": src }] })
+        })
+        .collect();
+    let locations: Vec<_> = (scripts.len() + 1..)
+        .zip(locations)
+        .map(|(id, (name, x, y))| json!({ "id": id, "name": name, "x": x, "y": y }))
+        .collect();
+    json!({ "layers": [
+        { "name": "map", "data": vec![0; 256 * 256] },
+        { "name": "scripts", "objects": scripts },
+        { "name": "locations", "objects": locations },
+    ] })
+    .to_string()
+}
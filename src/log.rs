@@ -0,0 +1,62 @@
+//! Level-gated progress output.
+//!
+//! Progress messages (`info!`) only show with `--verbose`; warnings
+//! (`warn!`) show unless `--quiet` is given and go to stderr. Warnings are
+//! also kept, whatever the level, so `--report` can list them.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Errors only.
+    Quiet = 0,
+    /// Errors and warnings, the default.
+    Warn,
+    /// Everything, including progress.
+    Verbose,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages of `level` are currently printed.
+pub fn enabled(level: Level) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level as u8
+}
+
+/// Keep a warning for [`take_warnings`] and print it unless `--quiet`.
+pub fn warning(msg: String) {
+    if enabled(Level::Warn) {
+        eprintln!("Warning: {}", msg);
+    }
+    WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).push(msg);
+}
+
+/// Every warning issued since the last call.
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Progress message, printed with `--verbose`.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Verbose) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Warning, printed to stderr unless `--quiet`.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log::warning(format!($($arg)*))
+    };
+}
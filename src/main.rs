@@ -1,3 +1,8 @@
-fn main() -> anyhow::Result<()> {
-    pokervm_rust::run()
+use pokervm_rust::exit::Failure;
+
+fn main() {
+    if let Err(e) = pokervm_rust::run() {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(Failure::of(&e).code());
+    }
 }
@@ -2,6 +2,10 @@
 pub const MAP_W: i32 = 256;
 pub const MAP_H: i32 = 256;
 
+/// Tile of a map cell Tiled leaves empty (gid 0) once gids are mapped to
+/// tileset indices; no tileset index takes it.
+pub const EMPTY_TILE: u16 = u16::MAX;
+
 pub const CHUNK_W: i32 = 8;
 pub const CHUNK_H: i32 = 4;
 
@@ -9,13 +13,117 @@ pub const CHUNK_COLS: i32 = MAP_W / CHUNK_W; // 32
 pub const CHUNK_ROWS: i32 = MAP_H / CHUNK_H; // 64
 pub const TOTAL_CHUNKS: usize = (CHUNK_COLS * CHUNK_ROWS) as usize;
 
+/// Bytes of script the VM loads per chunk, including the `0xFF` terminator.
+pub const CHUNK_BYTE_LIMIT: usize = 128;
+
+/// Longest script source accepted by default; far more than a chunk can
+/// hold, it only stops runaway properties.
+pub const MAX_SCRIPT_LEN: usize = 100_000;
+
+/// Bytes of the VM's dialogue buffer, the longest message it can show.
+pub const MAX_TEXT_BYTES: usize = 255;
+
+/// Byte order of every multi-byte value in the generated artifacts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Endianness {
+    /// Least significant byte first, what the VM expects
+    #[default]
+    Little,
+    /// Most significant byte first
+    Big,
+}
+
+impl Endianness {
+    pub fn u16_bytes(self, v: u16) -> [u8; 2] {
+        match self {
+            Endianness::Little => v.to_le_bytes(),
+            Endianness::Big => v.to_be_bytes(),
+        }
+    }
+
+    pub fn read_u16(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endianness::Little => u16::from_le_bytes(bytes),
+            Endianness::Big => u16::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// Chunk grid scripts and map are split into, and the per-chunk script
+/// budget. The default is the grid described by the constants above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLayout {
+    pub width: i32,
+    pub height: i32,
+    pub byte_limit: usize,
+}
+
+impl ChunkLayout {
+    /// A layout whose chunks tile the map exactly.
+    pub fn new(width: i32, height: i32, byte_limit: usize) -> Result<Self, String> {
+        if width <= 0 || MAP_W % width != 0 {
+            return Err(format!(
+                "chunk width {width} does not divide the map width {MAP_W}"
+            ));
+        }
+        if height <= 0 || MAP_H % height != 0 {
+            return Err(format!(
+                "chunk height {height} does not divide the map height {MAP_H}"
+            ));
+        }
+        if byte_limit == 0 {
+            return Err("chunk byte limit must leave room for the terminator".into());
+        }
+        Ok(Self {
+            width,
+            height,
+            byte_limit,
+        })
+    }
+
+    pub fn cols(&self) -> i32 {
+        MAP_W / self.width
+    }
+
+    pub fn rows(&self) -> i32 {
+        MAP_H / self.height
+    }
+
+    pub fn total(&self) -> usize {
+        (self.cols() * self.rows()) as usize
+    }
+
+    /// Row-major index of the chunk holding tile (`x`, `y`).
+    pub fn index(&self, x: i32, y: i32) -> usize {
+        ((y / self.height) * self.cols() + x / self.width) as usize
+    }
+}
+
+impl Default for ChunkLayout {
+    fn default() -> Self {
+        Self {
+            width: CHUNK_W,
+            height: CHUNK_H,
+            byte_limit: CHUNK_BYTE_LIMIT,
+        }
+    }
+}
+
 use crate::processor::ast::Cmd;
-use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+/// `key: value` pairs of a script's front matter, by key.
+pub type Metadata = BTreeMap<String, String>;
 
 #[derive(Debug, Clone)]
 pub struct Script {
+    /// Tiled object id of the script.
+    pub id: i32,
+    /// `name` property, set on scripts `call` can run.
+    pub name: Option<String>,
     pub script: String,
+    /// Front matter of `script`, empty without one.
+    pub meta: Metadata,
     pub body: Vec<Cmd>,
     pub x: i32,
     pub y: i32,
@@ -24,12 +132,18 @@ pub struct Script {
 #[derive(Debug, Clone)]
 pub struct ParsedScripts {
     /// Chunked representation; `chunks[idx]` holds all scripts that belong
-    /// to that chunk – vector length is always `TOTAL_CHUNKS`.
+    /// to that chunk – vector length is the layout's chunk count
+    /// (`TOTAL_CHUNKS` by default).
     pub chunks: Vec<Vec<Script>>,
 
     pub tags: HashMap<String, u16>,
     pub flags: HashMap<String, u16>,
     pub texts: HashMap<String, u16>,
+    pub vars: HashMap<String, u16>,
+    /// Named scripts some `call` refers to.
+    pub scripts: HashMap<String, u16>,
+    /// Object ids of scripts that parsed to no commands and were dropped.
+    pub empty: Vec<i32>,
 }
 
 /// Entire project as it comes out of the JSON loader.
@@ -49,13 +163,20 @@ pub struct RawTiled {
 pub type MapLayer = Vec<u16>;
 
 /// A single "script object" coming from the Tiled layer.
-/// Only the 3 fields required by the compiler/VM are kept.
-#[derive(Debug, Clone)]
+/// Besides the script itself only its position and the optional typed
+/// custom properties are kept.
+#[derive(Debug, Clone, Default)]
 pub struct ScriptEntry {
     pub id: i32,
     pub script: String,
     pub x: f32,
     pub y: f32,
+    /// `oneshot` bool property: run the script only once.
+    pub oneshot: bool,
+    /// `priority` int property; 0 when absent.
+    pub priority: i32,
+    /// `name` string property: other scripts can `call @name` it.
+    pub name: Option<String>,
 }
 
 /// Holds **all** objects that belong to Tiled's "script" layer.
@@ -91,6 +212,42 @@ pub struct ProcessedProject {
     pub blob: blob::ProcessedScripts,
     pub flags: HashMap<String, u16>,
     pub locations: HashMap<String, u16>,
+    /// Tile of every location on the map, by name.
+    pub location_tiles: HashMap<String, (u16, u16)>,
     pub texts: HashMap<String, u16>,
+    /// Named scripts some `call` runs, name → call index; `blob.calls`
+    /// holds where each of them starts.
+    pub scripts: HashMap<String, u16>,
+    /// Front matter of every script that has one, by object id.
+    pub metadata: BTreeMap<i32, Metadata>,
     pub map: Vec<MapLayer>,
+    /// Grid `blob` and `map` were chunked with.
+    pub layout: ChunkLayout,
+    /// Byte order `blob` was encoded in; writers use it for everything else.
+    pub endianness: Endianness,
+}
+
+impl ProcessedProject {
+    /// Script bytes the VM stores, terminators included; chunks sharing a
+    /// blob count it once.
+    pub fn total_size(&self) -> usize {
+        self.blob.blob.iter().map(|b| b.blob.len()).sum()
+    }
+
+    /// `(chunk, bytes)` of every chunk that runs a script, in chunk order.
+    pub fn chunk_sizes(&self) -> Vec<(usize, usize)> {
+        (0..self.blob.index.len())
+            .map(|chunk| (chunk, self.blob.chunk(chunk)))
+            .filter(|(_, blob)| !blob.offsets.is_empty())
+            .map(|(chunk, blob)| (chunk, blob.blob.len()))
+            .collect()
+    }
+
+    /// The first of the largest chunks, `None` without any scripts.
+    pub fn largest_chunk(&self) -> Option<(usize, usize)> {
+        self.chunk_sizes()
+            .into_iter()
+            .rev()
+            .max_by_key(|&(_, bytes)| bytes)
+    }
 }
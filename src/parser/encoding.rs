@@ -0,0 +1,434 @@
+//! Decoders for Tiled's encoded layer `data`: base64, optionally zlib or
+//! gzip compressed. Small enough not to pull in crates for them.
+
+use crate::checksum::crc32;
+use anyhow::{Result, anyhow, bail};
+
+/// Standard base64, padding optional; whitespace is skipped since Tiled
+/// wraps long strings in the XML format and some tools keep the breaks.
+pub fn base64(text: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut padding = false;
+    for (i, c) in text.bytes().enumerate() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding = true;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => continue,
+            _ => bail!("invalid base64 character {:?} at {i}", c as char),
+        };
+        if padding {
+            bail!("base64 data continues after `=` padding at {i}");
+        }
+        acc = acc << 6 | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Inflate a zlib stream (RFC 1950) of at most `limit` bytes, checking its
+/// Adler-32.
+pub fn zlib(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let [cmf, flg, ..] = *data else {
+        bail!("zlib stream too short");
+    };
+    if cmf & 0x0f != 8 || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        bail!("not a zlib deflate stream");
+    }
+    if flg & 0x20 != 0 {
+        bail!("zlib preset dictionaries are not supported");
+    }
+    let (out, used) = inflate(&data[2..], limit)?;
+    let trailer = data
+        .get(2 + used..2 + used + 4)
+        .ok_or_else(|| anyhow!("zlib stream is missing its checksum"))?;
+    if u32::from_be_bytes(trailer.try_into().unwrap()) != adler32(&out) {
+        bail!("zlib checksum mismatch");
+    }
+    Ok(out)
+}
+
+/// Inflate a gzip member (RFC 1952) of at most `limit` bytes, checking its
+/// CRC-32 and size.
+pub fn gzip(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let short = || anyhow!("gzip stream too short");
+    if data.len() < 10 || data[..3] != [0x1f, 0x8b, 8] {
+        bail!("not a gzip deflate stream");
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or_else(short)?;
+        pos += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0));
+            pos += end.ok_or_else(short)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    let (out, used) = inflate(data.get(pos..).ok_or_else(short)?, limit)?;
+    let trailer = data
+        .get(pos + used..pos + used + 8)
+        .ok_or_else(|| anyhow!("gzip stream is missing its trailer"))?;
+    let word = |at: usize| u32::from_le_bytes(trailer[at..at + 4].try_into().unwrap());
+    if word(0) != crc32(&out) {
+        bail!("gzip checksum mismatch");
+    }
+    if word(4) != out.len() as u32 {
+        bail!("gzip size mismatch");
+    }
+    Ok(out)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+/// LSB-first bit reader over a DEFLATE stream.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl Bits<'_> {
+    fn need(&mut self, n: u32) -> Result<u32> {
+        let mut v = 0;
+        for i in 0..n {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| anyhow!("deflate stream ends early"))?;
+            v |= u32::from(byte >> self.bit & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(v)
+    }
+
+    /// Skip to the next byte boundary, as stored blocks require.
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// Canonical Huffman code: how many codes have each length, and the
+/// symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = left * 2 - i32::from(count);
+            if left < 0 {
+                bail!("deflate stream has an over-subscribed code");
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.need(1)? as i32;
+            let count = i32::from(count);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("deflate stream has an invalid code")
+    }
+}
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Inflate a raw DEFLATE stream (RFC 1951); returns the output and the
+/// number of input bytes it took up. Stops with an error once the output
+/// would pass `limit` bytes, so a small stream cannot inflate without bound.
+fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize)> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        bit: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.need(1)? == 1;
+        match bits.need(2)? {
+            0 => stored(&mut bits, &mut out, limit)?,
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let lit = Huffman::new(&lengths)?;
+                let dist = Huffman::new(&[5; 30])?;
+                codes(&mut bits, &mut out, &lit, &dist, limit)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut bits)?;
+                codes(&mut bits, &mut out, &lit, &dist, limit)?;
+            }
+            _ => bail!("deflate stream has an invalid block type"),
+        }
+        if last {
+            bits.align();
+            return Ok((out, bits.pos));
+        }
+    }
+}
+
+/// Fail unless `n` more bytes keep `out` within `limit`.
+fn room(out: &[u8], n: usize, limit: usize) -> Result<()> {
+    if out.len() + n > limit {
+        bail!("deflate stream inflates past {limit} bytes");
+    }
+    Ok(())
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>, limit: usize) -> Result<()> {
+    bits.align();
+    let header = bits
+        .data
+        .get(bits.pos..bits.pos + 4)
+        .ok_or_else(|| anyhow!("deflate stream ends early"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    if len != !u16::from_le_bytes([header[2], header[3]]) {
+        bail!("deflate stored block has a corrupt length");
+    }
+    let start = bits.pos + 4;
+    let block = bits
+        .data
+        .get(start..start + usize::from(len))
+        .ok_or_else(|| anyhow!("deflate stream ends early"))?;
+    room(out, block.len(), limit)?;
+    out.extend_from_slice(block);
+    bits.pos = start + usize::from(len);
+    Ok(())
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let nlen = bits.need(5)? as usize + 257;
+    let ndist = bits.need(5)? as usize + 1;
+    let ncode = bits.need(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        bail!("deflate stream has too many codes");
+    }
+    let mut lengths = [0u8; 19];
+    for &at in &ORDER[..ncode] {
+        lengths[at] = bits.need(3)? as u8;
+    }
+    let lencode = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = lencode.decode(bits)?;
+        let (len, times) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *i
+                    .checked_sub(1)
+                    .and_then(|p| lengths.get(p))
+                    .ok_or_else(|| anyhow!("deflate stream repeats a missing length"))?;
+                (prev, 3 + bits.need(2)?)
+            }
+            17 => (0, 3 + bits.need(3)?),
+            _ => (0, 11 + bits.need(7)?),
+        };
+        for _ in 0..times {
+            *lengths
+                .get_mut(i)
+                .ok_or_else(|| anyhow!("deflate stream has too many lengths"))? = len;
+            i += 1;
+        }
+    }
+    if lengths[256] == 0 {
+        bail!("deflate stream has no end-of-block code");
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen])?,
+        Huffman::new(&lengths[nlen..])?,
+    ))
+}
+
+fn codes(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    lit: &Huffman,
+    dist: &Huffman,
+    limit: usize,
+) -> Result<()> {
+    loop {
+        let symbol = usize::from(lit.decode(bits)?);
+        match symbol {
+            0..=255 => {
+                room(out, 1, limit)?;
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            _ => {
+                let at = symbol - 257;
+                if at >= LEN_BASE.len() {
+                    bail!("deflate stream has an invalid length code");
+                }
+                let len = usize::from(LEN_BASE[at]) + bits.need(LEN_EXTRA[at].into())? as usize;
+                let at = usize::from(dist.decode(bits)?);
+                if at >= DIST_BASE.len() {
+                    bail!("deflate stream has an invalid distance code");
+                }
+                let back = usize::from(DIST_BASE[at]) + bits.need(DIST_EXTRA[at].into())? as usize;
+                if back > out.len() {
+                    bail!("deflate stream refers back past its start");
+                }
+                room(out, len, limit)?;
+                for _ in 0..len {
+                    out.push(out[out.len() - back]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(base64("aGVs\nbG8").unwrap(), b"hello");
+        assert_eq!(base64("").unwrap(), b"");
+        assert!(base64("aGV*").is_err());
+        assert!(base64("aG=Vs").is_err());
+    }
+
+    #[test]
+    fn test_inflate_each_block_type() {
+        // `hello hello hello` as produced by zlib at levels 0 and 9, then a
+        // longer text that gets a dynamic table
+        let stored = base64("eAEBEQDu/2hlbGxvIGhlbGxvIGhlbGxvOi4GfQ==").unwrap();
+        let fixed = base64("eNrLSM3JyVfIQJAAOi4GfQ==").unwrap();
+        assert_eq!(zlib(&stored, 17).unwrap(), b"hello hello hello");
+        assert_eq!(zlib(&fixed, 17).unwrap(), b"hello hello hello");
+
+        let text: String = (0..60)
+            .map(|i| format!("{i} squared is {}; ", i * i))
+            .collect();
+        let dynamic = base64(DYNAMIC).unwrap();
+        assert_eq!(dynamic[2] >> 1 & 3, 2);
+        assert_eq!(zlib(&dynamic, LIMIT).unwrap(), text.as_bytes());
+
+        let mut corrupt = fixed.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(zlib(&corrupt, LIMIT).is_err());
+        assert!(zlib(&fixed[..fixed.len() - 5], LIMIT).is_err());
+    }
+
+    #[test]
+    fn test_gzip() {
+        let data = base64("H4sIAAAAAAACA8tIzcnJV8hAkACAiPnlEQAAAA==").unwrap();
+        assert_eq!(gzip(&data, LIMIT).unwrap(), b"hello hello hello");
+        assert!(gzip(&data[..data.len() - 1], LIMIT).is_err());
+        assert!(gzip(b"not gzip at all", LIMIT).is_err());
+    }
+
+    #[test]
+    fn test_inflate_stops_at_the_limit() {
+        let stored = base64("eAEBEQDu/2hlbGxvIGhlbGxvIGhlbGxvOi4GfQ==").unwrap();
+        let fixed = base64("eNrLSM3JyVfIQJAAOi4GfQ==").unwrap();
+        let gzipped = base64("H4sIAAAAAAACA8tIzcnJV8hAkACAiPnlEQAAAA==").unwrap();
+        for result in [zlib(&stored, 16), zlib(&fixed, 16), gzip(&gzipped, 16)] {
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "deflate stream inflates past 16 bytes"
+            );
+        }
+    }
+
+    const LIMIT: usize = 4096;
+
+    const DYNAMIC: &str = "\
+        eNpdk0tuBSEMBK/CEfAPBs1pIiWLLJOn3D/0rKi3xLLs6qbd2+vn7+P367N9v1q/m51vu5uf77xbnO91t0T/uFudBa+7jbMQu2Ni\
+        5J5xnYWxl6yzcG0K61jTBUpSVxdgLfckC/LtbUbktYmM0KK2QR3qArhfmgX0cG1c1CsPQZ+id9BnqotWX3uWg758b3TQ19xcDvoh\
+        egf9eLpAP59ZoJ/PRlovrgD9En2Afklj0PsuK4LmdzkWdN9kbBS/UgpisKZvCkiw0G/G9fbn2gsRVspGMkFDMpIRGkpaUsdUHJM6\
+        LmU231KkYCdj1KUjmSPTiSSD5F3zoMOjay90ePbNV525lI6CDh9PH3T41LwKBlh7Czp8PScMHfHoKJ6xSW/Nt/RrHi8i5F/xJFI+\
+        /wOymk5V";
+}
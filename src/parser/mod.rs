@@ -1,72 +1,121 @@
-use anyhow::{Result, anyhow};
+pub(crate) mod encoding;
+mod tiled;
+
+use anyhow::{Context, Result, anyhow};
 use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use tiled::{Layer, Object, Property};
 
 use crate::model::{
-    LocationEntry, LocationLayer, MapLayer, RawProject, RawTiled, ScriptEntry, ScriptLayer,
+    EMPTY_TILE, LocationEntry, LocationLayer, MAP_H, MAP_W, MapLayer, RawProject, RawTiled,
+    ScriptEntry, ScriptLayer,
 };
+use crate::{info, warn};
+
+/// Scripts longer than this many bytes are loaded with a warning.
+const LONG_SCRIPT: usize = 10_000;
+
+/// Names of the three layers the loader looks for.
+///
+/// Defaults match the reference world map; projects with other naming
+/// conventions override them from the CLI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerNames {
+    pub map: String,
+    pub scripts: String,
+    pub locations: String,
+}
+
+impl LayerNames {
+    /// Whether `name` is the scripts layer. `script` and `scripts` are
+    /// treated as the same name since Tiled files in the wild use both.
+    fn is_scripts(&self, name: &str) -> bool {
+        fn spelling(n: &str) -> &str {
+            if n == "script" { "scripts" } else { n }
+        }
+        spelling(name) == spelling(&self.scripts)
+    }
+}
+
+impl Default for LayerNames {
+    fn default() -> Self {
+        Self {
+            map: "map".into(),
+            scripts: "scripts".into(),
+            locations: "locations".into(),
+        }
+    }
+}
 
 /// Parse the whole input JSON string into `RawProject`.
 ///
 /// The Tiled file is expected to contain a top-level `layers` array with
-/// exactly three entries whose `name` property equals one of
+/// three entries whose `name` property equals one of `names`
+/// (by default)
 ///   • "map"
-///   • "script"
+///   • "scripts" (or "script")
 ///   • "locations"
 ///
-/// Any additional layer or a missing one is reported as an error.
-pub fn load(json: &str) -> Result<RawProject> {
-    println!("File loaded, size: {} bytes", json.len());
-    let tiled = load_from_json(&json).map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
-    println!("JSON parsed successfully");
+/// Layers inside `group` layers are found at any depth. A missing layer is
+/// reported as an error; any other layer (background, collision, …) is
+/// skipped with a warning.
+pub fn load(json: &str, names: &LayerNames) -> Result<RawProject> {
+    info!("File loaded, size: {} bytes", json.len());
+    let tiled = load_from_json(json, names).map_err(|e| anyhow!("Failed to parse JSON: {}", e))?;
+    info!("JSON parsed successfully");
 
     let raw = tiled_to_raw(&tiled);
     Ok(raw)
 }
 
-pub fn load_from_json(json: &str) -> Result<RawTiled> {
-    // Grab the entire file as a dynamic value first.
-    let root: Value = serde_json::from_str(json)?;
+pub fn load_from_json(json: &str, names: &LayerNames) -> Result<RawTiled> {
+    let root: tiled::Map = serde_json::from_str(json)?;
 
     let layers = root
-        .get("layers")
-        .and_then(|v| v.as_array())
+        .layers
+        .as_deref()
         .ok_or_else(|| anyhow!("file has no `layers` array"))?;
 
-    println!("Found {} layers", layers.len());
+    let mut flat = Vec::with_capacity(layers.len());
+    flatten_layers(layers, &mut flat);
+    let layers = flat;
+
+    info!("Found {} layers", layers.len());
 
     let mut map: Option<MapLayer> = None;
     let mut scripts: Option<ScriptLayer> = None;
     let mut locations: Option<LocationLayer> = None;
 
     for (i, layer_val) in layers.iter().enumerate() {
-        // We only need the name to decide where to deserialize.
         let name = layer_val
-            .get("name")
-            .and_then(|n| n.as_str())
+            .name
+            .as_deref()
             .ok_or_else(|| anyhow!("layer {} missing `name` field", i))?;
 
-        println!("Processing layer: {}", name);
-
-        match name {
-            "map" => {
-                map = Some(parse_map(layer_val)?);
-                println!("Map layer parsed");
-            }
-            "scripts" => {
-                scripts = Some(parse_script_layer(layer_val)?);
-                println!("Scripts layer parsed");
-            }
-            "locations" => {
-                locations = Some(parse_location_layer(layer_val)?);
-                println!("Locations layer parsed");
-            }
-            other => return Err(anyhow!("unknown layer `{other}`")),
+        info!("Processing layer: {}", name);
+
+        if name == names.map {
+            map = Some(parse_map(layer_val)?);
+            info!("Map layer parsed");
+        } else if names.is_scripts(name) {
+            scripts = Some(parse_script_layer(layer_val)?);
+            info!("Scripts layer parsed");
+        } else if name == names.locations {
+            locations = Some(parse_location_layer(layer_val)?);
+            info!("Locations layer parsed");
+        } else {
+            warn!("skipping unknown layer `{name}`");
         }
     }
 
-    let map = map.ok_or_else(|| anyhow!("`map` layer missing"))?;
-    let scripts = scripts.ok_or_else(|| anyhow!("`script` layer missing"))?;
-    let locations = locations.ok_or_else(|| anyhow!("`locations` layer missing"))?;
+    let map = map.ok_or_else(|| anyhow!("`{}` layer missing", names.map))?;
+    let map = match first_tileset(root.tilesets.as_deref())? {
+        Some(tileset) => tileset_tiles(&map, tileset)?,
+        None => map,
+    };
+    let scripts = scripts.ok_or_else(|| anyhow!("`{}` layer missing", names.scripts))?;
+    let locations = locations.ok_or_else(|| anyhow!("`{}` layer missing", names.locations))?;
 
     Ok(RawTiled {
         map,
@@ -75,107 +124,182 @@ pub fn load_from_json(json: &str) -> Result<RawTiled> {
     })
 }
 
+/// Collect every non-group layer, descending into `group` layers in order.
+fn flatten_layers<'a, 'j>(layers: &'a [Layer<'j>], out: &mut Vec<&'a Layer<'j>>) {
+    for layer in layers {
+        let is_group = layer.kind.as_deref() == Some("group");
+        match &layer.layers {
+            Some(children) if is_group => flatten_layers(children, out),
+            _ => out.push(layer),
+        }
+    }
+}
+
+/// The objects of an object layer; `what` names the layer in errors.
+fn layer_objects<'j>(layer: &Layer<'j>, what: &str) -> Result<Vec<Object<'j>>> {
+    let raw = layer
+        .objects
+        .ok_or_else(|| anyhow!("{} layer has no `objects` array", what))?;
+    serde_json::from_str(raw.get()).with_context(|| format!("Reading the {} layer objects", what))
+}
+
 // ─────────────────────────────────────────────────────
-/// Helper: parse the "script" layer into a strongly-typed struct.
-fn parse_script_layer(layer: &Value) -> Result<ScriptLayer> {
-    let obj_arr = layer
-        .get("objects")
-        .and_then(|v| v.as_array())
-        .ok_or_else(|| anyhow!("`script` layer has no `objects` array"))?;
+/// Helper: parse the scripts layer into a strongly-typed struct.
+fn parse_script_layer(layer: &Layer) -> Result<ScriptLayer> {
+    let obj_arr = layer_objects(layer, "scripts")?;
 
-    println!("Found {} script objects", obj_arr.len());
+    info!("Found {} script objects", obj_arr.len());
 
     let mut entries = Vec::<ScriptEntry>::with_capacity(obj_arr.len());
+    // id -> (object index, x, y) of the first object carrying it
+    let mut seen_ids = HashMap::<i32, (usize, f32, f32)>::new();
+    let mut seen_names = HashMap::<String, (usize, f32, f32)>::new();
 
     for (i, obj) in obj_arr.iter().enumerate() {
         if i % 100 == 0 {
-            println!("Processing script object {}/{}", i, obj_arr.len());
+            info!("Processing script object {}/{}", i, obj_arr.len());
         }
 
-        let x = obj
-            .get("x")
-            .and_then(|v| v.as_f64())
-            .ok_or_else(|| anyhow!("object {} missing `x`", i))? as f32;
-
-        let y = obj
-            .get("y")
-            .and_then(|v| v.as_f64())
-            .ok_or_else(|| anyhow!("object {} missing `y`", i))? as f32;
+        let (x, y) = object_position(obj, i)?;
 
-        let id = obj
-            .get("id")
-            .and_then(|v| v.as_f64())
-            .ok_or_else(|| anyhow!("object {} missing `id`", i))? as i32;
+        let id = obj.id.ok_or_else(|| anyhow!("object {} missing `id`", i))? as i32;
 
         // Locate the property whose name == "script"
         let script_value = obj
-            .get("properties")
-            .and_then(|v| v.as_array())
-            .and_then(|props| {
-                props.iter().find_map(|p| {
-                    match (
-                        p.get("name").and_then(|n| n.as_str()),
-                        p.get("value").and_then(|v| v.as_str()),
-                    ) {
-                        (Some("script"), Some(val)) => {
-                            // Limit script length to prevent system issues
-                            if val.len() > 10000 {
-                                println!(
-                                    "Warning: script at ({}, {}) is very long: {} chars",
-                                    x,
-                                    y,
-                                    val.len()
-                                );
-                            }
-                            Some(val.to_string())
-                        }
-                        _ => None,
+            .properties
+            .iter()
+            .find_map(|p| match (p.name.as_deref(), p.value.as_str()) {
+                (Some("script"), Some(val)) => {
+                    // the hard limit is `--max-script-len`, checked when
+                    // processing
+                    if val.len() > LONG_SCRIPT {
+                        warn!("script at ({}, {}) is very long: {} chars", x, y, val.len());
                     }
-                })
+                    Some(val.to_string())
+                }
+                _ => None,
             })
             .ok_or_else(|| anyhow!("object {} at ({}, {}) missing `script` property", i, x, y))?;
 
+        if let Some(&(first_i, first_x, first_y)) = seen_ids.get(&id) {
+            return Err(anyhow!(
+                "duplicate script id {}: object {} at ({}, {}) and object {} at ({}, {})",
+                id,
+                first_i,
+                first_x,
+                first_y,
+                i,
+                x,
+                y
+            ));
+        }
+        seen_ids.insert(id, (i, x, y));
+
+        let props = &obj.properties;
+        let oneshot = typed_property(props, "oneshot", "bool", Value::as_bool)
+            .map_err(|e| anyhow!("object {} at ({}, {}): {}", i, x, y, e))?
+            .unwrap_or(false);
+        let priority = typed_property(props, "priority", "int", Value::as_i64)
+            .map_err(|e| anyhow!("object {} at ({}, {}): {}", i, x, y, e))?
+            .map(i32::try_from)
+            .transpose()
+            .map_err(|_| anyhow!("object {} at ({}, {}): `priority` out of range", i, x, y))?
+            .unwrap_or(0);
+        let name = typed_property(props, "name", "string", |v| v.as_str().map(String::from))
+            .map_err(|e| anyhow!("object {} at ({}, {}): {}", i, x, y, e))?;
+        if let Some(name) = &name {
+            if let Some(&(first_i, first_x, first_y)) = seen_names.get(name.as_str()) {
+                return Err(anyhow!(
+                    "duplicate script name {}: object {} at ({}, {}) and object {} at ({}, {})",
+                    name,
+                    first_i,
+                    first_x,
+                    first_y,
+                    i,
+                    x,
+                    y
+                ));
+            }
+            seen_names.insert(name.clone(), (i, x, y));
+        }
+
         entries.push(ScriptEntry {
             id,
             script: script_value,
             x,
             y,
+            oneshot,
+            priority,
+            name,
         });
     }
 
-    println!("Successfully parsed {} script entries", entries.len());
+    info!("Successfully parsed {} script entries", entries.len());
     Ok(ScriptLayer { objects: entries })
 }
 
-fn parse_location_layer(layer: &Value) -> Result<LocationLayer> {
-    let obj_arr = layer
-        .get("objects")
-        .and_then(|v| v.as_array())
-        .ok_or_else(|| anyhow!("`script` layer has no `objects` array"))?;
+/// Top-left corner of object `i`, in pixels.
+///
+/// Tiled places point and rectangle objects by their top-left corner but
+/// tile objects, the ones with a `gid`, by their bottom-left one; those
+/// are moved up by their `height` so both land on the tile they cover.
+fn object_position(obj: &Object, i: usize) -> Result<(f32, f32)> {
+    let x = obj.x.ok_or_else(|| anyhow!("object {} missing `x`", i))? as f32;
+    let mut y = obj.y.ok_or_else(|| anyhow!("object {} missing `y`", i))? as f32;
+
+    if obj.gid.is_some() {
+        let height = obj
+            .height
+            .ok_or_else(|| anyhow!("tile object {} missing `height`", i))?;
+        y -= height as f32;
+    }
+    Ok((x, y))
+}
+
+/// Look up the custom property `name` and read its value with `read`.
+///
+/// Tiled tags every property with its `type`; a property declared with a
+/// different type, or whose value does not match it, is an error. A missing
+/// property yields `None`.
+fn typed_property<T>(
+    props: &[Property],
+    name: &str,
+    ty: &str,
+    read: impl Fn(&Value) -> Option<T>,
+) -> Result<Option<T>> {
+    let Some(prop) = props.iter().find(|p| p.name.as_deref() == Some(name)) else {
+        return Ok(None);
+    };
+
+    let declared = prop.kind.as_deref().unwrap_or(ty);
+    if declared != ty {
+        return Err(anyhow!(
+            "property `{}` must be of type {}, got {}",
+            name,
+            ty,
+            declared
+        ));
+    }
+    let value = read(&prop.value)
+        .ok_or_else(|| anyhow!("property `{}` does not hold a {} value", name, ty))?;
+    Ok(Some(value))
+}
+
+fn parse_location_layer(layer: &Layer) -> Result<LocationLayer> {
+    let obj_arr = layer_objects(layer, "locations")?;
 
-    println!("Found {} location objects", obj_arr.len());
+    info!("Found {} location objects", obj_arr.len());
 
     let mut entries = Vec::<LocationEntry>::with_capacity(obj_arr.len());
 
     for (i, obj) in obj_arr.iter().enumerate() {
-        let x = obj
-            .get("x")
-            .and_then(|v| v.as_f64())
-            .ok_or_else(|| anyhow!("object {} missing `x`", i))? as f32;
-
-        let y = obj
-            .get("y")
-            .and_then(|v| v.as_f64())
-            .ok_or_else(|| anyhow!("object {} missing `y`", i))? as f32;
+        let (x, y) = object_position(obj, i)?;
 
-        let id = obj
-            .get("id")
-            .and_then(|v| v.as_f64())
-            .ok_or_else(|| anyhow!("object {} missing `id`", i))? as i32;
+        let id = obj.id.ok_or_else(|| anyhow!("object {} missing `id`", i))? as i32;
 
         let name = obj
-            .get("name")
-            .and_then(|v| v.as_str())
+            .name
+            .as_deref()
             .ok_or_else(|| anyhow!("object {} missing `name`", i))?;
 
         entries.push(LocationEntry {
@@ -186,22 +310,130 @@ fn parse_location_layer(layer: &Value) -> Result<LocationLayer> {
         });
     }
 
-    println!("Successfully parsed {} location entries", entries.len());
+    info!("Successfully parsed {} location entries", entries.len());
     Ok(LocationLayer { objects: entries })
 }
 
-fn parse_map(layer: &Value) -> Result<MapLayer> {
-    let data = layer
-        .get("data")
-        .and_then(|v| v.as_array())
-        .ok_or_else(|| anyhow!("`script` layer has no `objects` array"))?;
+/// Tile gids of the map layer's `data`, a plain array or, with
+/// `"encoding": "base64"`, little-endian u32s that may be zlib or gzip
+/// compressed. The 256×256 size is checked later by `map_parser` when the
+/// tiles are chunked.
+fn parse_map(layer: &Layer) -> Result<MapLayer> {
+    let encoding = layer.encoding.as_deref();
+    if encoding == Some("base64") {
+        return parse_encoded_map(layer);
+    }
+    if let Some(other) = encoding.filter(|e| *e != "csv") {
+        return Err(anyhow!("`map` layer has unknown encoding `{}`", other));
+    }
 
-    let mut buf = Vec::new();
-    for v in data {
-        let v = v.as_u64().ok_or_else(|| anyhow!("invalid map data"))?;
-        buf.push(v as u16);
+    let no_array = || anyhow!("`map` layer has no `data` array");
+    let data = layer.data.ok_or_else(no_array)?.get();
+    let gids: Vec<u64> = match serde_json::from_str(data) {
+        Ok(gids) => gids,
+        // read it again, slowly, to name the tile that is not a gid
+        Err(_) => {
+            let values: Vec<Value> = serde_json::from_str(data).map_err(|_| no_array())?;
+            let (i, v) = values
+                .iter()
+                .enumerate()
+                .find(|(_, v)| v.as_u64().is_none())
+                .ok_or_else(no_array)?;
+            return Err(anyhow!("map tile {} is not a tile id: {}", i, v));
+        }
+    };
+    gids.into_iter()
+        .enumerate()
+        .map(|(i, gid)| tile_gid(i, gid))
+        .collect()
+}
+
+fn parse_encoded_map(layer: &Layer) -> Result<MapLayer> {
+    let text: Cow<str> = layer
+        .data
+        .and_then(|data| serde_json::from_str(data.get()).ok())
+        .ok_or_else(|| anyhow!("base64 `map` layer has no `data` string"))?;
+    let bytes = encoding::base64(&text).with_context(|| "Decoding `map` layer data")?;
+    let compression = layer.compression.as_deref();
+    // a u32 per tile of the whole map
+    let limit = (MAP_W * MAP_H) as usize * 4;
+    let bytes = match compression.unwrap_or("") {
+        "" => bytes,
+        "zlib" => encoding::zlib(&bytes, limit).with_context(|| "Inflating `map` layer data")?,
+        "gzip" => encoding::gzip(&bytes, limit).with_context(|| "Inflating `map` layer data")?,
+        other => {
+            return Err(anyhow!(
+                "`map` layer has unsupported compression `{}`",
+                other
+            ));
+        }
+    };
+    if bytes.len() % 4 != 0 {
+        return Err(anyhow!(
+            "`map` layer data is {} bytes, not a whole number of tiles",
+            bytes.len()
+        ));
+    }
+
+    bytes
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(i, gid)| tile_gid(i, u32::from_le_bytes(gid.try_into().unwrap()).into()))
+        .collect()
+}
+
+/// `firstgid` of the first tileset and of the one after it, if the
+/// project lists any tilesets.
+fn first_tileset(tilesets: Option<&[tiled::Tileset]>) -> Result<Option<(u16, Option<u16>)>> {
+    let Some(tilesets) = tilesets else {
+        return Ok(None);
+    };
+    let mut firstgids = Vec::with_capacity(tilesets.len());
+    for (i, tileset) in tilesets.iter().enumerate() {
+        let firstgid = tileset
+            .firstgid
+            .ok_or_else(|| anyhow!("tileset {} has no `firstgid`", i))?;
+        let firstgid = u16::try_from(firstgid).map_err(|_| {
+            anyhow!(
+                "tileset {} has firstgid {}, which does not fit in u16",
+                i,
+                firstgid
+            )
+        })?;
+        firstgids.push(firstgid);
     }
-    Ok(buf)
+    firstgids.sort_unstable();
+    Ok(firstgids
+        .first()
+        .map(|&first| (first, firstgids.get(1).copied())))
+}
+
+/// Turn gids into indices into the first tileset, which is the one the
+/// VM draws from; Tiled's empty gid 0 becomes `EMPTY_TILE`, so it cannot
+/// be told apart from the tileset's first tile.
+fn tileset_tiles(map: &MapLayer, (first, next): (u16, Option<u16>)) -> Result<MapLayer> {
+    map.iter()
+        .enumerate()
+        .map(|(i, &gid)| {
+            if gid == 0 {
+                return Ok(EMPTY_TILE);
+            }
+            gid.checked_sub(first)
+                .filter(|&tile| tile != EMPTY_TILE && next.is_none_or(|next| gid < next))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "map tile {} has gid {}, which is not in the first tileset",
+                        i,
+                        gid
+                    )
+                })
+        })
+        .collect()
+}
+
+fn tile_gid(i: usize, gid: u64) -> Result<u16> {
+    u16::try_from(gid)
+        .map_err(|_| anyhow!("map tile {} has gid {}, which does not fit in u16", i, gid))
 }
 
 pub fn tiled_to_raw(tiled: &RawTiled) -> RawProject {
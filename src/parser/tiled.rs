@@ -0,0 +1,70 @@
+//! The parts of a Tiled JSON map the loader reads, deserialized straight
+//! from the input text and borrowing from it where possible.
+//!
+//! A layer's `data` and `objects` stay raw JSON until the layer turns out
+//! to be one of ours, so layers the loader skips may hold anything.
+//! Fields are optional where the loader has its own message for a missing
+//! one.
+
+use serde::Deserialize;
+use serde_json::Value;
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Map<'a> {
+    #[serde(borrow)]
+    pub layers: Option<Vec<Layer<'a>>>,
+    pub tilesets: Option<Vec<Tileset>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Tileset {
+    pub firstgid: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Layer<'a> {
+    #[serde(borrow)]
+    pub name: Option<Cow<'a, str>>,
+    #[serde(borrow, rename = "type")]
+    pub kind: Option<Cow<'a, str>>,
+    /// Children of a `group` layer.
+    #[serde(borrow)]
+    pub layers: Option<Vec<Layer<'a>>>,
+    /// Tile gids: an array, or a base64 string with `encoding`.
+    #[serde(borrow)]
+    pub data: Option<&'a RawValue>,
+    #[serde(borrow)]
+    pub encoding: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub compression: Option<Cow<'a, str>>,
+    /// `Object`s of an object layer.
+    #[serde(borrow)]
+    pub objects: Option<&'a RawValue>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Object<'a> {
+    pub id: Option<f64>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    /// Set on tile objects only.
+    pub gid: Option<u64>,
+    pub height: Option<f64>,
+    #[serde(borrow)]
+    pub name: Option<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    pub properties: Vec<Property<'a>>,
+}
+
+/// A custom property; its `value` has whatever JSON type `type` names.
+#[derive(Debug, Deserialize)]
+pub(super) struct Property<'a> {
+    #[serde(borrow)]
+    pub name: Option<Cow<'a, str>>,
+    #[serde(borrow, rename = "type")]
+    pub kind: Option<Cow<'a, str>>,
+    #[serde(default)]
+    pub value: Value,
+}
@@ -1,8 +1,14 @@
 //! High-level AST for one script *before* it is lowered to byte-code.
 
+use crate::model::Endianness;
+use std::sync::Arc;
+
+/// A symbol by name and index. The parser hands every message with the
+/// same wording one shared `text`; an `Arc` as scripts are parsed on
+/// rayon's threads.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Text {
-    pub text: String,
+    pub text: Arc<str>,
     pub index: u16, // numeric id assigned by the parser
 }
 
@@ -26,6 +32,21 @@ pub enum Opcode {
     SetFlag,
     UnsetFlag,
     ReadFlag,
+    Wait,
+    Give,
+    Take,
+    SetVar,
+    AddVar,
+    Choice,
+    PlaySound,
+    Warp,
+    Move,
+    Random,
+    Call,
+    Return,
+    Lock,
+    Unlock,
+    Face,
     End = 255,
 }
 
@@ -40,22 +61,52 @@ impl From<Opcode> for u8 {
 /*  AST nodes                                                                */
 /* ------------------------------------------------------------------------- */
 
+/// Encoded as a kind byte, 0 cords, 1 relative, 2 tag, then its operands;
+/// no coordinate can pass for another kind, whatever the byte order.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Location {
     Cords(u16, u16),
+    /// Tiles away from the current position.
+    Relative(i16, i16),
     Tag(Text), // ← uses Text now
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Branch {
-    ThenElse(Box<Cmd>, Box<Cmd>),
-    Then(Box<Cmd>),
+    ThenElse(Vec<Cmd>, Vec<Cmd>),
+    Then(Vec<Cmd>),
+}
+
+/// Comparison operator of a `var_x <op> <value>` condition; the numeric
+/// value is the byte written to the stream.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq = 0, // ==
+    Ne,     // !=
+    Lt,     // <
+    Gt,     // >
+}
+
+/// Where `face` turns to; the numeric value is the byte written to the
+/// stream.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up = 0,
+    Down,
+    Left,
+    Right,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Condition {
-    FlagSet(Text),   // flag_X
-    FlagClear(Text), // !flag_X
+    FlagSet(Text),                               // flag_X
+    FlagClear(Text),                             // !flag_X
+    HasItem { item: u16, count: u16 },           // has_item <item> [count]
+    VarCmp { var: Text, op: CmpOp, value: u16 }, // var_x > 5
+    And(Box<Condition>, Box<Condition>),         // a and b
+    Or(Box<Condition>, Box<Condition>),          // a or b
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -65,6 +116,12 @@ pub enum Cmd {
         text: Text,
     },
 
+    /// `note {text}`, an annotation for listings that emits no bytecode;
+    /// its text is in no table, so `text.index` means nothing.
+    Note {
+        text: Text,
+    },
+
     /// `tmsg @loc {text}`
     TMsg {
         at: Location,
@@ -96,6 +153,81 @@ pub enum Cmd {
         flag: Text,
     },
 
+    /// `wait <frames>`
+    Wait {
+        frames: u16,
+    },
+
+    /// `setvar var_x <value>`
+    SetVar {
+        var: Text,
+        value: u16,
+    },
+    /// `addvar var_x <value>`
+    AddVar {
+        var: Text,
+        value: u16,
+    },
+
+    /// `choice {prompt} [ {option} -> cmds … ] endchoice`
+    Choice {
+        prompt: Text,
+        options: Vec<(Text, Vec<Cmd>)>,
+    },
+
+    /// `call @name`, runs the script named `name` …
+    Call {
+        script: Text,
+    },
+    /// … until its `return`
+    Return,
+
+    /// `lock` – the player cannot move, for cutscenes …
+    Lock,
+    /// … until `unlock`
+    Unlock,
+
+    /// `face <up|down|left|right>`
+    Face {
+        dir: Direction,
+    },
+
+    /// `random <percent> then <cmd>`
+    Random {
+        chance: u8,
+        then: Box<Cmd>,
+    },
+
+    /// `move …`, walks from `from` to `to` where `tp` snaps
+    Move {
+        from: Location,
+        to: Location,
+    },
+
+    /// `warp <map> <loc>`, `@loc` being a location of map `map`
+    Warp {
+        map: u16,
+        to: Location,
+    },
+
+    /// `playsound <id>`
+    PlaySound {
+        id: u16,
+    },
+
+    /// `give <item> <count>`
+    Give {
+        item: u16,
+        count: u16,
+    },
+    /// `take <item> <count>`
+    Take {
+        item: u16,
+        count: u16,
+    },
+
+    /// `end` – stops the current script, also from inside an `if` branch.
+    /// The assembler still closes every chunk with its own `0xFF`.
     End,
 }
 
@@ -110,13 +242,30 @@ impl Cmd {
         "SetFlag",
         "UnsetFlag",
         "ReadFlag",
+        "Wait",
+        "Give",
+        "Take",
+        "SetVar",
+        "AddVar",
+        "Choice",
+        "PlaySound",
+        "Warp",
+        "Move",
+        "Random",
+        "Call",
+        "Return",
+        "Lock",
+        "Unlock",
+        "Face",
         "End",
     ];
 
-    /// Return the single-byte opcode for this command.
+    /// Return the single-byte opcode for this command, `None` for a note,
+    /// which is not encoded.
     #[inline(always)]
-    pub fn opcode(&self) -> Opcode {
-        match self {
+    pub fn opcode(&self) -> Option<Opcode> {
+        Some(match self {
+            Cmd::Note { .. } => return None,
             Cmd::Msg { .. } => Opcode::Msg,
             Cmd::TMsg { .. } => Opcode::TMsg,
             Cmd::Tp { .. } => Opcode::Tp,
@@ -125,7 +274,141 @@ impl Cmd {
             Cmd::SetFlag { .. } => Opcode::SetFlag,
             Cmd::UnsetFlag { .. } => Opcode::UnsetFlag,
             Cmd::ReadFlag { .. } => Opcode::ReadFlag,
+            Cmd::Wait { .. } => Opcode::Wait,
+            Cmd::Give { .. } => Opcode::Give,
+            Cmd::Take { .. } => Opcode::Take,
+            Cmd::SetVar { .. } => Opcode::SetVar,
+            Cmd::AddVar { .. } => Opcode::AddVar,
+            Cmd::Choice { .. } => Opcode::Choice,
+            Cmd::PlaySound { .. } => Opcode::PlaySound,
+            Cmd::Warp { .. } => Opcode::Warp,
+            Cmd::Move { .. } => Opcode::Move,
+            Cmd::Random { .. } => Opcode::Random,
+            Cmd::Call { .. } => Opcode::Call,
+            Cmd::Return => Opcode::Return,
+            Cmd::Lock => Opcode::Lock,
+            Cmd::Unlock => Opcode::Unlock,
+            Cmd::Face { .. } => Opcode::Face,
             Cmd::End => Opcode::End,
+        })
+    }
+
+    /// Texts of the notes in the command, nested blocks included, in
+    /// source order.
+    pub fn notes(&self) -> Vec<&str> {
+        match self {
+            Cmd::Note { text } => vec![&text.text],
+            Cmd::If { branches, .. } => {
+                let (then_cmds, else_cmds) = match branches {
+                    Branch::ThenElse(then_cmds, else_cmds) => (then_cmds, &else_cmds[..]),
+                    Branch::Then(cmds) => (cmds, &[][..]),
+                };
+                then_cmds
+                    .iter()
+                    .chain(else_cmds)
+                    .flat_map(Cmd::notes)
+                    .collect()
+            }
+            Cmd::Choice { options, .. } => options
+                .iter()
+                .flat_map(|(_, cmds)| cmds)
+                .flat_map(Cmd::notes)
+                .collect(),
+            Cmd::Random { then, .. } => then.notes(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/* ------------------------------------------------------------------------- */
+/*  Symbol references                                                        */
+/* ------------------------------------------------------------------------- */
+
+/// Symbol table a `Text` indexes into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Tag,
+    Flag,
+    Text,
+    Var,
+    Script,
+}
+
+impl Location {
+    fn for_each_symbol_mut(&mut self, f: &mut impl FnMut(SymbolKind, &mut Text)) {
+        if let Location::Tag(t) = self {
+            f(SymbolKind::Tag, t);
+        }
+    }
+}
+
+impl Condition {
+    fn for_each_symbol_mut(&mut self, f: &mut impl FnMut(SymbolKind, &mut Text)) {
+        match self {
+            Condition::FlagSet(flag) | Condition::FlagClear(flag) => f(SymbolKind::Flag, flag),
+            Condition::VarCmp { var, .. } => f(SymbolKind::Var, var),
+            Condition::HasItem { .. } => {}
+            Condition::And(lhs, rhs) | Condition::Or(lhs, rhs) => {
+                lhs.for_each_symbol_mut(f);
+                rhs.for_each_symbol_mut(f);
+            }
+        }
+    }
+}
+
+impl Cmd {
+    /// Call `f` on every symbol the command refers to, nested branches
+    /// included, in encoding order.
+    pub fn for_each_symbol_mut(&mut self, f: &mut impl FnMut(SymbolKind, &mut Text)) {
+        match self {
+            Cmd::Msg { text } => f(SymbolKind::Text, text),
+            Cmd::TMsg { at, text } => {
+                at.for_each_symbol_mut(f);
+                f(SymbolKind::Text, text);
+            }
+            Cmd::Tp { to } | Cmd::Warp { to, .. } => to.for_each_symbol_mut(f),
+            Cmd::TpIf { from, to } | Cmd::Move { from, to } => {
+                from.for_each_symbol_mut(f);
+                to.for_each_symbol_mut(f);
+            }
+            Cmd::If {
+                condition,
+                branches,
+            } => {
+                condition.for_each_symbol_mut(f);
+                let (then_cmds, else_cmds) = match branches {
+                    Branch::ThenElse(then_cmds, else_cmds) => (then_cmds, Some(else_cmds)),
+                    Branch::Then(cmds) => (cmds, None),
+                };
+                for cmd in then_cmds.iter_mut().chain(else_cmds.into_iter().flatten()) {
+                    cmd.for_each_symbol_mut(f);
+                }
+            }
+            Cmd::SetFlag { flag } | Cmd::UnsetFlag { flag } | Cmd::ReadFlag { flag } => {
+                f(SymbolKind::Flag, flag)
+            }
+            Cmd::SetVar { var, .. } | Cmd::AddVar { var, .. } => f(SymbolKind::Var, var),
+            Cmd::Random { then, .. } => then.for_each_symbol_mut(f),
+            Cmd::Call { script } => f(SymbolKind::Script, script),
+            Cmd::Choice { prompt, options } => {
+                f(SymbolKind::Text, prompt);
+                for (option, cmds) in options {
+                    f(SymbolKind::Text, option);
+                    for cmd in cmds {
+                        cmd.for_each_symbol_mut(f);
+                    }
+                }
+            }
+            Cmd::Note { .. }
+            | Cmd::Wait { .. }
+            | Cmd::PlaySound { .. }
+            | Cmd::Give { .. }
+            | Cmd::Take { .. }
+            | Cmd::Return
+            | Cmd::Lock
+            | Cmd::Unlock
+            | Cmd::Face { .. }
+            | Cmd::End => {}
         }
     }
 }
@@ -136,34 +419,120 @@ impl Cmd {
 
 /// Everything that can be put into the final byte-stream implements this trait.
 pub trait ToBytecode {
-    fn to_bytes(&self) -> Vec<u8>;
+    /// Encode with every multi-byte value in `order`.
+    fn encode(&self, order: Endianness) -> Vec<u8>;
+
+    /// Encode in the VM's default byte order.
+    fn to_bytes(&self) -> Vec<u8> {
+        self.encode(Endianness::default())
+    }
 }
 
 /* -------- Helper ---------- */
 
-fn write_u16(v: u16, out: &mut Vec<u8>) {
-    out.extend_from_slice(&v.to_be_bytes());
+fn write_u16(v: u16, order: Endianness, out: &mut Vec<u8>) {
+    out.extend_from_slice(&order.u16_bytes(v));
+}
+
+/// Encoded size of a branch body, `None` when it does not fit the `u16`
+/// jump offset the VM skips it with.
+pub fn block_len(cmds: &[Cmd]) -> Option<u16> {
+    u16::try_from(cmds.iter().map(Cmd::byte_len).sum::<usize>()).ok()
+}
+
+/// [`block_len`] of a body the parser already accepted.
+fn checked_block_len(cmds: &[Cmd]) -> u16 {
+    block_len(cmds).expect("the parser rejects branches too long to jump over")
+}
+
+/* -------- Encoded sizes ---- */
+
+impl Location {
+    pub fn byte_len(&self) -> usize {
+        match self {
+            Location::Cords(..) | Location::Relative(..) => 5,
+            Location::Tag(_) => 3,
+        }
+    }
+}
+
+impl Condition {
+    pub fn byte_len(&self) -> usize {
+        1 + match self {
+            Condition::FlagSet(_) | Condition::FlagClear(_) => 2,
+            Condition::HasItem { .. } => 4,
+            Condition::VarCmp { .. } => 5,
+            Condition::And(lhs, rhs) | Condition::Or(lhs, rhs) => lhs.byte_len() + rhs.byte_len(),
+        }
+    }
+}
+
+impl Branch {
+    pub fn byte_len(&self) -> usize {
+        let body = |cmds: &[Cmd]| 2 + cmds.iter().map(Cmd::byte_len).sum::<usize>();
+        1 + match self {
+            Branch::ThenElse(then_cmds, else_cmds) => body(then_cmds) + body(else_cmds),
+            Branch::Then(cmds) => body(cmds),
+        }
+    }
+}
+
+impl Cmd {
+    /// Number of bytes `encode` produces, computed without encoding.
+    pub fn byte_len(&self) -> usize {
+        1 + match self {
+            Cmd::Note { .. } => return 0,
+            Cmd::Msg { .. } => 2,
+            Cmd::TMsg { at, .. } => at.byte_len() + 2,
+            Cmd::Tp { to } => to.byte_len(),
+            Cmd::Warp { to, .. } => 2 + to.byte_len(),
+            Cmd::Random { then, .. } => 3 + then.byte_len(),
+            Cmd::Call { .. } => 2,
+            Cmd::Return | Cmd::Lock | Cmd::Unlock => 0,
+            Cmd::Face { .. } => 1,
+            Cmd::TpIf { from, to } | Cmd::Move { from, to } => from.byte_len() + to.byte_len(),
+            Cmd::If {
+                condition,
+                branches,
+            } => condition.byte_len() + branches.byte_len(),
+            Cmd::SetFlag { .. } | Cmd::UnsetFlag { .. } | Cmd::ReadFlag { .. } => 2,
+            Cmd::Wait { .. } | Cmd::PlaySound { .. } => 2,
+            Cmd::Give { .. } | Cmd::Take { .. } => 4,
+            Cmd::SetVar { .. } | Cmd::AddVar { .. } => 4,
+            Cmd::Choice { options, .. } => {
+                let option = |cmds: &[Cmd]| 4 + cmds.iter().map(Cmd::byte_len).sum::<usize>();
+                3 + options.iter().map(|(_, cmds)| option(cmds)).sum::<usize>()
+            }
+            Cmd::End => 0,
+        }
+    }
 }
 
 /* -------- Implementations -- */
 
 impl ToBytecode for Text {
-    fn to_bytes(&self) -> Vec<u8> {
-        self.index.to_be_bytes().to_vec()
+    fn encode(&self, order: Endianness) -> Vec<u8> {
+        order.u16_bytes(self.index).to_vec()
     }
 }
 
 impl ToBytecode for Location {
-    fn to_bytes(&self) -> Vec<u8> {
+    fn encode(&self, order: Endianness) -> Vec<u8> {
         let mut buf = Vec::new();
         match self {
             Location::Cords(x, y) => {
-                write_u16(*x, &mut buf);
-                write_u16(*y, &mut buf);
+                buf.push(0);
+                write_u16(*x, order, &mut buf);
+                write_u16(*y, order, &mut buf);
+            }
+            Location::Relative(dx, dy) => {
+                buf.push(1);
+                write_u16(*dx as u16, order, &mut buf);
+                write_u16(*dy as u16, order, &mut buf);
             }
             Location::Tag(t) => {
-                buf.push(255);
-                buf.extend_from_slice(&t.to_bytes());
+                buf.push(2);
+                buf.extend_from_slice(&t.encode(order));
             }
         }
         buf
@@ -171,16 +540,38 @@ impl ToBytecode for Location {
 }
 
 impl ToBytecode for Condition {
-    fn to_bytes(&self) -> Vec<u8> {
+    fn encode(&self, order: Endianness) -> Vec<u8> {
         let mut buf = Vec::new();
         match self {
             Condition::FlagSet(f) => {
                 buf.push(0);
-                buf.extend_from_slice(&f.to_bytes());
+                buf.extend_from_slice(&f.encode(order));
             }
             Condition::FlagClear(f) => {
                 buf.push(1);
-                buf.extend_from_slice(&f.to_bytes());
+                buf.extend_from_slice(&f.encode(order));
+            }
+            Condition::HasItem { item, count } => {
+                buf.push(4);
+                write_u16(*item, order, &mut buf);
+                write_u16(*count, order, &mut buf);
+            }
+            Condition::VarCmp { var, op, value } => {
+                buf.push(5);
+                buf.extend_from_slice(&var.encode(order));
+                buf.push(*op as u8);
+                write_u16(*value, order, &mut buf);
+            }
+            // prefix encoding: tag, then both operands in order
+            Condition::And(lhs, rhs) => {
+                buf.push(2);
+                buf.extend_from_slice(&lhs.encode(order));
+                buf.extend_from_slice(&rhs.encode(order));
+            }
+            Condition::Or(lhs, rhs) => {
+                buf.push(3);
+                buf.extend_from_slice(&lhs.encode(order));
+                buf.extend_from_slice(&rhs.encode(order));
             }
         }
         buf
@@ -188,56 +579,106 @@ impl ToBytecode for Condition {
 }
 
 impl ToBytecode for Branch {
-    fn to_bytes(&self) -> Vec<u8> {
+    fn encode(&self, order: Endianness) -> Vec<u8> {
         let mut buf = Vec::new();
         match self {
-            Branch::ThenElse(then_cmd, else_cmd) => {
+            // every block is prefixed by its u16 byte length so the VM can
+            // jump over whichever side it does not take
+            Branch::ThenElse(then_cmds, else_cmds) => {
                 buf.push(0);
-                let then = then_cmd.to_bytes();
-                buf.push(then.len() as u8);
-                buf.extend_from_slice(&then);
-                buf.extend_from_slice(&else_cmd.to_bytes());
+                write_u16(checked_block_len(then_cmds), order, &mut buf);
+                buf.extend_from_slice(&then_cmds.encode(order));
+                write_u16(checked_block_len(else_cmds), order, &mut buf);
+                buf.extend_from_slice(&else_cmds.encode(order));
             }
-            Branch::Then(cmd) => {
-                buf.push(1); // TODO: Don't think need this?
-                let branch = &cmd.to_bytes();
-                buf.push(branch.len() as u8);
-                buf.extend_from_slice(branch);
+            Branch::Then(cmds) => {
+                buf.push(1);
+                write_u16(checked_block_len(cmds), order, &mut buf);
+                buf.extend_from_slice(&cmds.encode(order));
             }
         }
         buf
     }
 }
 
+/// A branch body is just its commands back-to-back.
+impl ToBytecode for Vec<Cmd> {
+    fn encode(&self, order: Endianness) -> Vec<u8> {
+        self.iter().flat_map(|cmd| cmd.encode(order)).collect()
+    }
+}
+
 impl ToBytecode for Cmd {
-    fn to_bytes(&self) -> Vec<u8> {
+    fn encode(&self, order: Endianness) -> Vec<u8> {
+        let Some(opcode) = self.opcode() else {
+            return Vec::new();
+        };
         let mut buf = Vec::new();
-        buf.push(u8::from(self.opcode())); // 1-byte discriminant
+        buf.push(u8::from(opcode)); // 1-byte discriminant
 
         match self {
+            Cmd::Note { .. } => unreachable!("notes are not encoded"),
             Cmd::Msg { text } => {
-                buf.extend_from_slice(&text.to_bytes());
+                buf.extend_from_slice(&text.encode(order));
             }
             Cmd::TMsg { at, text } => {
-                buf.extend_from_slice(&at.to_bytes());
-                buf.extend_from_slice(&text.to_bytes());
+                buf.extend_from_slice(&at.encode(order));
+                buf.extend_from_slice(&text.encode(order));
             }
             Cmd::Tp { to } => {
-                buf.extend_from_slice(&to.to_bytes());
+                buf.extend_from_slice(&to.encode(order));
             }
-            Cmd::TpIf { from, to } => {
-                buf.extend_from_slice(&from.to_bytes());
-                buf.extend_from_slice(&to.to_bytes());
+            Cmd::TpIf { from, to } | Cmd::Move { from, to } => {
+                buf.extend_from_slice(&from.encode(order));
+                buf.extend_from_slice(&to.encode(order));
             }
             Cmd::If {
                 condition,
                 branches,
             } => {
-                buf.extend_from_slice(&condition.to_bytes());
-                buf.extend_from_slice(&branches.to_bytes());
+                buf.extend_from_slice(&condition.encode(order));
+                buf.extend_from_slice(&branches.encode(order));
             }
             Cmd::SetFlag { flag } | Cmd::UnsetFlag { flag } | Cmd::ReadFlag { flag } => {
-                buf.extend_from_slice(&flag.to_bytes());
+                buf.extend_from_slice(&flag.encode(order));
+            }
+            Cmd::Wait { frames } => write_u16(*frames, order, &mut buf),
+            Cmd::PlaySound { id } => write_u16(*id, order, &mut buf),
+            // like an if branch, length-prefixed so the VM can skip it
+            Cmd::Random { chance, then } => {
+                buf.push(*chance);
+                write_u16(
+                    checked_block_len(std::slice::from_ref(then)),
+                    order,
+                    &mut buf,
+                );
+                buf.extend_from_slice(&then.encode(order));
+            }
+            Cmd::Call { script } => buf.extend_from_slice(&script.encode(order)),
+            Cmd::Return | Cmd::Lock | Cmd::Unlock => {}
+            Cmd::Face { dir } => buf.push(*dir as u8),
+            Cmd::Warp { map, to } => {
+                write_u16(*map, order, &mut buf);
+                buf.extend_from_slice(&to.encode(order));
+            }
+            Cmd::SetVar { var, value } | Cmd::AddVar { var, value } => {
+                buf.extend_from_slice(&var.encode(order));
+                write_u16(*value, order, &mut buf);
+            }
+            Cmd::Give { item, count } | Cmd::Take { item, count } => {
+                write_u16(*item, order, &mut buf);
+                write_u16(*count, order, &mut buf);
+            }
+            // prompt, option count, then per option its text and its
+            // length-prefixed branch
+            Cmd::Choice { prompt, options } => {
+                buf.extend_from_slice(&prompt.encode(order));
+                buf.push(options.len() as u8);
+                for (option, cmds) in options {
+                    buf.extend_from_slice(&option.encode(order));
+                    write_u16(checked_block_len(cmds), order, &mut buf);
+                    buf.extend_from_slice(&cmds.encode(order));
+                }
             }
             Cmd::End => { /* nothing extra */ }
         }
@@ -245,6 +686,372 @@ impl ToBytecode for Cmd {
     }
 }
 
+/* ------------------------------------------------------------------------- */
+/*  Disassembly                                                              */
+/* ------------------------------------------------------------------------- */
+
+impl TryFrom<u8> for Opcode {
+    type Error = String;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Ok(match byte {
+            0 => Opcode::Msg,
+            1 => Opcode::TMsg,
+            2 => Opcode::Tp,
+            3 => Opcode::TpIf,
+            4 => Opcode::If,
+            5 => Opcode::SetFlag,
+            6 => Opcode::UnsetFlag,
+            7 => Opcode::ReadFlag,
+            8 => Opcode::Wait,
+            9 => Opcode::Give,
+            10 => Opcode::Take,
+            11 => Opcode::SetVar,
+            12 => Opcode::AddVar,
+            13 => Opcode::Choice,
+            14 => Opcode::PlaySound,
+            15 => Opcode::Warp,
+            16 => Opcode::Move,
+            17 => Opcode::Random,
+            18 => Opcode::Call,
+            19 => Opcode::Return,
+            20 => Opcode::Lock,
+            21 => Opcode::Unlock,
+            22 => Opcode::Face,
+            255 => Opcode::End,
+            other => return Err(format!("unknown opcode {other:#04x}")),
+        })
+    }
+}
+
+/// Cursor over an encoded byte-stream.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    order: Endianness,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Result<u8, String> {
+        let b = *self
+            .bytes
+            .get(self.pos)
+            .ok_or_else(|| format!("unexpected end of bytecode at offset {}", self.pos))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(self.order.read_u16([self.u8()?, self.u8()?]))
+    }
+
+    /// Texts only carry their index in the stream; the string stays empty.
+    fn text(&mut self) -> Result<Text, String> {
+        Ok(Text {
+            text: "".into(),
+            index: self.u16()?,
+        })
+    }
+
+    fn location(&mut self) -> Result<Location, String> {
+        Ok(match self.u8()? {
+            0 => Location::Cords(self.u16()?, self.u16()?),
+            1 => Location::Relative(self.u16()? as i16, self.u16()? as i16),
+            2 => Location::Tag(self.text()?),
+            other => return Err(format!("unknown location kind {other}")),
+        })
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        Ok(match self.u8()? {
+            0 => Condition::FlagSet(self.text()?),
+            1 => Condition::FlagClear(self.text()?),
+            2 => Condition::And(Box::new(self.condition()?), Box::new(self.condition()?)),
+            3 => Condition::Or(Box::new(self.condition()?), Box::new(self.condition()?)),
+            4 => Condition::HasItem {
+                item: self.u16()?,
+                count: self.u16()?,
+            },
+            5 => Condition::VarCmp {
+                var: self.text()?,
+                op: match self.u8()? {
+                    0 => CmpOp::Eq,
+                    1 => CmpOp::Ne,
+                    2 => CmpOp::Lt,
+                    3 => CmpOp::Gt,
+                    other => return Err(format!("unknown comparison operator {other}")),
+                },
+                value: self.u16()?,
+            },
+            other => return Err(format!("unknown condition tag {other}")),
+        })
+    }
+
+    /// A length-prefixed branch body.
+    fn block(&mut self) -> Result<Vec<Cmd>, String> {
+        let len = self.u16()? as usize;
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err(format!(
+                "branch of {len} bytes at offset {} runs past the end of the bytecode",
+                self.pos
+            ));
+        }
+        let mut cmds = Vec::new();
+        while self.pos < end {
+            cmds.push(self.cmd()?);
+        }
+        if self.pos != end {
+            return Err(format!("branch overran its length of {len} bytes"));
+        }
+        Ok(cmds)
+    }
+
+    fn branch(&mut self) -> Result<Branch, String> {
+        Ok(match self.u8()? {
+            0 => Branch::ThenElse(self.block()?, self.block()?),
+            1 => Branch::Then(self.block()?),
+            other => return Err(format!("unknown branch tag {other}")),
+        })
+    }
+
+    fn cmd(&mut self) -> Result<Cmd, String> {
+        Ok(match Opcode::try_from(self.u8()?)? {
+            Opcode::Msg => Cmd::Msg { text: self.text()? },
+            Opcode::TMsg => Cmd::TMsg {
+                at: self.location()?,
+                text: self.text()?,
+            },
+            Opcode::Tp => Cmd::Tp {
+                to: self.location()?,
+            },
+            Opcode::TpIf => Cmd::TpIf {
+                from: self.location()?,
+                to: self.location()?,
+            },
+            Opcode::If => Cmd::If {
+                condition: self.condition()?,
+                branches: self.branch()?,
+            },
+            Opcode::SetFlag => Cmd::SetFlag { flag: self.text()? },
+            Opcode::UnsetFlag => Cmd::UnsetFlag { flag: self.text()? },
+            Opcode::ReadFlag => Cmd::ReadFlag { flag: self.text()? },
+            Opcode::Wait => Cmd::Wait {
+                frames: self.u16()?,
+            },
+            Opcode::Give => Cmd::Give {
+                item: self.u16()?,
+                count: self.u16()?,
+            },
+            Opcode::Take => Cmd::Take {
+                item: self.u16()?,
+                count: self.u16()?,
+            },
+            Opcode::SetVar => Cmd::SetVar {
+                var: self.text()?,
+                value: self.u16()?,
+            },
+            Opcode::AddVar => Cmd::AddVar {
+                var: self.text()?,
+                value: self.u16()?,
+            },
+            Opcode::PlaySound => Cmd::PlaySound { id: self.u16()? },
+            Opcode::Call => Cmd::Call {
+                script: self.text()?,
+            },
+            Opcode::Return => Cmd::Return,
+            Opcode::Lock => Cmd::Lock,
+            Opcode::Unlock => Cmd::Unlock,
+            Opcode::Face => Cmd::Face {
+                dir: match self.u8()? {
+                    0 => Direction::Up,
+                    1 => Direction::Down,
+                    2 => Direction::Left,
+                    3 => Direction::Right,
+                    other => return Err(format!("unknown direction {other}")),
+                },
+            },
+            Opcode::Random => {
+                let chance = self.u8()?;
+                let mut block = self.block()?;
+                if block.len() != 1 {
+                    return Err(format!(
+                        "random branch holds {} commands instead of one",
+                        block.len()
+                    ));
+                }
+                Cmd::Random {
+                    chance,
+                    then: Box::new(block.remove(0)),
+                }
+            }
+            Opcode::Move => Cmd::Move {
+                from: self.location()?,
+                to: self.location()?,
+            },
+            Opcode::Warp => Cmd::Warp {
+                map: self.u16()?,
+                to: self.location()?,
+            },
+            Opcode::Choice => {
+                let prompt = self.text()?;
+                let count = self.u8()?;
+                let options = (0..count)
+                    .map(|_| Ok((self.text()?, self.block()?)))
+                    .collect::<Result<_, String>>()?;
+                Cmd::Choice { prompt, options }
+            }
+            Opcode::End => Cmd::End,
+        })
+    }
+}
+
+/// Decode the command at the start of `bytes`, the inverse of
+/// `Cmd::to_bytes`. Returns the command and the number of bytes consumed.
+///
+/// Texts come back with an empty `text` since only the index is encoded.
+pub fn from_bytes(bytes: &[u8]) -> Result<(Cmd, usize), String> {
+    decode(bytes, Endianness::default())
+}
+
+/// [`from_bytes`] for a stream encoded in `order`, the inverse of
+/// `Cmd::encode`.
+pub fn decode(bytes: &[u8], order: Endianness) -> Result<(Cmd, usize), String> {
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        order,
+    };
+    let cmd = reader.cmd()?;
+    Ok((cmd, reader.pos))
+}
+
+/* ------------------------------------------------------------------------- */
+/*  Tree printing                                                            */
+/* ------------------------------------------------------------------------- */
+
+/// `cmds` as an indented tree for the `ast` subcommand: one command per
+/// line, the blocks of `if`, `choice` and `random` one level deeper than
+/// the command that opens them.
+pub fn tree(cmds: &[Cmd]) -> String {
+    let mut out = String::new();
+    write_block(&mut out, cmds, 0);
+    out
+}
+
+fn write_block(out: &mut String, cmds: &[Cmd], depth: usize) {
+    for cmd in cmds {
+        write_cmd(out, cmd, depth);
+    }
+}
+
+fn write_line(out: &mut String, depth: usize, line: impl std::fmt::Display) {
+    out.push_str(&format!("{:indent$}{line}\n", "", indent = depth * 2));
+}
+
+fn write_cmd(out: &mut String, cmd: &Cmd, depth: usize) {
+    match cmd {
+        Cmd::If {
+            condition,
+            branches,
+        } => {
+            write_line(
+                out,
+                depth,
+                format_args!("If {}", condition_label(condition)),
+            );
+            let (then, otherwise) = match branches {
+                Branch::Then(then) => (then, None),
+                Branch::ThenElse(then, otherwise) => (then, Some(otherwise)),
+            };
+            write_line(out, depth + 1, "Then");
+            write_block(out, then, depth + 2);
+            if let Some(otherwise) = otherwise {
+                write_line(out, depth + 1, "Else");
+                write_block(out, otherwise, depth + 2);
+            }
+        }
+        Cmd::Choice { prompt, options } => {
+            write_line(out, depth, format_args!("Choice {:?}", &*prompt.text));
+            for (option, cmds) in options {
+                write_line(out, depth + 1, format_args!("Option {:?}", &*option.text));
+                write_block(out, cmds, depth + 2);
+            }
+        }
+        Cmd::Random { chance, then } => {
+            write_line(out, depth, format_args!("Random {chance}%"));
+            write_cmd(out, then, depth + 1);
+        }
+        leaf => write_line(out, depth, leaf_label(leaf)),
+    }
+}
+
+/// A command without blocks, its operands much as they were written.
+fn leaf_label(cmd: &Cmd) -> String {
+    match cmd {
+        Cmd::Msg { text } => format!("Msg {:?}", &*text.text),
+        Cmd::Note { text } => format!("Note {:?}", &*text.text),
+        Cmd::TMsg { at, text } => format!("TMsg {} {:?}", location_label(at), &*text.text),
+        Cmd::Tp { to } => format!("Tp {}", location_label(to)),
+        Cmd::TpIf { from, to } => {
+            format!("TpIf {} {}", location_label(from), location_label(to))
+        }
+        Cmd::Move { from, to } => {
+            format!("Move {} {}", location_label(from), location_label(to))
+        }
+        Cmd::Warp { map, to } => format!("Warp {map} {}", location_label(to)),
+        Cmd::SetFlag { flag } => format!("SetFlag {}", flag.text),
+        Cmd::UnsetFlag { flag } => format!("UnsetFlag {}", flag.text),
+        Cmd::ReadFlag { flag } => format!("ReadFlag {}", flag.text),
+        Cmd::Wait { frames } => format!("Wait {frames}"),
+        Cmd::SetVar { var, value } => format!("SetVar {} {value}", var.text),
+        Cmd::AddVar { var, value } => format!("AddVar {} {value}", var.text),
+        Cmd::PlaySound { id } => format!("PlaySound {id}"),
+        Cmd::Give { item, count } => format!("Give {item} {count}"),
+        Cmd::Take { item, count } => format!("Take {item} {count}"),
+        Cmd::Call { script } => format!("Call @{}", script.text),
+        Cmd::Return => "Return".into(),
+        Cmd::Lock => "Lock".into(),
+        Cmd::Unlock => "Unlock".into(),
+        Cmd::Face { dir } => format!("Face {dir:?}"),
+        Cmd::End => "End".into(),
+        Cmd::If { .. } | Cmd::Choice { .. } | Cmd::Random { .. } => {
+            unreachable!("{cmd:?} has blocks")
+        }
+    }
+}
+
+fn location_label(loc: &Location) -> String {
+    match loc {
+        Location::Cords(x, y) => format!("({x}, {y})"),
+        Location::Relative(dx, dy) => format!("~{dx} ~{dy}"),
+        Location::Tag(tag) => format!("@@{}", tag.text),
+    }
+}
+
+fn condition_label(condition: &Condition) -> String {
+    match condition {
+        Condition::FlagSet(flag) => flag.text.to_string(),
+        Condition::FlagClear(flag) => format!("!{}", flag.text),
+        Condition::HasItem { item, count } => format!("has_item {item} {count}"),
+        Condition::VarCmp { var, op, value } => {
+            let op = match op {
+                CmpOp::Eq => "==",
+                CmpOp::Ne => "!=",
+                CmpOp::Lt => "<",
+                CmpOp::Gt => ">",
+            };
+            format!("{} {op} {value}", var.text)
+        }
+        Condition::And(lhs, rhs) => {
+            format!("({} and {})", condition_label(lhs), condition_label(rhs))
+        }
+        Condition::Or(lhs, rhs) => {
+            format!("({} or {})", condition_label(lhs), condition_label(rhs))
+        }
+    }
+}
+
 /* ------------------------------------------------------------------------- */
 /*  Unit-tests                                                               */
 /* ------------------------------------------------------------------------- */
@@ -257,6 +1064,9 @@ mod tests {
     //  Helpers
     // ──────────────────────────────────────────────────────────────────────
 
+    /// The encoding tests spell out big-endian bytes.
+    const BE: Endianness = Endianness::Big;
+
     fn txt(idx: u16, s: &str) -> Text {
         Text {
             text: s.into(),
@@ -271,18 +1081,28 @@ mod tests {
     #[test]
     fn test_text_to_bytes() {
         let t = txt(0x1224, "dummy");
-        assert_eq!(t.to_bytes(), vec![0x12, 0x24]); // little-endian
+        assert_eq!(t.to_bytes(), vec![0x24, 0x12]); // little-endian
+        assert_eq!(t.encode(BE), vec![0x12, 0x24]);
     }
 
     #[test]
     fn test_location_to_bytes() {
         // Cords
         let loc = Location::Cords(1, 2);
-        assert_eq!(loc.to_bytes(), vec![0, 1, 0, 2]);
+        assert_eq!(loc.encode(BE), vec![0, 0, 1, 0, 2]);
+
+        // Relative
+        let loc = Location::Relative(1, -1);
+        assert_eq!(loc.encode(BE), vec![1, 0, 1, 0xff, 0xff]);
 
         // Tag
         let loc = Location::Tag(txt(7, "tag"));
-        assert_eq!(loc.to_bytes(), vec![255, 0, 7]);
+        assert_eq!(loc.encode(BE), vec![2, 0, 7]);
+
+        // in the default little-endian order x = 255 leads with 255 as
+        // well, the kind byte still tells it from a tag
+        assert_eq!(Location::Cords(255, 1).to_bytes(), vec![0, 255, 0, 1, 0]);
+        assert_eq!(Location::Tag(txt(1, "tag")).to_bytes(), vec![2, 1, 0]);
     }
 
     // ──────────────────────────────────────────────────────────────────────
@@ -292,8 +1112,8 @@ mod tests {
     #[test]
     fn test_cmd_msg() {
         let cmd = Cmd::Msg { text: txt(3, "hi") };
-        // opcode 0 (Msg) + text-index 3 (u16 LE)
-        assert_eq!(cmd.to_bytes(), vec![0, 0, 3]);
+        // opcode 0 (Msg) + text-index 3 (u16 BE)
+        assert_eq!(cmd.encode(BE), vec![0, 0, 3]);
     }
 
     #[test]
@@ -302,8 +1122,8 @@ mod tests {
             at: Location::Tag(txt(1, "place")),
             text: txt(2, "hello"),
         };
-        // opcode 1  | Location(Tag) => 1, idx 1  | text-idx 2
-        assert_eq!(cmd.to_bytes(), vec![1, 255, 0, 1, 0, 2]);
+        // opcode 1  | Location(Tag) => 2, idx 1  | text-idx 2
+        assert_eq!(cmd.encode(BE), vec![1, 2, 0, 1, 0, 2]);
     }
 
     #[test]
@@ -312,20 +1132,553 @@ mod tests {
             from: Location::Cords(1, 2),
             to: Location::Cords(3, 4),
         };
-        // opcode 2
-        //  from:  1,0, 2,0
-        //  to  :  3,0, 4,0
+        // opcode 3
+        //  from:  cords, 0,1, 0,2
+        //  to  :  cords, 0,3, 0,4
+        assert_eq!(
+            cmd.encode(BE),
+            vec![Opcode::TpIf as u8, 0, 0, 1, 0, 2, 0, 0, 3, 0, 4]
+        );
+    }
+
+    #[test]
+    fn test_relative_location_to_bytes() {
+        let cmd = Cmd::TpIf {
+            from: Location::Relative(1, -1),
+            to: Location::Cords(0, 0),
+        };
+        // relative kind and two's-complement offsets, then the plain pair
+        assert_eq!(
+            cmd.encode(BE),
+            vec![Opcode::TpIf as u8, 1, 0, 1, 0xff, 0xff, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(cmd.byte_len(), 11);
+    }
+
+    #[test]
+    fn test_cmd_move() {
+        let cmd = Cmd::Move {
+            from: Location::Cords(1, 2),
+            to: Location::Tag(txt(3, "door")),
+        };
+        assert_eq!(
+            cmd.encode(BE),
+            vec![Opcode::Move as u8, 0, 0, 1, 0, 2, 2, 0, 3]
+        );
+        assert_eq!(Opcode::Move as u8, 16);
+    }
+
+    #[test]
+    fn test_cmd_random() {
+        let cmd = Cmd::Random {
+            chance: 50,
+            then: Box::new(Cmd::Msg {
+                text: txt(2, "rare!"),
+            }),
+        };
+        // chance, then the branch's byte length and the branch
+        assert_eq!(
+            cmd.encode(BE),
+            vec![Opcode::Random as u8, 50, 0, 3, Opcode::Msg as u8, 0, 2]
+        );
+        assert_eq!(cmd.byte_len(), 7);
+    }
+
+    #[test]
+    fn test_cmd_call_return() {
+        let cmd = Cmd::Call {
+            script: txt(2, "greeting"),
+        };
+        assert_eq!(cmd.encode(BE), vec![Opcode::Call as u8, 0, 2]);
+        assert_eq!(Cmd::Return.to_bytes(), vec![Opcode::Return as u8]);
+    }
+
+    #[test]
+    fn test_face_to_bytes() {
+        for (dir, byte) in [
+            (Direction::Up, 0),
+            (Direction::Down, 1),
+            (Direction::Left, 2),
+            (Direction::Right, 3),
+        ] {
+            let cmd = Cmd::Face { dir };
+            assert_eq!(cmd.encode(BE), vec![Opcode::Face as u8, byte]);
+            assert_eq!(cmd.byte_len(), 2);
+        }
+        assert_eq!(
+            from_bytes(&[Opcode::Face as u8, 4]),
+            Err("unknown direction 4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lock_unlock_are_one_byte() {
+        // no operands, like `end`
+        for (cmd, op) in [(Cmd::Lock, Opcode::Lock), (Cmd::Unlock, Opcode::Unlock)] {
+            assert_eq!(cmd.encode(BE), vec![op as u8]);
+            assert_eq!(cmd.byte_len(), 1);
+            assert_eq!(Cmd::VARIANT_NAMES[op as usize], format!("{op:?}"));
+        }
+    }
+
+    #[test]
+    fn test_condition_to_bytes() {
+        // single flags keep their original encoding
+        assert_eq!(Condition::FlagSet(txt(4, "a")).encode(BE), vec![0, 0, 4]);
+        assert_eq!(Condition::FlagClear(txt(4, "a")).encode(BE), vec![1, 0, 4]);
+
+        let cond = Condition::Or(
+            Box::new(Condition::FlagSet(txt(1, "a"))),
+            Box::new(Condition::And(
+                Box::new(Condition::FlagSet(txt(2, "b"))),
+                Box::new(Condition::FlagClear(txt(3, "c"))),
+            )),
+        );
+        assert_eq!(cond.encode(BE), vec![3, 0, 0, 1, 2, 0, 0, 2, 1, 0, 3]);
+
+        let cond = Condition::HasItem { item: 5, count: 3 };
+        assert_eq!(cond.encode(BE), vec![4, 0, 5, 0, 3]);
+
+        for (op, byte) in [
+            (CmpOp::Eq, 0),
+            (CmpOp::Ne, 1),
+            (CmpOp::Lt, 2),
+            (CmpOp::Gt, 3),
+        ] {
+            let cond = Condition::VarCmp {
+                var: txt(2, "var_x"),
+                op,
+                value: 5,
+            };
+            assert_eq!(cond.encode(BE), vec![5, 0, 2, byte, 0, 5]);
+        }
+    }
+
+    #[test]
+    fn test_cmd_if_block_branch() {
+        let cmd = Cmd::If {
+            condition: Condition::FlagSet(txt(0, "flag_X")),
+            branches: Branch::Then(vec![
+                Cmd::SetFlag {
+                    flag: txt(1, "flag_A"),
+                },
+                Cmd::Msg { text: txt(2, "hi") },
+            ]),
+        };
+        // opcode | cond: set, flag 0 | then-only, 6 branch bytes | setflag 1 | msg 2
+        assert_eq!(
+            cmd.encode(BE),
+            vec![
+                Opcode::If as u8,
+                0,
+                0,
+                0,
+                1,
+                0,
+                6,
+                Opcode::SetFlag as u8,
+                0,
+                1,
+                Opcode::Msg as u8,
+                0,
+                2
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cmd_wait() {
+        let cmd = Cmd::Wait { frames: 0x0102 };
+        assert_eq!(cmd.encode(BE), vec![Opcode::Wait as u8, 0x01, 0x02]);
+        assert_eq!(Cmd::VARIANT_NAMES[Opcode::Wait as usize], "Wait");
+    }
+
+    #[test]
+    fn test_cmd_give_take() {
+        let cmd = Cmd::Give { item: 5, count: 1 };
+        assert_eq!(cmd.encode(BE), vec![Opcode::Give as u8, 0, 5, 0, 1]);
+
+        let cmd = Cmd::Take {
+            item: 0x0302,
+            count: 2,
+        };
+        assert_eq!(cmd.encode(BE), vec![Opcode::Take as u8, 3, 2, 0, 2]);
+    }
+
+    #[test]
+    fn test_cmd_vars() {
+        let cmd = Cmd::SetVar {
+            var: txt(1, "var_x"),
+            value: 3,
+        };
+        assert_eq!(cmd.encode(BE), vec![Opcode::SetVar as u8, 0, 1, 0, 3]);
+
+        let cmd = Cmd::AddVar {
+            var: txt(1, "var_x"),
+            value: 0x0100,
+        };
+        assert_eq!(cmd.encode(BE), vec![Opcode::AddVar as u8, 0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_choice_to_bytes() {
+        let cmd = Cmd::Choice {
+            prompt: txt(0, "Pick one"),
+            options: vec![
+                (
+                    txt(1, "Yes"),
+                    vec![Cmd::SetFlag {
+                        flag: txt(0, "flag_a"),
+                    }],
+                ),
+                (txt(2, "No"), vec![Cmd::Msg { text: txt(3, "ok") }]),
+            ],
+        };
+        // prompt, option count, then text + branch length + branch per option
+        #[rustfmt::skip]
+        let expected = vec![
+            Opcode::Choice as u8, 0, 0, 2,
+            0, 1, 0, 3, Opcode::SetFlag as u8, 0, 0,
+            0, 2, 0, 3, Opcode::Msg as u8, 0, 3,
+        ];
+        assert_eq!(cmd.encode(BE), expected);
+        assert_eq!(Opcode::Choice as u8, 13);
+    }
+
+    #[test]
+    fn test_playsound_to_bytes() {
+        let cmd = Cmd::PlaySound { id: 0x0104 };
+        assert_eq!(cmd.to_bytes(), vec![Opcode::PlaySound as u8, 0x04, 0x01]);
+        assert_eq!(Opcode::PlaySound as u8, 14);
+        assert_eq!(Cmd::VARIANT_NAMES[Opcode::PlaySound as usize], "PlaySound");
+    }
+
+    #[test]
+    fn test_warp_to_bytes() {
+        let cmd = Cmd::Warp {
+            map: 3,
+            to: Location::Tag(txt(1, "spawn")),
+        };
+        assert_eq!(cmd.encode(BE), vec![Opcode::Warp as u8, 0, 3, 2, 0, 1]);
+
+        let cmd = Cmd::Warp {
+            map: 3,
+            to: Location::Cords(10, 20),
+        };
         assert_eq!(
             cmd.to_bytes(),
-            vec![Opcode::TpIf as u8, 0, 1, 0, 2, 0, 3, 0, 4]
+            vec![Opcode::Warp as u8, 3, 0, 0, 10, 0, 20, 0]
+        );
+        assert_eq!(cmd.byte_len(), 8);
+    }
+
+    #[test]
+    fn test_cmd_if_then_else_offsets() {
+        let cmd = Cmd::If {
+            condition: Condition::FlagClear(txt(0, "flag_X")),
+            branches: Branch::ThenElse(
+                vec![Cmd::Msg { text: txt(1, "a") }],
+                vec![Cmd::Wait { frames: 10 }, Cmd::End],
+            ),
+        };
+        #[rustfmt::skip]
+        let expected = vec![
+            Opcode::If as u8,
+            1, 0, 0,                        // !flag 0
+            0,                              // then + else
+            0, 3, Opcode::Msg as u8, 0, 1,  // then: 3 bytes
+            0, 4, Opcode::Wait as u8, 0, 10, Opcode::End as u8, // else: 4 bytes
+        ];
+        assert_eq!(cmd.encode(BE), expected);
+    }
+
+    #[test]
+    fn test_cmd_nested_if_offsets() {
+        let inner = Cmd::If {
+            condition: Condition::FlagSet(txt(1, "flag_Y")),
+            branches: Branch::Then(vec![Cmd::SetFlag {
+                flag: txt(2, "flag_Z"),
+            }]),
+        };
+        let outer = Cmd::If {
+            condition: Condition::FlagSet(txt(0, "flag_X")),
+            branches: Branch::Then(vec![inner.clone()]),
+        };
+
+        // inner: opcode, 3 condition bytes, tag, u16 length, 3 setflag bytes
+        assert_eq!(inner.byte_len(), 10);
+        #[rustfmt::skip]
+        let expected = vec![
+            Opcode::If as u8, 0, 0, 0, 1, 0, 10,
+            Opcode::If as u8, 0, 0, 1, 1, 0, 3,
+            Opcode::SetFlag as u8, 0, 2,
+        ];
+        assert_eq!(outer.encode(BE), expected);
+    }
+
+    #[test]
+    fn test_byte_len_matches_encoding() {
+        let cmds = vec![
+            Cmd::Msg { text: txt(1, "a") },
+            Cmd::TMsg {
+                at: Location::Tag(txt(3, "loc")),
+                text: txt(1, "a"),
+            },
+            Cmd::Tp {
+                to: Location::Cords(1, 2),
+            },
+            Cmd::TpIf {
+                from: Location::Cords(1, 2),
+                to: Location::Tag(txt(0, "loc")),
+            },
+            Cmd::If {
+                condition: Condition::Or(
+                    Box::new(Condition::HasItem { item: 1, count: 2 }),
+                    Box::new(Condition::VarCmp {
+                        var: txt(0, "var_x"),
+                        op: CmpOp::Gt,
+                        value: 3,
+                    }),
+                ),
+                branches: Branch::ThenElse(vec![Cmd::End], vec![]),
+            },
+            Cmd::ReadFlag { flag: txt(4, "f") },
+            Cmd::Give { item: 1, count: 1 },
+            Cmd::AddVar {
+                var: txt(0, "var_x"),
+                value: 1,
+            },
+            Cmd::Wait { frames: 1 },
+            Cmd::Choice {
+                prompt: txt(0, "?"),
+                options: vec![
+                    (txt(1, "y"), vec![Cmd::Wait { frames: 1 }]),
+                    (txt(2, "n"), vec![]),
+                ],
+            },
+            Cmd::End,
+        ];
+        for cmd in cmds {
+            assert_eq!(cmd.byte_len(), cmd.to_bytes().len(), "{cmd:?}");
+        }
+    }
+
+    fn blank(idx: u16) -> Text {
+        txt(idx, "")
+    }
+
+    #[test]
+    fn test_from_bytes_round_trip() {
+        let cmds = vec![
+            Cmd::Msg { text: blank(1) },
+            Cmd::TMsg {
+                at: Location::Tag(blank(3)),
+                text: blank(1),
+            },
+            Cmd::TMsg {
+                at: Location::Cords(7, 300),
+                text: blank(0),
+            },
+            Cmd::Tp {
+                to: Location::Cords(1, 2),
+            },
+            Cmd::TpIf {
+                from: Location::Cords(1, 2),
+                to: Location::Tag(blank(0)),
+            },
+            Cmd::If {
+                condition: Condition::And(
+                    Box::new(Condition::FlagClear(blank(2))),
+                    Box::new(Condition::Or(
+                        Box::new(Condition::HasItem { item: 1, count: 2 }),
+                        Box::new(Condition::VarCmp {
+                            var: blank(0),
+                            op: CmpOp::Lt,
+                            value: 0x1234,
+                        }),
+                    )),
+                ),
+                branches: Branch::ThenElse(
+                    vec![Cmd::Msg { text: blank(4) }, Cmd::End],
+                    vec![Cmd::If {
+                        condition: Condition::FlagSet(blank(1)),
+                        branches: Branch::Then(vec![Cmd::Wait { frames: 60 }]),
+                    }],
+                ),
+            },
+            Cmd::If {
+                condition: Condition::FlagSet(blank(0)),
+                branches: Branch::Then(vec![]),
+            },
+            Cmd::SetFlag { flag: blank(9) },
+            Cmd::UnsetFlag { flag: blank(9) },
+            Cmd::ReadFlag { flag: blank(9) },
+            Cmd::Wait { frames: 65535 },
+            Cmd::PlaySound { id: 4 },
+            Cmd::Warp {
+                map: 2,
+                to: Location::Tag(blank(5)),
+            },
+            Cmd::Move {
+                from: Location::Tag(blank(1)),
+                to: Location::Cords(4, 5),
+            },
+            Cmd::Tp {
+                to: Location::Relative(-300, 2),
+            },
+            Cmd::Call { script: blank(7) },
+            Cmd::Return,
+            Cmd::Lock,
+            Cmd::Unlock,
+            Cmd::Face {
+                dir: Direction::Right,
+            },
+            Cmd::Random {
+                chance: 100,
+                then: Box::new(Cmd::If {
+                    condition: Condition::FlagSet(blank(0)),
+                    branches: Branch::Then(vec![Cmd::End]),
+                }),
+            },
+            Cmd::Give { item: 3, count: 1 },
+            Cmd::Take { item: 3, count: 2 },
+            Cmd::SetVar {
+                var: blank(1),
+                value: 5,
+            },
+            Cmd::AddVar {
+                var: blank(1),
+                value: 1,
+            },
+            Cmd::Choice {
+                prompt: blank(0),
+                options: vec![
+                    (blank(1), vec![Cmd::SetFlag { flag: blank(0) }]),
+                    (blank(2), vec![Cmd::Msg { text: blank(3) }, Cmd::End]),
+                ],
+            },
+            Cmd::End,
+        ];
+
+        for cmd in cmds {
+            let bytes = cmd.to_bytes();
+            let (decoded, used) = from_bytes(&bytes).unwrap();
+            assert_eq!(decoded, cmd);
+            assert_eq!(used, bytes.len(), "{cmd:?}");
+
+            let (decoded, _) = decode(&cmd.encode(BE), BE).unwrap();
+            assert_eq!(decoded, cmd);
+        }
+    }
+
+    #[test]
+    fn test_edge_column_cords_round_trip() {
+        // x = 254 and 255 once read as the relative and tag markers
+        for x in [254, 255] {
+            let cmd = Cmd::Tp {
+                to: Location::Cords(x, 0),
+            };
+            for order in [Endianness::Little, BE] {
+                let bytes = cmd.encode(order);
+                assert_eq!(bytes[1], 0, "cords kind byte");
+                assert_eq!(decode(&bytes, order).unwrap(), (cmd.clone(), 6));
+            }
+        }
+        assert_eq!(
+            from_bytes(&[Opcode::Tp as u8, 3, 0, 0]).unwrap_err(),
+            "unknown location kind 3"
         );
     }
 
+    #[test]
+    fn test_for_each_symbol_mut_visits_nested_branches() {
+        let mut cmd = Cmd::If {
+            condition: Condition::And(
+                Box::new(Condition::FlagSet(txt(0, "flag_a"))),
+                Box::new(Condition::VarCmp {
+                    var: txt(0, "var_n"),
+                    op: CmpOp::Eq,
+                    value: 1,
+                }),
+            ),
+            branches: Branch::ThenElse(
+                vec![Cmd::TMsg {
+                    at: Location::Tag(txt(0, "home")),
+                    text: txt(0, "hi"),
+                }],
+                vec![Cmd::SetFlag {
+                    flag: txt(1, "flag_b"),
+                }],
+            ),
+        };
+
+        let mut seen = Vec::new();
+        cmd.for_each_symbol_mut(&mut |kind, t| {
+            seen.push((kind, t.text.to_string()));
+            t.index += 10;
+        });
+        use SymbolKind::*;
+        let names = |v: &[(SymbolKind, &str)]| -> Vec<(SymbolKind, String)> {
+            v.iter().map(|&(k, n)| (k, n.to_string())).collect()
+        };
+        assert_eq!(
+            seen,
+            names(&[
+                (Flag, "flag_a"),
+                (Var, "var_n"),
+                (Tag, "home"),
+                (Text, "hi"),
+                (Flag, "flag_b")
+            ])
+        );
+        let Cmd::If {
+            branches: Branch::ThenElse(_, else_cmds),
+            ..
+        } = &cmd
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            else_cmds[0],
+            Cmd::SetFlag {
+                flag: txt(11, "flag_b")
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_drops_text_strings() {
+        let cmd = Cmd::Msg {
+            text: txt(3, "hello"),
+        };
+        let (decoded, _) = from_bytes(&cmd.to_bytes()).unwrap();
+        assert_eq!(decoded, Cmd::Msg { text: blank(3) });
+    }
+
+    #[test]
+    fn test_from_bytes_reads_one_cmd_of_a_stream() {
+        let stream = vec![Cmd::Wait { frames: 2 }, Cmd::End].to_bytes();
+        let (cmd, used) = from_bytes(&stream).unwrap();
+        assert_eq!(cmd, Cmd::Wait { frames: 2 });
+        assert_eq!(used, 3);
+        assert_eq!(from_bytes(&stream[used..]).unwrap(), (Cmd::End, 1));
+    }
+
+    #[test]
+    fn test_from_bytes_errors() {
+        assert!(from_bytes(&[]).unwrap_err().contains("unexpected end"));
+        assert!(from_bytes(&[Opcode::Msg as u8, 0]).is_err());
+        assert_eq!(from_bytes(&[200]).unwrap_err(), "unknown opcode 0xc8");
+        // then-branch claims 9 bytes but only 3 follow
+        let bad = [Opcode::If as u8, 0, 0, 0, 1, 0, 9, Opcode::Msg as u8, 0, 1];
+        assert!(from_bytes(&bad).unwrap_err().contains("runs past the end"));
+    }
+
     #[test]
     fn test_cmd_setflag() {
         let cmd = Cmd::SetFlag {
             flag: txt(5, "flag"),
         };
-        assert_eq!(cmd.to_bytes(), vec![Opcode::SetFlag as u8, 0, 5]); // opcode 4
+        assert_eq!(cmd.encode(BE), vec![Opcode::SetFlag as u8, 0, 5]); // opcode 4
     }
 }
@@ -1,65 +1,178 @@
-//! Minimal VM assembler stub.
-//! Real assembler will go through lexer/AST/linker; for now we just
-//! echo the plain text back as bytes so that the pipeline compiles.
+//! Assembles each map chunk's parsed scripts into one byte-code blob,
+//! checks it against the per-chunk byte limit and shares identical blobs
+//! between chunks.
 
-use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::fmt;
 
-use crate::model::ParsedScripts;
-use crate::processor::ast::ToBytecode; // bring the trait into scope
+use anyhow::Result;
+
+use crate::model::{Endianness, ParsedScripts, Script};
+use crate::processor::ast::{Opcode, ToBytecode}; // bring the trait into scope
 
 #[derive(Debug, PartialEq, Eq)]
 
 pub struct ScriptBlob {
     pub blob: Vec<u8>,
     pub script: String,
+    /// Start of each of the chunk's scripts, relative to the blob start.
+    /// Blobs sit at a fixed byte-limit stride in `scripts.bin`.
+    pub offsets: Vec<u16>,
+    /// `note`s of the scripts by the offset of the top-level command they
+    /// precede or sit in, for the listing.
+    pub notes: Vec<(u16, String)>,
 }
 #[derive(Debug)]
 pub struct ProcessedScripts {
-    pub blob: Vec<ScriptBlob>, // concatenated bytecode for all scripts
-    pub offsets: Vec<u16>,     // starting offset of each script
+    pub blob: Vec<ScriptBlob>, // distinct chunk blobs, in order of first use
+    pub index: Vec<usize>,     // chunk → position of its blob in `blob`
+    /// Chunk and chunk-relative start of every script `call` runs, by
+    /// call index.
+    pub calls: Vec<(usize, u16)>,
+}
+
+impl ProcessedScripts {
+    /// The blob chunk `chunk` runs.
+    pub fn chunk(&self, chunk: usize) -> &ScriptBlob {
+        &self.blob[self.index[chunk]]
+    }
+}
+
+/// A chunk whose scripts do not fit the per-chunk byte budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkOverflow {
+    pub chunk: usize,
+    pub size: usize,
+    pub limit: usize,
+    /// The chunk's scripts, in the order they were assembled.
+    pub scripts: Vec<ScriptSize>,
+}
+
+/// Bytes one script of an overflowing chunk takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptSize {
+    pub id: i32,
+    pub size: usize,
+    /// Opcode and size of the script's largest command.
+    pub largest: Option<(Opcode, usize)>,
+}
+
+impl ScriptSize {
+    fn of(script: &Script) -> Self {
+        let largest = script
+            .body
+            .iter()
+            .filter_map(|cmd| Some((cmd.opcode()?, cmd.byte_len())))
+            .max_by_key(|&(_, len)| len);
+        Self {
+            id: script.id,
+            size: script.body.iter().map(|cmd| cmd.byte_len()).sum(),
+            largest,
+        }
+    }
+}
+
+impl fmt::Display for ChunkOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunk {} too large, {} bytes instead of {}",
+            self.chunk, self.size, self.limit
+        )?;
+        for script in &self.scripts {
+            write!(f, "\n  id {}: {} bytes", script.id, script.size)?;
+            if let Some((op, len)) = script.largest {
+                write!(f, ", largest command {op:?} of {len} bytes")?;
+            }
+        }
+        Ok(())
+    }
 }
 
-/// Convert every raw script into “bytecode”.
-pub fn assemble_scripts(parsed_scripts: &ParsedScripts) -> Result<ProcessedScripts> {
+impl std::error::Error for ChunkOverflow {}
+
+/// Convert every raw script into “bytecode” in byte order `order`; no
+/// chunk may exceed `byte_limit` bytes. Chunks that assemble to the same
+/// bytes and script starts share one blob.
+pub fn assemble_scripts(
+    parsed_scripts: &ParsedScripts,
+    byte_limit: usize,
+    order: Endianness,
+) -> Result<ProcessedScripts> {
     let mut blob = Vec::<ScriptBlob>::new(); // final buffer (all chunks)
-    let mut offsets = Vec::<u16>::new(); // absolute offsets into `blob`
+    let mut index = Vec::<usize>::new();
+    let mut seen = HashMap::<(Vec<u8>, Vec<u16>), usize>::new();
+    let mut starts = HashMap::<&str, (usize, u16)>::new();
 
-    // Iterate over map-chunks (0‥2047)
+    // Iterate over map-chunks (0‥2047 with the default layout)
     for (chunk_idx, chunk) in parsed_scripts.chunks.iter().enumerate() {
         // ------- assemble this chunk into a temporary buffer -------------
         let mut tmp = Vec::<u8>::new();
-        let base_offset = blob.len() as u16; // where this chunk will start
+        let mut offsets = Vec::<u16>::new(); // chunk-relative script starts
+        let mut notes = Vec::new();
         let mut s = String::new();
         for script in chunk {
             // append the script string to s
             s += &script.script;
+            // saturates only for chunks the size check below rejects
+            let start = u16::try_from(tmp.len()).unwrap_or(u16::MAX);
+            offsets.push(start);
+            if let Some(name) = &script.name {
+                starts.insert(name, (chunk_idx, start));
+            }
             for cmd in &script.body {
-                // record absolute offset (base + current tmp len)
-                offsets.push(base_offset + tmp.len() as u16);
-
-                // encode command and append stub terminator
-                tmp.extend_from_slice(&cmd.to_bytes());
+                let at = u16::try_from(tmp.len()).unwrap_or(u16::MAX);
+                notes.extend(cmd.notes().into_iter().map(|note| (at, note.to_string())));
+                tmp.extend_from_slice(&cmd.encode(order));
             }
         }
         tmp.push(0xff);
 
         // ------- size check ----------------------------------------------
-        if tmp.len() > 128 {
-            return Err(anyhow!(
-                "chunk {} too large, {} bytes instead of 128",
-                chunk_idx,
-                tmp.len()
-            ));
+        if tmp.len() > byte_limit {
+            return Err(ChunkOverflow {
+                chunk: chunk_idx,
+                size: tmp.len(),
+                limit: byte_limit,
+                scripts: chunk.iter().map(ScriptSize::of).collect(),
+            }
+            .into());
         }
 
-        // append verified chunk to the final blob
-        blob.push(ScriptBlob {
-            blob: tmp.clone(),
-            script: s.clone(),
-        });
+        // append verified chunk to the final blob unless an earlier chunk
+        // already produced the same one
+        let next = blob.len();
+        let at = *seen.entry((tmp.clone(), offsets.clone())).or_insert(next);
+        if at == next {
+            blob.push(ScriptBlob {
+                blob: tmp,
+                script: s,
+                offsets,
+                notes,
+            });
+        }
+        index.push(at);
     }
 
-    Ok(ProcessedScripts { blob, offsets })
+    let calls = call_starts(&parsed_scripts.scripts, &starts)?;
+    Ok(ProcessedScripts { blob, index, calls })
+}
+
+/// Where each of the `called` scripts starts, in call index order.
+fn call_starts(
+    called: &HashMap<String, u16>,
+    starts: &HashMap<&str, (usize, u16)>,
+) -> Result<Vec<(usize, u16)>> {
+    let mut names: Vec<(&String, &u16)> = called.iter().collect();
+    names.sort_unstable_by_key(|&(_, i)| i);
+    names
+        .into_iter()
+        .map(|(name, _)| {
+            starts.get(name.as_str()).copied().ok_or_else(|| {
+                anyhow::anyhow!("script {name} is called but assembles to no commands")
+            })
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -67,13 +180,16 @@ mod tests {
     use std::collections::HashMap;
 
     use super::*;
-    use crate::model::{ScriptEntry, ScriptLayer};
+    use crate::model::{CHUNK_BYTE_LIMIT, ChunkLayout, ScriptEntry, ScriptLayer};
+    use crate::processor::ast::Opcode;
     use crate::processor::script_parser::parse_scripts;
 
     /// Helper: parse a layer and immediately assemble it.
     fn pipe(layer: ScriptLayer) -> (ParsedScripts, ProcessedScripts) {
-        let parsed = parse_scripts(&layer, &HashMap::new()).expect("parser ok");
-        let processed = assemble_scripts(&parsed).expect("assembler ok");
+        let parsed =
+            parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default()).expect("parser ok");
+        let processed = assemble_scripts(&parsed, CHUNK_BYTE_LIMIT, Endianness::default())
+            .expect("assembler ok");
         (parsed, processed)
     }
 
@@ -85,14 +201,16 @@ mod tests {
                 ScriptEntry {
                     id: 0,
                     script: "msg {a};".into(),
-                    x: 1.0,
-                    y: 1.0, // inside chunk 0
+                    x: 16.0,
+                    y: 16.0, // inside chunk 0
+                    ..Default::default()
                 },
                 ScriptEntry {
                     id: 0,
                     script: "msg {b};".into(),
-                    x: 2.0,
-                    y: 1.0, // same chunk 0
+                    x: 32.0,
+                    y: 16.0, // same chunk 0, next tile
+                    ..Default::default()
                 },
             ],
         };
@@ -104,53 +222,203 @@ mod tests {
 
         // ── Offsets ──────────────────────────────────────────────────
         // Each Msg serialises to 3 bytes
-        assert_eq!(processed.offsets, vec![0, 3]);
+        assert_eq!(processed.blob[0].offsets, vec![0, 3]);
+        assert!(processed.blob[1].offsets.is_empty());
 
-        // Blob must be a Vec<Vec<u8>> with one chunk containing both scripts
-        // Chunk 0: [0, 0, 0, 0, 0, 1, 0, 0]
-        //          [Msg opcode(0) + text-idx 0 + terminator, Msg opcode(0) + text-idx 1 + terminator]
+        // One blob index per chunk: chunk 0 has both scripts, every other
+        // chunk points at the one `End`-only blob they all share
         assert_eq!(
-            processed.blob.len(),
+            processed.index.len(),
             2048,
             "should have exactly 2048 chunks"
         );
+        assert_eq!(processed.blob.len(), 2);
+        assert!(processed.index[1..].iter().all(|&i| i == 1));
         assert_eq!(
             processed.blob[0],
             ScriptBlob {
                 blob: vec![
-                    0, 0, 0, 0, // first  script
-                    0, 1, 255 // second script
+                    0, 0, 0, // first  script
+                    0, 1, 0, // second script, text 1 little-endian
+                    255
                 ],
                 script: "msg {a};msg {b};".into(),
+                offsets: vec![0, 3],
+                notes: Vec::new(),
             }
         );
     }
 
+    #[test]
+    fn test_identical_chunks_share_a_blob() {
+        let sign = |x: f32| ScriptEntry {
+            script: "msg {welcome};".into(),
+            x: x * 16.0,
+            y: 0.0,
+            ..Default::default()
+        };
+        // chunks 0, 1 and 3 hold the same sign post
+        let layer = ScriptLayer {
+            objects: vec![sign(0.0), sign(8.0), sign(24.0)],
+        };
+
+        let (_, processed) = pipe(layer);
+
+        assert_eq!(processed.blob.len(), 2, "sign post and empty chunk");
+        assert_eq!(&processed.index[..4], [0, 0, 1, 0]);
+        assert_eq!(processed.chunk(3).script, "msg {welcome};");
+        assert_eq!(processed.chunk(2).blob, [0xff]);
+    }
+
+    #[test]
+    fn test_same_bytes_split_differently_are_kept_apart() {
+        let entry = |script: &str, x: f32| ScriptEntry {
+            script: script.into(),
+            x: x * 16.0,
+            y: 0.0,
+            ..Default::default()
+        };
+        // chunk 0 runs two scripts, chunk 1 one script with the same bytes
+        let layer = ScriptLayer {
+            objects: vec![
+                entry("wait 1;", 0.0),
+                entry("wait 2;", 1.0),
+                entry("wait 1; wait 2;", 8.0),
+            ],
+        };
+
+        let (_, processed) = pipe(layer);
+
+        assert_eq!(processed.chunk(0).blob, processed.chunk(1).blob);
+        assert_ne!(processed.index[0], processed.index[1]);
+    }
+
+    #[test]
+    fn test_assemble_explicit_end() {
+        let layer = ScriptLayer {
+            objects: vec![ScriptEntry {
+                id: 0,
+                script: "msg {a}; end;".into(),
+                x: 0.0,
+                y: 0.0,
+                ..Default::default()
+            }],
+        };
+
+        let (_, processed) = pipe(layer);
+
+        // `end` encodes to its opcode, the chunk terminator follows as usual
+        assert_eq!(
+            processed.blob[0].blob,
+            vec![0, 0, 0, Opcode::End as u8, 0xff]
+        );
+    }
+
     // ──────────────────────────────────────────────────────────────────
     //  Chunk-size limit
     // ──────────────────────────────────────────────────────────────────
 
     #[test]
     fn test_chunk_too_large_error() {
-        // Each Msg encodes to 4 bytes (incl. the VM terminator),
-        // so 129 messages → 516 bytes > 512.
+        // Each Msg encodes to 3 bytes, so a message on each of chunk 0's
+        // 32 tiles is 96 bytes and two are 192 > 128.
         let mut scripts = Vec::<ScriptEntry>::new();
-        for _ in 0..129 {
+        for tile in 0..32 {
             scripts.push(ScriptEntry {
-                id: 0,
-                script: "msg {x};".into(),
-                x: 0.0,
-                y: 0.0, // all go into chunk 0
+                id: tile,
+                script: "msg {x}; msg {y};".into(),
+                x: (tile % 8 * 16) as f32,
+                y: (tile / 8 * 16) as f32, // all go into chunk 0
+                ..Default::default()
             });
         }
         let layer = ScriptLayer { objects: scripts };
 
-        let parsed = parse_scripts(&layer, &HashMap::new()).expect("parse ok");
-        let err = assemble_scripts(&parsed).unwrap_err();
+        let parsed =
+            parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default()).expect("parse ok");
+        let err = assemble_scripts(&parsed, CHUNK_BYTE_LIMIT, Endianness::default()).unwrap_err();
 
         assert!(
             err.to_string().starts_with("chunk 0 too large"),
             "got error message: {err}"
         );
     }
+
+    #[test]
+    fn test_chunk_too_large_names_its_scripts() {
+        // 17 bytes in script 7 and 3 in script 9 with the terminator make
+        // 21; script 8 sits in another chunk
+        let entry = |id, script: String, x| ScriptEntry {
+            id,
+            script,
+            x,
+            ..Default::default()
+        };
+        let layer = ScriptLayer {
+            objects: vec![
+                entry(7, "msg {a}; msg {b}; tmsg 1 1 {c}; msg {d};".into(), 0.0),
+                entry(8, "msg {a};".into(), 2000.0),
+                entry(9, "msg {a};".into(), 16.0),
+            ],
+        };
+        let parsed =
+            parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default()).expect("parse ok");
+
+        let err = assemble_scripts(&parsed, 16, Endianness::default()).unwrap_err();
+        let overflow = err
+            .downcast_ref::<ChunkOverflow>()
+            .expect("a chunk overflow");
+        assert_eq!(
+            overflow.scripts,
+            [
+                ScriptSize {
+                    id: 7,
+                    size: 17,
+                    largest: Some((Opcode::TMsg, 8)),
+                },
+                ScriptSize {
+                    id: 9,
+                    size: 3,
+                    largest: Some((Opcode::Msg, 3)),
+                },
+            ]
+        );
+        assert_eq!(
+            err.to_string(),
+            "chunk 0 too large, 21 bytes instead of 16\n  \
+             id 7: 17 bytes, largest command TMsg of 8 bytes\n  \
+             id 9: 3 bytes, largest command Msg of 3 bytes"
+        );
+    }
+
+    #[test]
+    fn test_notes_emit_nothing() {
+        let entry = |script: &str| ScriptEntry {
+            script: script.into(),
+            x: 16.0,
+            y: 16.0,
+            ..Default::default()
+        };
+        let one = |script| ScriptLayer {
+            objects: vec![entry(script)],
+        };
+
+        let (_, plain) = pipe(one("msg {hi}; if flag_a then wait 1 endif; end;"));
+        let (parsed, noted) = pipe(one(
+            "note {greet}; msg {hi}; if flag_a then note {rare} wait 1 endif; end; note {done};",
+        ));
+        assert_eq!(noted.blob[0].blob, plain.blob[0].blob);
+        assert_eq!(noted.blob[0].offsets, plain.blob[0].offsets);
+        // a note is no text the VM could show
+        assert_eq!(parsed.texts.len(), 1);
+        assert_eq!(
+            noted.blob[0].notes,
+            [(0, "greet".into()), (3, "rare".into()), (14, "done".into())]
+        );
+
+        // and a script of notes alone is no script at all
+        let (parsed, _) = pipe(one("note {later};"));
+        assert!(parsed.chunks[0].is_empty());
+        assert_eq!(parsed.empty, [0]);
+    }
 }
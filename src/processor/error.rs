@@ -0,0 +1,213 @@
+//! Errors reported while turning script source into commands.
+
+use super::lexer::{LexError, Span, Token};
+use crate::model::{MAP_H, MAP_W};
+use std::fmt;
+
+/// What went wrong, independent of where.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// The lexer could not turn the input into a token.
+    Lex(String),
+    /// The input ended while `context` still expected more.
+    UnexpectedEof { context: String },
+    /// `found` appeared where `expected` was required.
+    UnexpectedToken { expected: String, found: Token },
+    /// `@name` does not match any object on the locations layer;
+    /// `suggestion` is the closest known name, if any is close.
+    UnknownLocation {
+        name: String,
+        suggestion: Option<String>,
+    },
+    /// `call @name` names no script of the layer; `suggestion` is the
+    /// closest script name, if any is close.
+    UnknownScript {
+        name: String,
+        suggestion: Option<String>,
+    },
+    /// A flag operand without the `flag_` prefix.
+    BadFlagName(String),
+    /// A condition testing a name without the `flag_` prefix.
+    BadConditionFlag(String),
+    /// A variable operand without the `var_` prefix.
+    BadVarName(String),
+    /// `if … then` followed directly by `else`/`endif`.
+    EmptyBranch,
+    /// The `if` opened at `line` ends without its `endif`.
+    UnclosedIf { line: u32 },
+    /// An `endif` with no `if` to close.
+    StrayEndif,
+    /// `()` inside a condition.
+    EmptyParens,
+    /// Parentheses or command bodies nested more than this many levels.
+    TooDeep(usize),
+    /// `random` with a chance above 100 percent.
+    BadChance(u16),
+    /// `face` towards something that is not up, down, left or right.
+    BadDirection(String),
+    /// A `choice` with more options than its count byte can hold.
+    TooManyOptions,
+    /// A branch body of `len` bytes, more than its `u16` jump offset holds.
+    BlockTooLarge(usize),
+    /// The `---` opening the front matter is never closed.
+    UnclosedFrontMatter,
+    /// A front matter line that is not `key: value`.
+    BadFrontMatter(String),
+    /// The front matter sets `key` twice.
+    DuplicateMetadata(String),
+    /// The script object sits at tile (`x`, `y`), outside the map.
+    OffMap { x: i32, y: i32 },
+    /// The script object sits on tile (`x`, `y`) like script `first` before it.
+    SameTile { x: i32, y: i32, first: i32 },
+    /// More than `limit` distinct symbols of one kind, e.g. `flags`.
+    TooManySymbols { what: &'static str, limit: usize },
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Lex(msg) => write!(f, "{msg}"),
+            ParseErrorKind::UnexpectedEof { context } => {
+                write!(f, "unexpected end of input while parsing {context}")
+            }
+            ParseErrorKind::UnexpectedToken { expected, found } => {
+                write!(f, "expected {expected}, got {found:?}")
+            }
+            ParseErrorKind::UnknownLocation { name, suggestion } => {
+                write!(f, "location {name} not found!")?;
+                if let Some(s) = suggestion {
+                    write!(f, " did you mean @{s}?")?;
+                }
+                Ok(())
+            }
+            ParseErrorKind::UnknownScript { name, suggestion } => {
+                write!(f, "script {name} not found!")?;
+                if let Some(s) = suggestion {
+                    write!(f, " did you mean @{s}?")?;
+                }
+                Ok(())
+            }
+            ParseErrorKind::BadFlagName(name) => {
+                write!(f, "expected a flag_ identifier, got '{name}'")
+            }
+            ParseErrorKind::BadConditionFlag(name) => {
+                write!(f, "condition expects a flag_ identifier, got '{name}'")
+            }
+            ParseErrorKind::BadVarName(name) => {
+                write!(f, "expected a var_ identifier, got '{name}'")
+            }
+            ParseErrorKind::EmptyBranch => write!(f, "if branch must have then branch"),
+            ParseErrorKind::UnclosedIf { line } => {
+                write!(f, "expected 'endif' to close 'if' opened at line {line}")
+            }
+            ParseErrorKind::StrayEndif => write!(f, "'endif' without an open 'if'"),
+            ParseErrorKind::EmptyParens => write!(f, "empty parentheses in condition"),
+            ParseErrorKind::TooDeep(max) => write!(f, "nested more than {max} levels deep"),
+            ParseErrorKind::BadChance(n) => {
+                write!(f, "random chance is a percentage, got {n}")
+            }
+            ParseErrorKind::BadDirection(dir) => {
+                write!(f, "expected up, down, left or right, got '{dir}'")
+            }
+            ParseErrorKind::TooManyOptions => write!(f, "choice has more than 255 options"),
+            ParseErrorKind::BlockTooLarge(len) => {
+                write!(
+                    f,
+                    "branch is {len} bytes, more than the 65535 a jump can skip"
+                )
+            }
+            ParseErrorKind::UnclosedFrontMatter => {
+                write!(f, "front matter opened with '---' is never closed")
+            }
+            ParseErrorKind::BadFrontMatter(line) => {
+                write!(f, "expected 'key: value' in front matter, got '{line}'")
+            }
+            ParseErrorKind::DuplicateMetadata(key) => {
+                write!(f, "front matter sets '{key}' more than once")
+            }
+            ParseErrorKind::TooManySymbols { what, limit } => {
+                write!(f, "too many {what}, at most {limit} are supported")
+            }
+            ParseErrorKind::OffMap { x, y } => write!(
+                f,
+                "script at tile ({x}, {y}) is outside the {MAP_W}x{MAP_H} map"
+            ),
+            ParseErrorKind::SameTile { x, y, first } => {
+                write!(f, "script at tile ({x}, {y}) shares it with id {first}")
+            }
+        }
+    }
+}
+
+/// A parse failure with its source position and, once known, the id of the
+/// Tiled object whose script failed. Problems with the object rather than
+/// its source, like its placement, have no position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub pos: Option<Span>,
+    pub script_id: Option<i32>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, pos: Span) -> Self {
+        Self {
+            kind,
+            pos: Some(pos),
+            script_id: None,
+        }
+    }
+
+    /// An error about the script object as a whole.
+    pub fn unplaced(kind: ParseErrorKind) -> Self {
+        Self {
+            kind,
+            pos: None,
+            script_id: None,
+        }
+    }
+
+    /// Attach the id of the script object the error came from.
+    pub fn in_script(mut self, id: i32) -> Self {
+        self.script_id = Some(id);
+        self
+    }
+}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> Self {
+        ParseError::new(ParseErrorKind::Lex(e.msg), e.pos)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = self.script_id {
+            write!(f, "id {id} failed: ")?;
+        }
+        if let Some(pos) = self.pos {
+            write!(f, "{pos}: ")?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Every script that failed to parse, in layer order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{e}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseErrors {}
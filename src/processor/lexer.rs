@@ -8,33 +8,80 @@
 //  Grammar excerpts (informal):
 //
 //      script  ::= stmt* EOF
-//      stmt    ::= IDENT … NEWLINE
+//      stmt    ::= IDENT … ';'?     (the final ';' may be omitted)
 //
 //  Lexical items:
 //
 //      Ident    ::= [A-Za-z_][A-Za-z0-9_]*
-//      Number   ::= [0-9]+        (fits in u16)
-//      Text     ::= '{' .*? '}'   (no nesting; '}' inside text forbidden)
-//      Symbols  ::= '@' | '!'     (single-byte tokens)
+//      Number   ::= [0-9]+ | 0[xX][0-9A-Fa-f]+   (fits in u16)
+//      Text     ::= '{' .*? '}'   (no nesting; escapes: \} \\ \n)
+//                   A line break inside the braces is kept as `\n`, even
+//                   when the source uses `\r\n`.
+//      Symbols  ::= '@' | '!' | ';' | '(' | ')' | '[' | ']' | '<' | '>'   (single-byte tokens)
+//      AtAt     ::= '@@' Ident   (symbolic location, resolved by the VM)
+//      Offset   ::= '~' '-'? Number   (relative coordinate, fits in i16)
+//      Compare  ::= '==' | '!='
+//      Arrow    ::= '->'   (choice option → branch)
 //      Whitespace and comments (# until end-of-line) are discarded.
+//      Block comments `/* … */` may span lines and do not nest.
 
+use std::fmt;
 use std::iter::Peekable;
 use std::str::Chars;
 
+/// 1-based source position of the first character of a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: u32,
+    pub col: u32,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// A character sequence that is not a valid token, with where it started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub msg: String,
+    pub pos: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.pos, self.msg)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Ident(String),
     Number(u16),
+    Offset(i16),  // '~n', '~-n'
     Text(String), // everything between { … }
     At(String),   // '@'
+    AtAt(String), // '@@'
     Bang(String), // '!'
     Semicolon,    // ';'
+    LParen,       // '('
+    RParen,       // ')'
+    LBracket,     // '['
+    RBracket,     // ']'
+    Arrow,        // '->'
+    EqEq,         // '=='
+    NotEq,        // '!='
+    Lt,           // '<'
+    Gt,           // '>'
     Eof,
 }
 #[derive(Clone)]
 pub struct Lexer<'a> {
     chars: Peekable<Chars<'a>>,
     finished: bool,
+    /// Position of the next character `next_char` will return.
+    pos: Span,
 }
 
 impl<'a> Lexer<'a> {
@@ -42,11 +89,25 @@ impl<'a> Lexer<'a> {
         Self {
             chars: src.chars().peekable(),
             finished: false,
+            pos: Span { line: 1, col: 1 },
         }
     }
 
+    /// Number lines from `line` on, for `src` cut out of a longer text.
+    pub fn starting_at_line(mut self, line: u32) -> Self {
+        self.pos.line = line;
+        self
+    }
+
     fn next_char(&mut self) -> Option<char> {
-        self.chars.next()
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.col = 1;
+        } else {
+            self.pos.col += 1;
+        }
+        Some(c)
     }
 
     fn peek_char(&mut self) -> Option<char> {
@@ -71,56 +132,143 @@ impl<'a> Lexer<'a> {
         id
     }
 
+    /// The name right after `sigil`; anything else is left for the next
+    /// token.
+    fn read_name(&mut self, sigil: &str) -> Result<String, String> {
+        match self.peek_char() {
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' => {
+                self.next_char();
+                Ok(self.read_identifier(c))
+            }
+            _ => Err(format!("expected a name after {sigil}")),
+        }
+    }
+
     fn read_number(&mut self, first: char) -> Result<u16, String> {
         let mut num = String::new();
-        num.push(first);
-        self.consume_while(|c| c.is_ascii_digit(), &mut num);
-        let value: u32 = num.parse().unwrap(); // only digits
-        if value > u16::MAX as u32 {
-            return Err(format!("vaule too large for uint16: {value}"));
+        let radix = if first == '0' && matches!(self.peek_char(), Some('x' | 'X')) {
+            self.next_char();
+            self.consume_while(|c| c.is_ascii_hexdigit(), &mut num);
+            if num.is_empty() {
+                return Err("expected hex digits after 0x".into());
+            }
+            16
+        } else {
+            num.push(first);
+            self.consume_while(|c| c.is_ascii_digit(), &mut num);
+            10
+        };
+        // only digits of `radix`, so the only possible failure is overflow
+        match u16::from_str_radix(&num, radix) {
+            Ok(value) => Ok(value),
+            Err(_) if radix == 16 => Err(format!("value too large for uint16: 0x{num}")),
+            Err(_) => Err(format!("value too large for uint16: {num}")),
         }
-        Ok(value as u16)
+    }
+
+    /// The signed number after a `~`.
+    fn read_offset(&mut self) -> Result<i16, String> {
+        let negative = self.peek_char() == Some('-');
+        if negative {
+            self.next_char();
+        }
+        let sign = if negative { "-" } else { "" };
+        let first = match self.next_char() {
+            Some(c) if c.is_ascii_digit() => c,
+            _ => return Err(format!("expected a number after ~{sign}")),
+        };
+        let magnitude = self.read_number(first)?;
+        let value = if negative {
+            -i32::from(magnitude)
+        } else {
+            i32::from(magnitude)
+        };
+        i16::try_from(value).map_err(|_| format!("offset too large for int16: ~{sign}{magnitude}"))
     }
 
     fn read_text(&mut self) -> Result<String, String> {
         let mut txt = String::new();
         while let Some(c) = self.next_char() {
-            if c == '}' {
-                return Ok(txt);
+            match c {
+                '}' => return Ok(txt),
+                '\\' => match self.next_char() {
+                    Some('}') => txt.push('}'),
+                    Some('\\') => txt.push('\\'),
+                    Some('n') => txt.push('\n'),
+                    Some(e) => return Err(format!("unknown escape sequence \\{e} in text")),
+                    None => return Err("text ends with a lone \\".into()),
+                },
+                '\r' if self.peek_char() == Some('\n') => {}
+                c => txt.push(c),
             }
-            txt.push(c);
         }
         Err("no closing } found")?
     }
+
+    /// Consume everything up to and including the closing `*/`.
+    /// The opening `/*` has already been read.
+    fn skip_block_comment(&mut self) -> Result<(), String> {
+        let mut prev = '\0';
+        while let Some(c) = self.next_char() {
+            if prev == '*' && c == '/' {
+                return Ok(());
+            }
+            prev = c;
+        }
+        Err("unterminated block comment".into())
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
-    type Item = Result<Token, String>;
+    /// Every token comes with the position it started at, and so does
+    /// every error.
+    type Item = Result<(Token, Span), LexError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.finished {
             return None;
         }
 
-        // Skip whitespace that isn't newline
-        while let Some(&c) = self.chars.peek() {
-            if c == ' ' || c == '\t' || c == '\r' {
-                self.next_char();
-            } else {
-                break;
+        let (ch, start) = loop {
+            // Skip whitespace, newlines included
+            while let Some(&c) = self.chars.peek() {
+                if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
+                    self.next_char();
+                } else {
+                    break;
+                }
             }
-        }
 
-        let ch = match self.next_char() {
-            Some(c) => c,
-            None => return Some(Err("Missing end of script ;".into())),
+            let start = self.pos;
+            let ch = match self.next_char() {
+                Some(c) => c,
+                None => {
+                    // real end of input: one `Eof`, then the iterator is done
+                    self.finished = true;
+                    return Some(Ok((Token::Eof, start)));
+                }
+            };
+
+            // `/*` opens a block comment, anything else is a real token
+            if ch == '/' && self.peek_char() == Some('*') {
+                self.next_char();
+                if let Err(msg) = self.skip_block_comment() {
+                    return Some(Err(LexError { msg, pos: start }));
+                }
+                continue;
+            }
+            break (ch, start);
         };
 
         let tok_res = match ch {
-            '@' => {
-                let next_char = self.next_char().unwrap_or('\0');
-                let text = self.read_identifier(next_char);
-                Ok(Token::At(text))
+            '@' if self.peek_char() == Some('@') => {
+                self.next_char();
+                self.read_name("@@").map(Token::AtAt)
+            }
+            '@' => self.read_name("@").map(Token::At),
+            '!' if self.peek_char() == Some('=') => {
+                self.next_char();
+                Ok(Token::NotEq)
             }
             '!' => {
                 let next_char = self.next_char().unwrap_or('\0');
@@ -129,22 +277,47 @@ impl<'a> Iterator for Lexer<'a> {
             }
             '{' => self.read_text().map(Token::Text),
 
-            ';' => {
-                self.finished = true;
-                Ok(Token::Eof)
+            ';' => Ok(Token::Semicolon),
+            '=' if self.peek_char() == Some('=') => {
+                self.next_char();
+                Ok(Token::EqEq)
             }
+            '<' => Ok(Token::Lt),
+            '>' => Ok(Token::Gt),
+            '(' => Ok(Token::LParen),
+            ')' => Ok(Token::RParen),
+            '[' => Ok(Token::LBracket),
+            ']' => Ok(Token::RBracket),
+            '-' if self.peek_char() == Some('>') => {
+                self.next_char();
+                Ok(Token::Arrow)
+            }
+            '~' => self.read_offset().map(Token::Offset),
             c if c.is_ascii_digit() => self.read_number(c).map(Token::Number),
             c if c.is_ascii_alphabetic() || c == '_' => Ok(Token::Ident(self.read_identifier(c))),
+            '/' => Err("Unexpected character /, block comments start with /*".into()),
             e => Err(format!("Unexpected character {e}")),
         };
 
-        Some(tok_res)
+        Some(
+            tok_res
+                .map(|tok| (tok, start))
+                .map_err(|msg| LexError { msg, pos: start }),
+        )
     }
 }
 
 mod tests {
     #[cfg(test)]
-    use super::{Lexer, Token};
+    use super::{Lexer, Span, Token};
+
+    /// Lex `src` and drop the spans.
+    #[cfg(test)]
+    fn lex(src: &str) -> Result<Vec<Token>, String> {
+        Lexer::new(src)
+            .map(|t| t.map(|(tok, _)| tok).map_err(|e| e.to_string()))
+            .collect()
+    }
 
     #[test]
     fn test_tokenisation() {
@@ -156,6 +329,7 @@ mod tests {
                     Token::Number(42),
                     Token::Number(40),
                     Token::Text("Hello world".into()),
+                    Token::Semicolon,
                     Token::Eof,
                 ],
             ),
@@ -165,13 +339,24 @@ mod tests {
                     Token::Ident("msg".into()),
                     Token::At("loc1".into()),
                     Token::Text("Hello world".into()),
+                    Token::Semicolon,
+                    Token::Eof,
+                ],
+            ),
+            (
+                "tp @@loc1 @loc2;",
+                vec![
+                    Token::Ident("tp".into()),
+                    Token::AtAt("loc1".into()),
+                    Token::At("loc2".into()),
+                    Token::Semicolon,
                     Token::Eof,
                 ],
             ),
         ];
 
         for (src, expected) in test_cases {
-            let tokens: Result<Vec<_>, _> = Lexer::new(src).collect();
+            let tokens = lex(src);
             let tokens = tokens.unwrap();
             assert_eq!(tokens, expected);
         }
@@ -192,14 +377,302 @@ mod tests {
                 Token::Ident("flag_Z".into()),
                 Token::Ident("endif".into()),
                 Token::Ident("endif".into()),
+                Token::Semicolon,
                 Token::Eof,
             ],
         )];
 
         for (src, expected) in test_cases {
-            let tokens: Result<Vec<_>, _> = Lexer::new(src).collect();
+            let tokens = lex(src);
             let tokens = tokens.unwrap();
             assert_eq!(tokens, expected);
         }
     }
+
+    #[test]
+    fn test_offset_tokens() {
+        assert_eq!(
+            lex("tp ~2 ~-1").unwrap(),
+            vec![
+                Token::Ident("tp".into()),
+                Token::Offset(2),
+                Token::Offset(-1),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(lex("~-32768").unwrap()[0], Token::Offset(i16::MIN));
+        assert_eq!(lex("~0x10").unwrap()[0], Token::Offset(16));
+        assert_eq!(
+            lex("~32768"),
+            Err("1:1: offset too large for int16: ~32768".to_string())
+        );
+        assert_eq!(
+            lex("~ 1"),
+            Err("1:1: expected a number after ~".to_string())
+        );
+        assert_eq!(
+            lex("~-x"),
+            Err("1:1: expected a number after ~-".to_string())
+        );
+    }
+
+    #[test]
+    fn test_choice_tokens() {
+        assert_eq!(
+            lex("choice {Pick} [ {Yes} -> end ] endchoice").unwrap(),
+            vec![
+                Token::Ident("choice".into()),
+                Token::Text("Pick".into()),
+                Token::LBracket,
+                Token::Text("Yes".into()),
+                Token::Arrow,
+                Token::Ident("end".into()),
+                Token::RBracket,
+                Token::Ident("endchoice".into()),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(lex("-1"), Err("1:1: Unexpected character -".to_string()));
+    }
+
+    #[test]
+    fn test_text_escapes() {
+        let test_cases = vec![
+            (r"msg {Press \} to continue};", "Press } to continue"),
+            (r"msg {C:\\maps};", r"C:\maps"),
+            (r"msg {one\ntwo};", "one\ntwo"),
+            ("msg {one\ntwo};", "one\ntwo"),
+            ("msg {one\r\ntwo};", "one\ntwo"),
+        ];
+
+        for (src, expected) in test_cases {
+            let tokens = lex(src);
+            let tokens = tokens.unwrap();
+            assert_eq!(tokens[1], Token::Text(expected.into()));
+        }
+    }
+
+    #[test]
+    fn test_text_escape_errors() {
+        let tokens = lex(r"msg {abc\");
+        assert_eq!(tokens, Err(r"1:5: text ends with a lone \".to_string()));
+
+        // `\}` is an escaped brace, so the text is never closed
+        let tokens = lex(r"msg {abc\}");
+        assert_eq!(tokens, Err("1:5: no closing } found".to_string()));
+
+        let tokens = lex(r"msg {a\tb};");
+        assert_eq!(
+            tokens,
+            Err(r"1:5: unknown escape sequence \t in text".to_string())
+        );
+    }
+
+    #[test]
+    fn test_location_needs_a_name() {
+        assert_eq!(
+            lex("tp @@;"),
+            Err("1:4: expected a name after @@".to_string())
+        );
+        assert_eq!(
+            lex("tp @;"),
+            Err("1:4: expected a name after @".to_string())
+        );
+        assert_eq!(lex("tp @"), Err("1:4: expected a name after @".to_string()));
+        assert_eq!(
+            lex("tp @ home;"),
+            Err("1:4: expected a name after @".to_string())
+        );
+    }
+
+    #[test]
+    fn test_hex_numbers() {
+        let test_cases = vec![
+            ("tp 0xFF 0x0;", 0xFF, 0x0),
+            ("tp 0xFFFF 0Xab;", 0xFFFF, 0xAB),
+            ("tp 0 255;", 0, 255),
+        ];
+
+        for (src, x, y) in test_cases {
+            let tokens = lex(src).unwrap();
+            assert_eq!(tokens[1..3], [Token::Number(x), Token::Number(y)]);
+        }
+    }
+
+    #[test]
+    fn test_number_errors() {
+        let tokens = lex("tp 0x10000 0;");
+        assert_eq!(
+            tokens,
+            Err("1:4: value too large for uint16: 0x10000".to_string())
+        );
+
+        let tokens = lex("tp 65536 0;");
+        assert_eq!(
+            tokens,
+            Err("1:4: value too large for uint16: 65536".to_string())
+        );
+
+        let tokens = lex("tp 0x 0;");
+        assert_eq!(tokens, Err("1:4: expected hex digits after 0x".to_string()));
+    }
+
+    #[test]
+    fn test_multiple_statements() {
+        let src = "msg {a}; setflag flag_X; tp 1 2;";
+        let tokens = lex(src);
+        assert_eq!(
+            tokens.unwrap(),
+            vec![
+                Token::Ident("msg".into()),
+                Token::Text("a".into()),
+                Token::Semicolon,
+                Token::Ident("setflag".into()),
+                Token::Ident("flag_X".into()),
+                Token::Semicolon,
+                Token::Ident("tp".into()),
+                Token::Number(1),
+                Token::Number(2),
+                Token::Semicolon,
+                Token::Eof,
+            ]
+        );
+
+        // the trailing `;` is optional
+        let tokens = lex("msg {a}; msg {b}").unwrap();
+        assert_eq!(tokens.len(), 6);
+        assert_eq!(tokens.last(), Some(&Token::Eof));
+    }
+
+    #[test]
+    fn test_empty_input() {
+        for src in ["", "   ", " \t\r\n  \n", "/* only a comment */"] {
+            assert_eq!(lex(src), Ok(vec![Token::Eof]), "input {src:?}");
+        }
+
+        // nothing after `Eof`
+        let mut lexer = Lexer::new("");
+        assert!(matches!(lexer.next(), Some(Ok((Token::Eof, _)))));
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn test_parens() {
+        let tokens = lex("if (flag_A or flag_B) and flag_C").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("if".into()),
+                Token::LParen,
+                Token::Ident("flag_A".into()),
+                Token::Ident("or".into()),
+                Token::Ident("flag_B".into()),
+                Token::RParen,
+                Token::Ident("and".into()),
+                Token::Ident("flag_C".into()),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        let tokens = lex("var_a == 1 != 2 < 3 > 4 !flag_B").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("var_a".into()),
+                Token::EqEq,
+                Token::Number(1),
+                Token::NotEq,
+                Token::Number(2),
+                Token::Lt,
+                Token::Number(3),
+                Token::Gt,
+                Token::Number(4),
+                Token::Bang("flag_B".into()),
+                Token::Eof,
+            ]
+        );
+
+        assert!(lex("var_a = 1").is_err());
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let test_cases = vec![
+            (
+                "/* disabled: msg {old} */ msg {new};",
+                vec![
+                    Token::Ident("msg".into()),
+                    Token::Text("new".into()),
+                    Token::Semicolon,
+                    Token::Eof,
+                ],
+            ),
+            (
+                // spans several lines
+                "msg /* first line\nsecond line\n*/ {hi};",
+                vec![
+                    Token::Ident("msg".into()),
+                    Token::Text("hi".into()),
+                    Token::Semicolon,
+                    Token::Eof,
+                ],
+            ),
+            (
+                // comments do not nest: the first `*/` closes the comment
+                "/* outer /* inner */ msg {x};",
+                vec![
+                    Token::Ident("msg".into()),
+                    Token::Text("x".into()),
+                    Token::Semicolon,
+                    Token::Eof,
+                ],
+            ),
+        ];
+
+        for (src, expected) in test_cases {
+            let tokens = lex(src);
+            let tokens = tokens.unwrap();
+            assert_eq!(tokens, expected);
+        }
+    }
+
+    #[test]
+    fn test_block_comment_errors() {
+        // nested-looking comment leaves a stray `*/` behind
+        let tokens = lex("/* a /* b */ c */;");
+        assert_eq!(tokens, Err("1:16: Unexpected character *".to_string()));
+
+        let tokens = lex("msg /* never closed");
+        assert_eq!(tokens, Err("1:5: unterminated block comment".to_string()));
+
+        let tokens = lex("msg / {a};");
+        assert!(
+            tokens
+                .unwrap_err()
+                .starts_with("1:5: Unexpected character /")
+        );
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let src = "msg {a};\n  tp 1\n 2;";
+        let spans: Vec<Span> = Lexer::new(src).map(|t| t.unwrap().1).collect();
+        let at = |line, col| Span { line, col };
+        assert_eq!(
+            spans,
+            vec![
+                at(1, 1), // msg
+                at(1, 5), // {a}
+                at(1, 8), // ;
+                at(2, 3), // tp
+                at(2, 6), // 1
+                at(3, 2), // 2
+                at(3, 3), // ;
+                at(3, 4), // EOF
+            ]
+        );
+    }
 }
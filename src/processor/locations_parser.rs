@@ -10,7 +10,10 @@ pub type LocationTags = HashMap<String, (u16, u16)>;
 ///
 /// Each location's world coordinates are divided by 16 to convert from
 /// pixel coordinates to tile coordinates.
-pub fn parse_locations(locations: &LocationLayer) -> LocationTags {
+///
+/// Two locations with the same name (after dropping the `@`) are an error,
+/// since scripts could not tell them apart.
+pub fn parse_locations(locations: &LocationLayer) -> Result<LocationTags, String> {
     let mut location_map = HashMap::new();
 
     for location in &locations.objects {
@@ -19,10 +22,15 @@ pub fn parse_locations(locations: &LocationLayer) -> LocationTags {
 
         // remove the leading @ symbol on the name string
         let name = location.name.strip_prefix('@').unwrap_or(&location.name);
+        if let Some(&(x, y)) = location_map.get(name) {
+            return Err(format!(
+                "duplicate location {name}: at ({x}, {y}) and at ({tile_x}, {tile_y})"
+            ));
+        }
         location_map.insert(name.to_string(), (tile_x, tile_y));
     }
 
-    location_map
+    Ok(location_map)
 }
 
 #[cfg(test)]
@@ -49,7 +57,7 @@ mod tests {
             ],
         };
 
-        let locations = parse_locations(&layer);
+        let locations = parse_locations(&layer).unwrap();
 
         assert_eq!(locations.len(), 2);
         assert_eq!(locations.get("spawn"), Some(&(2, 3)));
@@ -60,7 +68,7 @@ mod tests {
     fn test_parse_locations_empty() {
         let layer = LocationLayer { objects: vec![] };
 
-        let locations = parse_locations(&layer);
+        let locations = parse_locations(&layer).unwrap();
         assert_eq!(locations.len(), 0);
     }
 
@@ -75,7 +83,32 @@ mod tests {
             }],
         };
 
-        let locations = parse_locations(&layer);
+        let locations = parse_locations(&layer).unwrap();
         assert_eq!(locations.get("test"), Some(&(2, 2)));
     }
+
+    #[test]
+    fn test_parse_locations_duplicate_name() {
+        let layer = LocationLayer {
+            objects: vec![
+                LocationEntry {
+                    id: 1,
+                    name: "spawn".to_string(),
+                    x: 32.0,
+                    y: 48.0,
+                },
+                LocationEntry {
+                    id: 2,
+                    name: "@spawn".to_string(),
+                    x: 160.0,
+                    y: 80.0,
+                },
+            ],
+        };
+
+        assert_eq!(
+            parse_locations(&layer),
+            Err("duplicate location spawn: at (2, 3) and at (10, 5)".to_string())
+        );
+    }
 }
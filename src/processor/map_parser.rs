@@ -1,44 +1,41 @@
 //! Parser for map tile data from Tiled maps.
 //! Converts flat tile array into chunked representation matching script chunks.
 
-use crate::model::{CHUNK_COLS, CHUNK_H, CHUNK_W, MapLayer, TOTAL_CHUNKS};
+use crate::model::{ChunkLayout, MAP_W, MapLayer};
 
-pub type ParsedMap = Vec<MapLayer>;
 /// Chunked representation; `chunks[idx]` holds all tiles that belong
-/// to that chunk – vector length is always `TOTAL_CHUNKS`.
-/// Each chunk contains 32 bytes (8×4 tiles).
+/// to that chunk – vector length is the layout's chunk count.
+/// Each chunk contains 32 tiles (8×4) with the default layout.
+pub type ParsedMap = Vec<MapLayer>;
 
-/// Parse the flat 65536-byte map data into chunks matching the script chunking system.
+/// Parse the flat 65536-tile map data into chunks matching the script chunking system.
 ///
-/// The map is a 256×256 grid where each tile is 1 byte.
-/// Chunks are 8×4 tiles each, resulting in 2048 chunks of 32 bytes each.
-pub fn parse_map(map_layer: &MapLayer) -> Result<ParsedMap, String> {
+/// The map is a 256×256 grid where each tile is a `u16` gid.
+/// Chunks are `layout.width`×`layout.height` tiles each; the default 8×4
+/// layout gives 2048 chunks of 32 tiles each.
+pub fn parse_map(map_layer: &MapLayer, layout: &ChunkLayout) -> Result<ParsedMap, String> {
     // Extract the flat tile data from the map layer
     let tile_data = map_layer;
 
     if tile_data.len() != 65536 {
-        return Err(format!(
-            "Expected 65536 bytes of map data, got {}",
-            tile_data.len()
-        ));
+        return Err(format!("Expected 65536 map tiles, got {}", tile_data.len()));
     }
 
-    let mut chunks: Vec<MapLayer> = vec![Vec::new(); TOTAL_CHUNKS];
+    let mut chunks: Vec<MapLayer> = vec![Vec::new(); layout.total()];
+    let (w, h) = (layout.width, layout.height);
 
-    // Process each chunk (8×4 tiles = 32 bytes per chunk)
-    for chunk_y in 0..64 {
-        // 64 chunk rows
-        for chunk_x in 0..32 {
-            // 32 chunk columns
-            let chunk_idx = chunk_y * CHUNK_COLS + chunk_x;
-            let mut chunk_data = Vec::with_capacity(32);
+    // Process each chunk (8×4 tiles = 32 bytes per chunk by default)
+    for chunk_y in 0..layout.rows() {
+        for chunk_x in 0..layout.cols() {
+            let chunk_idx = chunk_y * layout.cols() + chunk_x;
+            let mut chunk_data = Vec::with_capacity((w * h) as usize);
 
-            // Extract 8×4 tiles for this chunk
-            for tile_y in 0..CHUNK_H {
-                for tile_x in 0..CHUNK_W {
-                    let world_x = chunk_x * CHUNK_W + tile_x;
-                    let world_y = chunk_y * CHUNK_H + tile_y;
-                    let tile_idx = (world_y * 256 + world_x) as usize;
+            // Extract w×h tiles for this chunk
+            for tile_y in 0..h {
+                for tile_x in 0..w {
+                    let world_x = chunk_x * w + tile_x;
+                    let world_y = chunk_y * h + tile_y;
+                    let tile_idx = (world_y * MAP_W + world_x) as usize;
 
                     chunk_data.push(tile_data[tile_idx]);
                 }
@@ -54,6 +51,7 @@ pub fn parse_map(map_layer: &MapLayer) -> Result<ParsedMap, String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::{CHUNK_COLS, CHUNK_H, CHUNK_W, TOTAL_CHUNKS};
 
     #[test]
     fn test_chunk_grouping() {
@@ -64,7 +62,7 @@ mod tests {
         // Chunk 0 should contain tiles (0-7, 0-3)
         test_data[0] = 100; // (0,0)
         test_data[7] = 101; // (7,0)
-        test_data[256 * 3 + 0] = 102; // (0,3)
+        test_data[256 * 3] = 102; // (0,3)
         test_data[256 * 3 + 7] = 103; // (7,3)
 
         // Chunk 1 should contain tiles (8-15, 0-3)
@@ -97,6 +95,12 @@ mod tests {
         assert_eq!(chunks[0].len(), 32, "Each chunk should contain 32 bytes");
     }
 
+    #[test]
+    fn test_rejects_wrong_tile_count() {
+        let err = parse_map(&vec![0u16; 100], &ChunkLayout::default()).unwrap_err();
+        assert_eq!(err, "Expected 65536 map tiles, got 100");
+    }
+
     #[test]
     fn test_chunk_count() {
         // Verify we have the correct number of chunks
@@ -107,4 +111,16 @@ mod tests {
         assert_eq!(CHUNK_COLS * 64, 2048, "32 columns × 64 rows = 2048 chunks");
         assert_eq!(CHUNK_W * CHUNK_H, 32, "8×4 tiles = 32 bytes per chunk");
     }
+
+    #[test]
+    fn test_custom_layout() {
+        let tiles: Vec<u16> = (0..65536).map(|i| i as u16).collect();
+        let layout = ChunkLayout::new(16, 8, 128).unwrap();
+        let chunks = parse_map(&tiles, &layout).unwrap();
+        assert_eq!(chunks.len(), 16 * 32);
+        assert_eq!(chunks[1].len(), 128);
+        // chunk 1 starts at tile (16, 0); its second row starts at (16, 1)
+        assert_eq!(chunks[1][0], 16);
+        assert_eq!(chunks[1][16], 256 + 16);
+    }
 }
@@ -3,34 +3,136 @@
 //! For the moment we only care about assembling scripts into byte-code.
 pub mod ast;
 pub mod blob;
+pub mod error;
 pub mod lexer;
 pub mod locations_parser;
 pub mod map_parser;
 pub mod script_parser;
+pub mod unused;
 
 pub use crate::model::Script;
 
-use crate::model::{ProcessedProject, RawProject};
-use anyhow::Result;
+use crate::model::{ChunkLayout, Endianness, ParsedScripts, ProcessedProject, RawProject};
+use crate::{info, warn};
+use anyhow::{Result, anyhow};
+
+/// Settings for one processing run, filled in from the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Drop scripts that fail to parse (with a warning) instead of failing.
+    pub keep_going: bool,
+    /// Chunk grid and per-chunk script budget.
+    pub layout: ChunkLayout,
+    /// Byte order of the bytecode.
+    pub endianness: Endianness,
+    /// Do not warn about flags never read and locations never referenced.
+    pub allow_unused: bool,
+    /// Longest script source accepted, in bytes; `None` for no limit.
+    pub max_script_len: Option<usize>,
+    /// Longest message accepted, in UTF-8 bytes; `None` for no limit.
+    pub max_text_bytes: Option<usize>,
+    /// Fail on scripts without a single command instead of dropping them.
+    pub reject_empty: bool,
+}
 
 /// Runs every processing pass and returns a read-only structure for writers.
-pub fn run(raw: &RawProject) -> Result<ProcessedProject> {
-    let locations = locations_parser::parse_locations(&raw.locations);
-    let parse_result = script_parser::parse_scripts(&raw.scripts, &locations);
-    let processed = match parse_result {
-        Ok(processed) => processed,
-        Err(e) => {
-            panic!("Error parsing scripts: {}", e);
+pub fn run(raw: &RawProject, opts: &Options) -> Result<ProcessedProject> {
+    if let Some(max) = opts.max_script_len {
+        check_script_lengths(raw, max)?;
+    }
+    let locations = locations_parser::parse_locations(&raw.locations).map_err(|e| anyhow!(e))?;
+    let processed = if opts.keep_going {
+        let (processed, errors) =
+            script_parser::parse_scripts_keep_going(&raw.scripts, &locations, &opts.layout);
+        for e in &errors {
+            warn!("skipping script, {}", e);
         }
+        processed
+    } else {
+        script_parser::parse_scripts(&raw.scripts, &locations, &opts.layout)
+            .map_err(|e| anyhow::Error::new(e).context("Error parsing scripts"))?
     };
-    let vm_scripts = blob::assemble_scripts(&processed)?;
-    let map = map_parser::parse_map(&raw.map).unwrap();
+    if let Some(max) = opts.max_text_bytes {
+        check_text_lengths(&processed, max)?;
+    }
+    if !processed.empty.is_empty() {
+        let ids: Vec<String> = processed.empty.iter().map(i32::to_string).collect();
+        let (scripts, have, id) = match ids.len() {
+            1 => ("script", "has", "id"),
+            _ => ("scripts", "have", "ids"),
+        };
+        if opts.reject_empty {
+            return Err(anyhow!(
+                "{} {scripts} {have} no commands: {id} {}",
+                ids.len(),
+                ids.join(", ")
+            ));
+        }
+        info!(
+            "dropping {} {scripts} without commands: {id} {}",
+            ids.len(),
+            ids.join(", ")
+        );
+    }
+    if !opts.allow_unused {
+        for unused in unused::unused_symbols(&processed, &locations) {
+            warn!("{}", unused);
+        }
+    }
+    let metadata = processed
+        .chunks
+        .iter()
+        .flatten()
+        .filter(|script| !script.meta.is_empty())
+        .map(|script| (script.id, script.meta.clone()))
+        .collect();
+    let vm_scripts = blob::assemble_scripts(&processed, opts.layout.byte_limit, opts.endianness)?;
+    let map = map_parser::parse_map(&raw.map, &opts.layout).map_err(|e| anyhow!(e))?;
 
     Ok(ProcessedProject {
         blob: vm_scripts,
         flags: processed.flags,
         locations: processed.tags,
+        location_tiles: locations,
         texts: processed.texts,
-        map: map,
+        scripts: processed.scripts,
+        metadata,
+        map,
+        layout: opts.layout,
+        endianness: opts.endianness,
     })
 }
+
+/// Refuse scripts longer than `max` before they turn into a confusing
+/// chunk overflow further down.
+fn check_script_lengths(raw: &RawProject, max: usize) -> Result<()> {
+    for entry in &raw.scripts.objects {
+        if entry.script.len() > max {
+            return Err(anyhow!(
+                "script object {} at ({}, {}) is {} bytes long, over the limit of {}",
+                entry.id,
+                entry.x,
+                entry.y,
+                entry.script.len(),
+                max
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Refuse messages that would overflow the VM's dialogue buffer of `max`
+/// bytes at runtime; the first one in text table order is named.
+fn check_text_lengths(parsed: &ParsedScripts, max: usize) -> Result<()> {
+    let mut texts: Vec<(&String, &u16)> = parsed.texts.iter().collect();
+    texts.sort_unstable_by_key(|&(_, i)| i);
+    match texts.into_iter().find(|(text, _)| text.len() > max) {
+        Some((text, _)) => Err(anyhow!(
+            "message {:?} is {} bytes long, over the limit of {}",
+            text,
+            text.len(),
+            max
+        )),
+        None => Ok(()),
+    }
+}
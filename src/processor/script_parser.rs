@@ -1,41 +1,115 @@
 //! Parser that consumes the lexer and builds a `Script` AST.
 
 use crate::model::{
-    CHUNK_COLS, CHUNK_H, CHUNK_W, ParsedScripts, Script, ScriptLayer, TOTAL_CHUNKS,
+    ChunkLayout, MAP_H, MAP_W, Metadata, ParsedScripts, Script, ScriptEntry, ScriptLayer,
 };
 
+use crate::info;
+
 use super::ast::*;
-use super::lexer::{Lexer, Token};
+use super::error::{ParseError, ParseErrorKind, ParseErrors};
+use super::lexer::{LexError, Lexer, Span, Token};
 use super::locations_parser::LocationTags;
-use std::collections::HashMap;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
+/// Parse every script of the layer; fails with all parse errors at once.
 pub fn parse_scripts(
     scripts: &ScriptLayer,
     loc_tags: &LocationTags,
-) -> Result<ParsedScripts, String> {
-    let mut chunks: Vec<Vec<Script>> = vec![Vec::new(); TOTAL_CHUNKS];
+    layout: &ChunkLayout,
+) -> Result<ParsedScripts, ParseErrors> {
+    let (parsed, errors) = parse_scripts_keep_going(scripts, loc_tags, layout);
+    if errors.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(ParseErrors(errors))
+    }
+}
+
+/// Like `parse_scripts`, but scripts that fail are left out of the result,
+/// symbols included, and their errors returned next to it.
+///
+/// Scripts are parsed in parallel, each against its own symbol tables;
+/// the tables are then merged in map order, row by row and left to right,
+/// so every symbol gets the index of its first use in that order and
+/// moving objects around in Tiled's object list changes no index. Errors
+/// are still reported in object order.
+pub fn parse_scripts_keep_going(
+    scripts: &ScriptLayer,
+    loc_tags: &LocationTags,
+    layout: &ChunkLayout,
+) -> (ParsedScripts, Vec<ParseError>) {
+    let mut chunks: Vec<Vec<Script>> = vec![Vec::new(); layout.total()];
+    let mut errors = Vec::new();
+
+    let subscripts = subscript_names(scripts);
+
+    // 1. parse every script on its own
+    let locals: Vec<_> = scripts
+        .objects
+        .par_iter()
+        .map(|script| parse_local(script, loc_tags, &subscripts))
+        .collect();
 
+    // 2. merge the local tables and renumber the scripts, in map order;
+    //    errors keep the position of their object to be sorted back
+    let mut in_map_order: Vec<_> = scripts.objects.iter().zip(locals).enumerate().collect();
+    in_map_order.sort_by_key(|(_, (script, _))| map_order(script));
     let mut controller = Controller::new();
+    let mut tiles = HashMap::new();
+    let mut empty = Vec::new();
 
-    for script in &scripts.objects {
-        let mut p = Parser::new(&script.script, controller, loc_tags.clone());
-        let parse_res = p.parse();
-        let cmds = match parse_res {
+    for (pos, (script, local)) in in_map_order {
+        let (x_i, y_i, local, meta, parse_res) = match local {
+            Ok(local) => local,
+            Err(e) => {
+                errors.push((pos, e));
+                continue;
+            }
+        };
+        if let Err(kind) = claim_tile(&mut tiles, script.id, x_i, y_i) {
+            errors.push((pos, ParseError::unplaced(kind).in_script(script.id)));
+            continue;
+        }
+        // the symbols of a failing script are dropped with it
+        let mut cmds = match parse_res {
             Ok(cmds) => cmds,
-            Err(e) => return Err(format!("id {} failed: {}", script.id, e)),
+            Err(e) => {
+                errors.push((pos, e.in_script(script.id)));
+                continue;
+            }
         };
-        let x_i = script.x as i32 / 16;
-        let y_i = script.y as i32 / 16;
+        let remap = match controller.merge(local) {
+            Ok(remap) => remap,
+            Err(e) => {
+                errors.push((pos, e.in_script(script.id)));
+                continue;
+            }
+        };
+        for cmd in &mut cmds {
+            cmd.for_each_symbol_mut(&mut |kind, t| {
+                t.index = remap.of(kind)[t.index as usize];
+                if kind == SymbolKind::Text {
+                    t.text = controller.share(&t.text);
+                }
+            });
+        }
         let s = Script {
+            id: script.id,
+            name: script.name.clone(),
             script: script.script.clone(),
+            meta,
             body: cmds,
             x: x_i,
             y: y_i,
         };
 
-        let idx = chunk_index(x_i, y_i);
-        if !s.body.is_empty() {
-            println!(
+        let idx = layout.index(x_i, y_i);
+        // notes alone run nothing either
+        if s.body.iter().any(|cmd| cmd.opcode().is_some()) {
+            info!(
                 "id {} has {} commands for index {} at {},{}",
                 script.id,
                 s.body.len(),
@@ -43,34 +117,200 @@ pub fn parse_scripts(
                 x_i,
                 y_i
             );
-            chunks[idx].push(s.clone());
+            chunks[idx].push(s);
+        } else {
+            empty.push(script.id);
         }
-
-        controller = p.controller;
     }
 
-    Ok(ParsedScripts {
+    errors.sort_by_key(|&(pos, _)| pos);
+    let errors = errors.into_iter().map(|(_, e)| e).collect();
+
+    let parsed = ParsedScripts {
         chunks,
         tags: controller.tags,
         flags: controller.flags,
         texts: controller.text,
-    })
+        vars: controller.vars,
+        scripts: controller.scripts,
+        empty,
+    };
+    (parsed, errors)
+}
+
+/// Record that script `id` sits on tile (`x`, `y`); the VM finds scripts by
+/// tile, so a second one there could never run.
+fn claim_tile(
+    tiles: &mut HashMap<(i32, i32), i32>,
+    id: i32,
+    x: i32,
+    y: i32,
+) -> Result<(), ParseErrorKind> {
+    match tiles.insert((x, y), id) {
+        Some(first) => {
+            tiles.insert((x, y), first);
+            Err(ParseErrorKind::SameTile { x, y, first })
+        }
+        None => Ok(()),
+    }
+}
+
+/// Tile of `script`, which may be off the map.
+fn tile_of(script: &ScriptEntry) -> (i32, i32) {
+    let x_i = (script.x / 16.0).floor() as i32;
+    let y_i = (script.y / 16.0).floor() as i32;
+    (x_i, y_i)
+}
+
+/// Sort key putting scripts in map order: by row, then by column.
+fn map_order(script: &ScriptEntry) -> (i32, i32) {
+    let (x_i, y_i) = tile_of(script);
+    (y_i, x_i)
+}
+
+/// A script parsed against fresh tables: its tile, the tables, its front
+/// matter and the commands, whose indices point into those tables.
+type LocalParse = (i32, i32, Controller, Metadata, Result<Vec<Cmd>, ParseError>);
+
+fn parse_local(
+    script: &ScriptEntry,
+    loc_tags: &LocationTags,
+    subscripts: &HashSet<String>,
+) -> Result<LocalParse, ParseError> {
+    let (x_i, y_i) = tile_of(script);
+    if !(0..MAP_W).contains(&x_i) || !(0..MAP_H).contains(&y_i) {
+        let kind = ParseErrorKind::OffMap { x: x_i, y: y_i };
+        return Err(ParseError::unplaced(kind).in_script(script.id));
+    }
+
+    let mut controller = Controller::new();
+    let mut parser =
+        Parser::new(&script.script, &mut controller, loc_tags).with_subscripts(subscripts);
+    let parse_res = parser.parse();
+    let meta = std::mem::take(&mut parser.meta);
+    Ok((x_i, y_i, controller, meta, parse_res))
+}
+
+/// Names of the layer's named scripts, the ones `call` can run.
+fn subscript_names(scripts: &ScriptLayer) -> HashSet<String> {
+    scripts
+        .objects
+        .iter()
+        .filter_map(|script| script.name.clone())
+        .collect()
+}
+
+/// Symbol tables collected while parsing, name → index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    pub tags: HashMap<String, u16>,
+    pub flags: HashMap<String, u16>,
+    pub texts: HashMap<String, u16>,
+    pub vars: HashMap<String, u16>,
+    pub scripts: HashMap<String, u16>,
+}
+
+/// Parse one script on its own, with fresh symbol tables and no known
+/// locations or named scripts, so only numeric coordinates resolve and
+/// nothing can be called.
+///
+/// ```
+/// use pokervm_rust::processor::ast::{Cmd, Text};
+/// use pokervm_rust::processor::script_parser::parse_script;
+///
+/// let (cmds, symbols) = parse_script("msg {hi};").unwrap();
+/// let hi = Text { text: "hi".into(), index: 0 };
+/// assert_eq!(cmds, vec![Cmd::Msg { text: hi }]);
+/// assert_eq!(symbols.texts["hi"], 0);
+/// ```
+pub fn parse_script(src: &str) -> Result<(Vec<Cmd>, Symbols), ParseError> {
+    let mut controller = Controller::new();
+    let cmds = Parser::new(src, &mut controller, &LocationTags::new()).parse()?;
+    let symbols = Symbols {
+        tags: controller.tags,
+        flags: controller.flags,
+        texts: controller.text,
+        vars: controller.vars,
+        scripts: controller.scripts,
+    };
+    Ok((cmds, symbols))
+}
+
+/// Edit distance between `a` and `b` (insertions, deletions, substitutions).
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let next = (diag + usize::from(ca != cb))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            diag = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate closest to `name`, if it is near enough to be a typo,
+/// i.e. within `max(2, len / 3)` edits.
+/// Ties go to the alphabetically first candidate.
+fn closest_name<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> Option<String> {
+    let limit = (name.chars().count() / 3).max(2);
+    candidates
+        .map(|c| (levenshtein(name, c), c))
+        .filter(|&(d, _)| d <= limit)
+        .min()
+        .map(|(_, c)| c.clone())
+}
+
+/// Local → merged index, per symbol table.
+struct Remap {
+    tags: Vec<u16>,
+    flags: Vec<u16>,
+    text: Vec<u16>,
+    vars: Vec<u16>,
+    scripts: Vec<u16>,
 }
 
-/// Convert map-coordinates into the linear chunk index (0‥2047).
-#[inline]
-fn chunk_index(x: i32, y: i32) -> usize {
-    let cx = x / CHUNK_W; // 0‥31 (which 8-wide column)
-    let cy = y / CHUNK_H; // 0‥63 (which 4-tall row)
-    (cy * CHUNK_COLS + cx) as usize // row-major (0‥2047)
+impl Remap {
+    fn of(&self, kind: SymbolKind) -> &[u16] {
+        match kind {
+            SymbolKind::Tag => &self.tags,
+            SymbolKind::Flag => &self.flags,
+            SymbolKind::Text => &self.text,
+            SymbolKind::Var => &self.vars,
+            SymbolKind::Script => &self.scripts,
+        }
+    }
 }
+
+/// Number of distinct symbols of one kind a project may use; each gets a
+/// `u16` index.
+const SYMBOL_LIMIT: usize = u16::MAX as usize + 1;
+
+/// Deepest nesting of parentheses and `if`/`random`/`choice` bodies a
+/// script may use; the parser recurses once per level.
+const MAX_NESTING: usize = 64;
+
 struct Controller {
     tags: HashMap<String, u16>,
     flags: HashMap<String, u16>,
     text: HashMap<String, u16>,
-    tag_count: u16,
-    flag_count: u16,
-    text_count: u16,
+    vars: HashMap<String, u16>,
+    /// Named scripts, in order of first `call`.
+    scripts: HashMap<String, u16>,
+    /// One copy of every message, for the `Text`s that say it.
+    shared: HashSet<Arc<str>>,
+    /// Where each symbol was first used, by table name and index, so an
+    /// overflow found while merging can still point at it.
+    first_use: HashMap<(&'static str, u16), Span>,
+    /// Position of the token being parsed, kept up to date by the parser.
+    at: Span,
+    /// `SYMBOL_LIMIT` outside of tests, which lower it to stay small.
+    limit: usize,
 }
 impl Controller {
     fn new() -> Self {
@@ -78,288 +318,841 @@ impl Controller {
             tags: HashMap::new(),
             flags: HashMap::new(),
             text: HashMap::new(),
-            tag_count: 0,
-            flag_count: 0,
-            text_count: 0,
+            vars: HashMap::new(),
+            scripts: HashMap::new(),
+            shared: HashSet::new(),
+            first_use: HashMap::new(),
+            at: Span { line: 1, col: 1 },
+            limit: SYMBOL_LIMIT,
         }
     }
 
-    fn insert_tag(&mut self, tag: &String) -> u16 {
-        if !self.tags.contains_key(tag) {
-            self.tags.insert(tag.clone(), self.tag_count);
-            self.tag_count += 1;
+    /// Index of `name` in `table`, assigning the next free one on first use.
+    fn intern(
+        table: &mut HashMap<String, u16>,
+        name: &str,
+        limit: usize,
+        what: &'static str,
+    ) -> Result<u16, ParseErrorKind> {
+        if let Some(&i) = table.get(name) {
+            return Ok(i);
         }
-        let v = self.tags.get(tag).unwrap();
-        *v
-    }
-    fn insert_flag(&mut self, flag: &String) -> u16 {
-        if !self.flags.contains_key(flag) {
-            self.flags.insert(flag.clone(), self.flag_count);
-            self.flag_count += 1;
+        if table.len() >= limit {
+            return Err(ParseErrorKind::TooManySymbols { what, limit });
         }
+        let i = table.len() as u16;
+        table.insert(name.to_string(), i);
+        Ok(i)
+    }
+
+    /// Intern every symbol of `local`, a script's own tables, and return
+    /// where each of its indices ended up. A symbol that does not fit is
+    /// reported where `local` first used it.
+    fn merge(&mut self, local: Controller) -> Result<Remap, ParseError> {
+        let limit = self.limit;
+        let at = &local.first_use;
+        Ok(Remap {
+            tags: Self::merge_table(&mut self.tags, local.tags, at, limit, "locations")?,
+            flags: Self::merge_table(&mut self.flags, local.flags, at, limit, "flags")?,
+            text: Self::merge_table(&mut self.text, local.text, at, limit, "texts")?,
+            vars: Self::merge_table(&mut self.vars, local.vars, at, limit, "vars")?,
+            scripts: Self::merge_table(&mut self.scripts, local.scripts, at, limit, "scripts")?,
+        })
+    }
+
+    /// Intern the names of `local` in the order they were first used there.
+    fn merge_table(
+        table: &mut HashMap<String, u16>,
+        local: HashMap<String, u16>,
+        first_use: &HashMap<(&'static str, u16), Span>,
+        limit: usize,
+        what: &'static str,
+    ) -> Result<Vec<u16>, ParseError> {
+        let mut names: Vec<_> = local.into_iter().collect();
+        names.sort_unstable_by_key(|&(_, i)| i);
+        names
+            .iter()
+            .map(|(name, i)| {
+                Self::intern(table, name, limit, what).map_err(|kind| {
+                    match first_use.get(&(what, *i)) {
+                        Some(&pos) => ParseError::new(kind, pos),
+                        None => ParseError::unplaced(kind),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Record that the symbol at `index` of the table `what` is used at the
+    /// current token, unless it was used before.
+    fn used(&mut self, what: &'static str, index: u16) -> u16 {
+        self.first_use.entry((what, index)).or_insert(self.at);
+        index
+    }
 
-        let v = self.flags.get(flag).unwrap();
-        *v
+    fn insert_tag(&mut self, tag: &str) -> Result<u16, ParseErrorKind> {
+        let index = Self::intern(&mut self.tags, tag, self.limit, "locations")?;
+        Ok(self.used("locations", index))
+    }
+    fn insert_flag(&mut self, flag: &str) -> Result<u16, ParseErrorKind> {
+        let index = Self::intern(&mut self.flags, flag, self.limit, "flags")?;
+        Ok(self.used("flags", index))
+    }
+    fn insert_var(&mut self, var: &str) -> Result<u16, ParseErrorKind> {
+        let index = Self::intern(&mut self.vars, var, self.limit, "vars")?;
+        Ok(self.used("vars", index))
+    }
+    fn insert_text(&mut self, text: &str) -> Result<Text, ParseErrorKind> {
+        let index = Self::intern(&mut self.text, text, self.limit, "texts")?;
+        let index = self.used("texts", index);
+        Ok(Text {
+            text: self.share(text),
+            index,
+        })
     }
-    fn insert_text(&mut self, text: &String) -> u16 {
-        if !self.text.contains_key(text) {
-            self.text.insert(text.clone(), self.text_count);
-            self.text_count += 1;
+    /// The shared copy of `text`, made on first use.
+    fn share(&mut self, text: &str) -> Arc<str> {
+        if let Some(shared) = self.shared.get(text) {
+            return shared.clone();
         }
+        let shared: Arc<str> = text.into();
+        self.shared.insert(shared.clone());
+        shared
+    }
+    fn insert_script(&mut self, script: &str) -> Result<u16, ParseErrorKind> {
+        let index = Self::intern(&mut self.scripts, script, self.limit, "scripts")?;
+        Ok(self.used("scripts", index))
+    }
+}
 
-        let v = self.text.get(text).unwrap();
-        *v
+/// Marks the first and last line of a script's front matter.
+const FRONT_MATTER_FENCE: &str = "---";
+
+/// Lines between a script's opening `---` and the closing one.
+struct FrontMatter<'a> {
+    lines: Vec<&'a str>,
+    closed: bool,
+}
+
+/// Split `src` into its front matter, if it starts with a `---` line, and
+/// the body after it, returning the line the body starts on as well.
+fn split_front_matter(src: &str) -> (Option<FrontMatter<'_>>, &str, u32) {
+    let mut lines = src.split_inclusive('\n');
+    if lines.next().map(str::trim_end) != Some(FRONT_MATTER_FENCE) {
+        return (None, src, 1);
+    }
+    let mut front = FrontMatter {
+        lines: Vec::new(),
+        closed: false,
+    };
+    let mut offset = src.len() - lines.clone().map(str::len).sum::<usize>();
+    for line in lines {
+        offset += line.len();
+        if line.trim_end() == FRONT_MATTER_FENCE {
+            front.closed = true;
+            break;
+        }
+        front.lines.push(line.trim_end());
     }
+    let body_line = front.lines.len() as u32 + 3;
+    (Some(front), &src[offset..], body_line)
 }
 
 struct Parser<'a> {
     lex: std::iter::Peekable<Lexer<'a>>,
-    controller: Controller,
-    locations: LocationTags,
+    /// Front matter still to read into `meta`.
+    front_matter: Option<FrontMatter<'a>>,
+    /// `key: value` pairs of the front matter, once `parse` has read it.
+    meta: Metadata,
+    controller: &'a mut Controller,
+    locations: &'a LocationTags,
+    /// Named scripts `call` may refer to; none unless `with_subscripts`.
+    subscripts: Option<&'a HashSet<String>>,
+    /// Position of the most recently consumed token.
+    pos: Span,
+    /// Levels of parentheses and command bodies currently open.
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
-    fn new(src: &'a str, controller: Controller, locations: LocationTags) -> Self {
-        let lex = Lexer::new(src).peekable();
+    fn new(src: &'a str, controller: &'a mut Controller, locations: &'a LocationTags) -> Self {
+        let (front_matter, body, line) = split_front_matter(src);
+        let lex = Lexer::new(body).starting_at_line(line).peekable();
         Self {
             lex,
+            front_matter,
+            meta: Metadata::new(),
             controller,
             locations,
+            subscripts: None,
+            pos: Span { line: 1, col: 1 },
+            depth: 0,
+        }
+    }
+
+    fn with_subscripts(mut self, names: &'a HashSet<String>) -> Self {
+        self.subscripts = Some(names);
+        self
+    }
+
+    /// Pull the next token off the lexer, remembering where it started.
+    fn next_token(&mut self) -> Option<Result<Token, ParseError>> {
+        let next = self.lex.next()?;
+        Some(match next {
+            Ok((tok, span)) => {
+                self.pos = span;
+                self.controller.at = span;
+                Ok(tok)
+            }
+            Err(e) => {
+                self.pos = e.pos;
+                Err(e.into())
+            }
+        })
+    }
+
+    fn peek_token(&mut self) -> Option<Result<&Token, &LexError>> {
+        self.lex.peek().map(|t| t.as_ref().map(|(tok, _)| tok))
+    }
+
+    fn peek_ident(&mut self, ident: &str) -> bool {
+        matches!(self.peek_token(), Some(Ok(Token::Ident(t))) if t == ident)
+    }
+
+    /// Like `next_token`, but running out of input while `context` still
+    /// expects something is an error rather than a panic.
+    fn expect_token(&mut self, context: &str) -> Result<Token, ParseError> {
+        match self.next_token() {
+            None | Some(Ok(Token::Eof)) => Err(self.err(ParseErrorKind::UnexpectedEof {
+                context: context.into(),
+            })),
+            Some(tok) => tok,
+        }
+    }
+
+    /// Run `parse` one nesting level deeper, failing past `MAX_NESTING`
+    /// levels rather than overflowing the stack.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth == MAX_NESTING {
+            return Err(self.err(ParseErrorKind::TooDeep(MAX_NESTING)));
         }
+        self.depth += 1;
+        let res = parse(self);
+        self.depth -= 1;
+        res
+    }
+
+    /// An error of `kind` at the position of the current token.
+    fn err(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(kind, self.pos)
     }
-    fn parse(&mut self) -> Result<Vec<Cmd>, String> {
+
+    /// `found` was read where `expected` should have been.
+    fn unexpected(&self, expected: impl Into<String>, found: Token) -> ParseError {
+        self.err(ParseErrorKind::UnexpectedToken {
+            expected: expected.into(),
+            found,
+        })
+    }
+
+    fn parse(&mut self) -> Result<Vec<Cmd>, ParseError> {
+        if let Some(front) = self.front_matter.take() {
+            self.meta = Self::parse_front_matter(front)?;
+        }
         let mut res = Vec::<Cmd>::new();
-        while self.lex.peek().unwrap().clone() != Ok(Token::Eof) {
-            let cmd = self.parse_cmd()?;
-            res.push(cmd);
+        loop {
+            match self.peek_token() {
+                None | Some(Ok(Token::Eof)) => break,
+                // `;` terminates a statement, there may be several per script
+                Some(Ok(Token::Semicolon)) => {
+                    self.next_token();
+                }
+                _ => res.push(self.parse_cmd()?),
+            }
         }
         Ok(res)
     }
-    fn parse_cmd(&mut self) -> Result<Cmd, String> {
+
+    /// `key: value` lines; blank lines and `#` comments are skipped.
+    fn parse_front_matter(front: FrontMatter) -> Result<Metadata, ParseError> {
+        let at = |line| Span { line, col: 1 };
+        if !front.closed {
+            return Err(ParseError::new(ParseErrorKind::UnclosedFrontMatter, at(1)));
+        }
+        let mut meta = Metadata::new();
+        for (line, text) in (2..).zip(front.lines) {
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match text.split_once(':') {
+                Some((key, value)) if !key.trim().is_empty() => (key.trim(), value.trim()),
+                _ => {
+                    let kind = ParseErrorKind::BadFrontMatter(text.into());
+                    return Err(ParseError::new(kind, at(line)));
+                }
+            };
+            if meta.insert(key.into(), value.into()).is_some() {
+                let kind = ParseErrorKind::DuplicateMetadata(key.into());
+                return Err(ParseError::new(kind, at(line)));
+            }
+        }
+        Ok(meta)
+    }
+
+    fn parse_cmd(&mut self) -> Result<Cmd, ParseError> {
         // while cmd is not end, iterate thought all tokens
 
-        let token_res = match self.lex.next() {
-            Some(token) => token,
-            None => return Err(format!("unexpected end of script")),
-        };
-        let token = match token_res {
-            Ok(t) => t,
-            Err(e) => return Err(format!("parse: {e}")),
-        };
+        let token = self.expect_token("command")?;
         let cmd = match token {
             Token::Ident(ident) => match ident.as_str() {
                 "msg" => self.parse_msg()?,
+                "note" => self.parse_note()?,
                 "tmsg" => self.parse_tmsg()?,
                 "tp" => self.parse_tp()?,
                 "tpif" => self.parse_tpif()?,
-                "if" => self.parse_if()?,
+                "move" => self.parse_move()?,
+                "if" => self.nested(Self::parse_if)?,
+                "random" => self.nested(Self::parse_random)?,
                 "setflag" | "unsetflag" | "readflag" => self.parse_flag_cmd(ident)?,
+                "wait" => self.parse_wait()?,
+                "warp" => self.parse_warp()?,
+                "playsound" => Cmd::PlaySound {
+                    id: self.parse_number("playsound", "a sound id")?,
+                },
+                "give" | "take" => self.parse_item_cmd(ident)?,
+                "setvar" | "addvar" => self.parse_var_cmd(ident)?,
+                "choice" => self.nested(Self::parse_choice)?,
+                "call" => self.parse_call()?,
+                "return" => Cmd::Return,
+                "lock" => Cmd::Lock,
+                "unlock" => Cmd::Unlock,
+                "face" => self.parse_face()?,
+                "end" => Cmd::End,
+                "endif" => return Err(self.err(ParseErrorKind::StrayEndif)),
 
-                t => return Err(format!("parse: invalid ident token: {t}")),
+                _ => return Err(self.unexpected("a command", Token::Ident(ident))),
             },
-            _ => return Err(format!("parse: invalid token: {token:?}")),
+            other => return Err(self.unexpected("a command", other)),
         };
 
         Ok(cmd)
     }
-    fn parse_msg(&mut self) -> Result<Cmd, String> {
-        let text = self.parse_text()?;
-        let i = self.controller.insert_text(&text);
-        Ok(Cmd::Msg {
+    fn parse_msg(&mut self) -> Result<Cmd, ParseError> {
+        let text = self.parse_text_symbol("msg")?;
+        Ok(Cmd::Msg { text })
+    }
+
+    /// `note {text}`: kept in the tree but not in the text table, the VM
+    /// never shows it.
+    fn parse_note(&mut self) -> Result<Cmd, ParseError> {
+        let text = self.parse_text("note")?;
+        Ok(Cmd::Note {
             text: Text {
-                text: text,
-                index: i,
+                text: self.controller.share(&text),
+                index: 0,
             },
         })
     }
 
-    fn parse_tmsg(&mut self) -> Result<Cmd, String> {
-        let loc = self.parse_location()?;
-        let text = self.parse_text()?;
-        let i = self.controller.insert_text(&text);
-        Ok(Cmd::TMsg {
-            at: loc,
-            text: Text {
-                text: text,
-                index: i,
+    fn parse_tmsg(&mut self) -> Result<Cmd, ParseError> {
+        let loc = self.parse_location("tmsg")?;
+        let text = self.parse_text_symbol("tmsg")?;
+        Ok(Cmd::TMsg { at: loc, text })
+    }
+
+    fn parse_tp(&mut self) -> Result<Cmd, ParseError> {
+        let to = self.parse_location("tp")?;
+
+        Ok(Cmd::Tp { to })
+    }
+    fn parse_tpif(&mut self) -> Result<Cmd, ParseError> {
+        let from = self.parse_location("tpif")?;
+
+        let to = self.parse_location("tpif")?;
+
+        Ok(Cmd::TpIf { from, to })
+    }
+
+    fn parse_call(&mut self) -> Result<Cmd, ParseError> {
+        let name = match self.expect_token("call")? {
+            Token::At(name) => name,
+            other => return Err(self.unexpected("@ and a script name", other)),
+        };
+        if !self.subscripts.is_some_and(|s| s.contains(&name)) {
+            let suggestion = closest_name(&name, self.subscripts.into_iter().flatten());
+            return Err(self.err(ParseErrorKind::UnknownScript { name, suggestion }));
+        }
+        let index = self
+            .controller
+            .insert_script(&name)
+            .map_err(|k| self.err(k))?;
+        Ok(Cmd::Call {
+            script: Text {
+                text: name.into(),
+                index,
             },
         })
     }
 
-    fn parse_tp(&mut self) -> Result<Cmd, String> {
-        let to = match self.parse_location() {
-            Err(e) => return Err(e),
-            Ok(loc) => loc,
-        };
+    fn parse_move(&mut self) -> Result<Cmd, ParseError> {
+        let from = self.parse_location("move")?;
 
-        Ok(Cmd::Tp { to })
+        let to = self.parse_location("move")?;
+
+        Ok(Cmd::Move { from, to })
+    }
+
+    /// Read the numeric operand `what` of command `cmd`.
+    fn parse_number(&mut self, cmd: &str, what: &str) -> Result<u16, ParseError> {
+        match self.expect_token(cmd)? {
+            Token::Number(n) => Ok(n),
+            other => Err(self.unexpected(format!("{what} for {cmd}"), other)),
+        }
     }
-    fn parse_tpif(&mut self) -> Result<Cmd, String> {
-        let from = match self.parse_location() {
-            Err(e) => return Err(e),
-            Ok(loc) => loc,
+
+    fn parse_face(&mut self) -> Result<Cmd, ParseError> {
+        let dir = match self.expect_token("face")? {
+            Token::Ident(dir) => match dir.as_str() {
+                "up" => Direction::Up,
+                "down" => Direction::Down,
+                "left" => Direction::Left,
+                "right" => Direction::Right,
+                _ => return Err(self.err(ParseErrorKind::BadDirection(dir))),
+            },
+            other => return Err(self.unexpected("a direction for face", other)),
         };
+        Ok(Cmd::Face { dir })
+    }
+
+    fn parse_wait(&mut self) -> Result<Cmd, ParseError> {
+        let frames = self.parse_number("wait", "a frame count")?;
+        Ok(Cmd::Wait { frames })
+    }
+
+    fn parse_item_cmd(&mut self, op: String) -> Result<Cmd, ParseError> {
+        let item = self.parse_number(&op, "an item id")?;
+        let count = self.parse_number(&op, "an item count")?;
+        match op.as_str() {
+            "give" => Ok(Cmd::Give { item, count }),
+            "take" => Ok(Cmd::Take { item, count }),
+            _ => unreachable!(),
+        }
+    }
 
-        let to = match self.parse_location() {
-            Err(e) => return Err(e),
-            Ok(loc) => loc,
+    /// Read a `var_`-prefixed variable name and register it.
+    fn parse_var(&mut self, cmd: &str) -> Result<Text, ParseError> {
+        let var = match self.expect_token(cmd)? {
+            Token::Ident(v) if v.starts_with("var_") => v,
+            Token::Ident(v) => return Err(self.err(ParseErrorKind::BadVarName(v))),
+            other => return Err(self.unexpected("a var name", other)),
         };
+        let index = self.controller.insert_var(&var).map_err(|k| self.err(k))?;
+        Ok(Text {
+            text: var.into(),
+            index,
+        })
+    }
 
-        Ok(Cmd::TpIf { from, to })
+    fn parse_var_cmd(&mut self, op: String) -> Result<Cmd, ParseError> {
+        let var = self.parse_var(&op)?;
+        let value = self.parse_number(&op, "a value")?;
+        match op.as_str() {
+            "setvar" => Ok(Cmd::SetVar { var, value }),
+            "addvar" => Ok(Cmd::AddVar { var, value }),
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_if(&mut self) -> Result<Cmd, ParseError> {
+        let opened = self.pos;
+        self.parse_if_opened_at(opened)
     }
 
-    fn parse_if(&mut self) -> Result<Cmd, String> {
+    /// The rest of an `if` whose chain started at `opened`, the `if` the
+    /// final `endif` closes.
+    fn parse_if_opened_at(&mut self, opened: Span) -> Result<Cmd, ParseError> {
         let condition = self.parse_condition()?;
-        let then_branch = self.parse_branch()?;
 
-        let then_branch = match then_branch {
-            Some(then_branch) => then_branch,
-            None => return Err("if branch must have then branch".to_string()),
-        };
+        match self.expect_token("if")? {
+            Token::Ident(t) if t == "then" => {}
+            other => return Err(self.unexpected("'then' after condition", other)),
+        }
 
-        let else_branch = self.parse_branch()?;
+        let (then_branch, closed_by) = self.parse_branch(opened)?;
+        if then_branch.is_empty() {
+            return Err(self.err(ParseErrorKind::EmptyBranch));
+        }
+        self.check_block(&then_branch)?;
 
-        // if else is none  then its an then only branch
-        let branches = match else_branch {
-            Some(else_branch) => Branch::ThenElse(Box::new(then_branch), Box::new(else_branch)),
-            None => Branch::Then(Box::new(then_branch)),
+        let branches = if closed_by == "else" && self.peek_ident("if") {
+            // `else if …` chains share the single `endif` at the very end,
+            // so the nested `if` consumes it for the whole chain.
+            self.next_token();
+            let else_branch = vec![self.nested(|p| p.parse_if_opened_at(opened))?];
+            self.check_block(&else_branch)?;
+            Branch::ThenElse(then_branch, else_branch)
+        } else if closed_by == "else" {
+            let (else_branch, closed_by) = self.parse_branch(opened)?;
+            if closed_by != "endif" {
+                return Err(self.err(ParseErrorKind::UnclosedIf { line: opened.line }));
+            }
+            self.check_block(&else_branch)?;
+            Branch::ThenElse(then_branch, else_branch)
+        } else {
+            Branch::Then(then_branch)
         };
 
-        match branches {
-            Branch::ThenElse(_, _) => {
-                let endif = match self.lex.next().unwrap() {
-                    Ok(Token::Ident(ident)) => match ident.as_str() {
-                        "endif" => true,
-                        _ => false,
-                    },
-                    _ => return Err("invalid token after tp".to_string()),
-                };
-                if !endif {
-                    return Err("invalid token after if, expected endif".to_string());
+        Ok(Cmd::If {
+            condition,
+            branches,
+        })
+    }
+
+    /// `choice {prompt} [ {option} -> cmds … ] endchoice`; an option's
+    /// branch runs up to the next option's text or the closing `]`.
+    fn parse_choice(&mut self) -> Result<Cmd, ParseError> {
+        let prompt = self.parse_text_symbol("choice")?;
+        match self.expect_token("choice")? {
+            Token::LBracket => {}
+            other => return Err(self.unexpected("'[' after the choice prompt", other)),
+        }
+
+        let mut options = Vec::new();
+        loop {
+            match self.expect_token("choice")? {
+                Token::RBracket if !options.is_empty() => break,
+                Token::Text(text) => {
+                    if options.len() == usize::from(u8::MAX) {
+                        return Err(self.err(ParseErrorKind::TooManyOptions));
+                    }
+                    let option = self
+                        .controller
+                        .insert_text(&text)
+                        .map_err(|k| self.err(k))?;
+                    match self.expect_token("choice option")? {
+                        Token::Arrow => {}
+                        other => return Err(self.unexpected("'->' after the option", other)),
+                    }
+                    let cmds = self.parse_option_branch()?;
+                    self.check_block(&cmds)?;
+                    options.push((option, cmds));
                 }
+                other if options.is_empty() => {
+                    return Err(self.unexpected("a choice option", other));
+                }
+                other => return Err(self.unexpected("a choice option or ']'", other)),
             }
-            _ => {}
         }
 
-        Ok(Cmd::If {
-            condition,
-            branches,
+        match self.expect_token("choice")? {
+            Token::Ident(t) if t == "endchoice" => {}
+            other => return Err(self.unexpected("endchoice", other)),
+        }
+        Ok(Cmd::Choice { prompt, options })
+    }
+
+    /// Commands of one choice option, up to the next option or the `]`.
+    fn parse_option_branch(&mut self) -> Result<Vec<Cmd>, ParseError> {
+        let mut cmds = Vec::new();
+        loop {
+            match self.peek_token() {
+                Some(Ok(Token::Text(_) | Token::RBracket)) => return Ok(cmds),
+                Some(Ok(Token::Semicolon)) => {
+                    self.next_token();
+                }
+                None | Some(Ok(Token::Eof)) => {
+                    self.next_token();
+                    return Err(self.err(ParseErrorKind::UnexpectedEof {
+                        context: "choice option".into(),
+                    }));
+                }
+                _ => cmds.push(self.parse_cmd()?),
+            }
+        }
+    }
+
+    /// A `{text}` operand of `cmd`, interned.
+    fn parse_text_symbol(&mut self, cmd: &str) -> Result<Text, ParseError> {
+        let text = self.parse_text(cmd)?;
+        self.controller.insert_text(&text).map_err(|k| self.err(k))
+    }
+
+    /// `random <percent> then <cmd>`
+    fn parse_random(&mut self) -> Result<Cmd, ParseError> {
+        let chance = self.parse_number("random", "a chance")?;
+        let chance = u8::try_from(chance)
+            .ok()
+            .filter(|&c| c <= 100)
+            .ok_or_else(|| self.err(ParseErrorKind::BadChance(chance)))?;
+
+        match self.expect_token("random")? {
+            Token::Ident(t) if t == "then" => {}
+            other => return Err(self.unexpected("'then' after the chance", other)),
+        }
+        let then = self.parse_cmd()?;
+        self.check_block(std::slice::from_ref(&then))?;
+        Ok(Cmd::Random {
+            chance,
+            then: Box::new(then),
         })
     }
 
-    fn parse_text(&mut self) -> Result<String, String> {
-        let text = match self.lex.next().unwrap() {
-            Ok(Token::Text(text)) => text,
-            _ => return Err("invalid token after tp".to_string()),
+    fn parse_text(&mut self, cmd: &str) -> Result<String, ParseError> {
+        let text = match self.expect_token(cmd)? {
+            Token::Text(text) => text,
+            other => return Err(self.unexpected(format!("text for {cmd}"), other)),
         };
         Ok(text)
     }
 
-    fn parse_location(&mut self) -> Result<Location, String> {
-        let next = self.lex.next().unwrap();
-        let next_token = match next {
-            Err(e) => return Err(e),
-            Ok(token) => token,
+    fn parse_warp(&mut self) -> Result<Cmd, ParseError> {
+        let map = self.parse_number("warp", "a map id")?;
+        // the target map's locations are not known here, so `@loc` is
+        // kept symbolic for whoever links that map to resolve
+        let to = match self.peek_token() {
+            Some(Ok(Token::At(_) | Token::AtAt(_))) => {
+                let (Token::At(at) | Token::AtAt(at)) = self.expect_token("warp")? else {
+                    unreachable!()
+                };
+                let index = self.controller.insert_tag(&at).map_err(|k| self.err(k))?;
+                Location::Tag(Text {
+                    text: at.into(),
+                    index,
+                })
+            }
+            _ => self.parse_location("warp")?,
         };
+        Ok(Cmd::Warp { map, to })
+    }
+
+    /// A location operand of `cmd`.
+    fn parse_location(&mut self, cmd: &str) -> Result<Location, ParseError> {
+        let next_token = self.expect_token(cmd)?;
 
         match next_token {
             Token::At(at) => {
-                self.controller.insert_tag(&at);
-                let res = self.locations.get(&at);
-                let cords = match res {
-                    Some(cords) => cords,
-                    None => return Err(format!("location {} not found!", at)),
-                };
-                Ok(Location::Cords(cords.0, cords.1))
+                let (x, y) = self.lookup_location(&at)?;
+                self.controller.insert_tag(&at).map_err(|k| self.err(k))?;
+                Ok(Location::Cords(x, y))
+            }
+            // `@@tag` stays symbolic: the VM looks the coordinates up at
+            // runtime, but the location must still exist on the map
+            Token::AtAt(at) => {
+                self.lookup_location(&at)?;
+                let index = self.controller.insert_tag(&at).map_err(|k| self.err(k))?;
+                Ok(Location::Tag(Text {
+                    text: at.into(),
+                    index,
+                }))
             }
             Token::Number(n1) => {
-                let n2 = match self
-                    .lex
-                    .next()
-                    .unwrap()
-                    .map_err(|e| format!("invalid token after tp {e}"))?
-                {
+                let n2 = match self.expect_token(cmd)? {
                     Token::Number(n) => n,
-                    _ => return Err("invalid token after number".to_string()),
+                    other => {
+                        return Err(self.unexpected(format!("a y coordinate for {cmd}"), other));
+                    }
                 };
 
                 Ok(Location::Cords(n1, n2))
             }
-            _ => Err("invalid token after tp".to_string()),
+            // `~dx ~dy`, both relative to where the script runs
+            Token::Offset(dx) => {
+                let dy = match self.expect_token(cmd)? {
+                    Token::Offset(n) => n,
+                    other => return Err(self.unexpected(format!("a ~y offset for {cmd}"), other)),
+                };
+
+                Ok(Location::Relative(dx, dy))
+            }
+            other => Err(self.unexpected(format!("a location for {cmd}"), other)),
         }
     }
 
-    fn parse_condition(&mut self) -> Result<Condition, String> {
-        let next = self.lex.next().unwrap();
-        let next_token = match next {
-            Err(e) => return Err(e),
-            Ok(token) => token,
-        };
+    fn lookup_location(&self, name: &str) -> Result<(u16, u16), ParseError> {
+        match self.locations.get(name) {
+            Some(&cords) => Ok(cords),
+            None => {
+                let suggestion = closest_name(name, self.locations.keys());
+                Err(self.err(ParseErrorKind::UnknownLocation {
+                    name: name.into(),
+                    suggestion,
+                }))
+            }
+        }
+    }
+
+    /// `cond ::= and_cond ('or' and_cond)*` – `or` binds looser than `and`,
+    /// both associate to the left.
+    fn parse_condition(&mut self) -> Result<Condition, ParseError> {
+        let mut cond = self.parse_and_condition()?;
+        while self.peek_ident("or") {
+            self.next_token();
+            let rhs = self.parse_and_condition()?;
+            cond = Condition::Or(Box::new(cond), Box::new(rhs));
+        }
+        Ok(cond)
+    }
+
+    /// `and_cond ::= atom ('and' atom)*`
+    fn parse_and_condition(&mut self) -> Result<Condition, ParseError> {
+        let mut cond = self.parse_condition_atom()?;
+        while self.peek_ident("and") {
+            self.next_token();
+            let rhs = self.parse_condition_atom()?;
+            cond = Condition::And(Box::new(cond), Box::new(rhs));
+        }
+        Ok(cond)
+    }
+
+    /// `atom ::= '(' cond ')' | 'has_item' NUM NUM? | var CMP NUM | flag | '!' flag`
+    ///
+    /// As in the flag commands, a flag must be spelled `flag_…`; bare names
+    /// are rejected so a typo cannot silently create a new flag.
+    fn parse_condition_atom(&mut self) -> Result<Condition, ParseError> {
+        let next_token = self.expect_token("condition")?;
 
         match next_token {
+            Token::LParen => {
+                let opened_at = self.pos;
+                if self.peek_token() == Some(Ok(&Token::RParen)) {
+                    self.next_token();
+                    return Err(self.err(ParseErrorKind::EmptyParens));
+                }
+                let cond = self.nested(Self::parse_condition)?;
+                match self.expect_token(&format!("'(' opened at {opened_at}"))? {
+                    Token::RParen => Ok(cond),
+                    other => {
+                        Err(self
+                            .unexpected(format!("')' to close '(' opened at {opened_at}"), other))
+                    }
+                }
+            }
+            Token::Ident(t) if t == "has_item" => {
+                let item = self.parse_number("has_item", "an item id")?;
+                // the count is optional and defaults to a single item
+                let count = match self.peek_token() {
+                    Some(Ok(Token::Number(_))) => self.parse_number("has_item", "an item count")?,
+                    _ => 1,
+                };
+                Ok(Condition::HasItem { item, count })
+            }
+            Token::Ident(var) if var.starts_with("var_") => {
+                let index = self.controller.insert_var(&var).map_err(|k| self.err(k))?;
+                let op = match self.expect_token("condition")? {
+                    Token::EqEq => CmpOp::Eq,
+                    Token::NotEq => CmpOp::Ne,
+                    Token::Lt => CmpOp::Lt,
+                    Token::Gt => CmpOp::Gt,
+                    other => {
+                        return Err(self.unexpected(format!("a comparison after {var}"), other));
+                    }
+                };
+                let value = self.parse_number("condition", "a value to compare against")?;
+                Ok(Condition::VarCmp {
+                    var: Text {
+                        text: var.into(),
+                        index,
+                    },
+                    op,
+                    value,
+                })
+            }
+            Token::Ident(flag) | Token::Bang(flag) if !flag.starts_with("flag_") => {
+                Err(self.err(ParseErrorKind::BadConditionFlag(flag)))
+            }
             Token::Ident(flag) => {
-                let i = self.controller.insert_flag(&flag);
+                let i = self
+                    .controller
+                    .insert_flag(&flag)
+                    .map_err(|k| self.err(k))?;
                 Ok(Condition::FlagSet(Text {
-                    text: flag,
-                    index: i as u16,
+                    text: flag.into(),
+                    index: i,
                 }))
             }
             Token::Bang(flag) => {
-                let i = self.controller.insert_flag(&flag);
+                let i = self
+                    .controller
+                    .insert_flag(&flag)
+                    .map_err(|k| self.err(k))?;
                 Ok(Condition::FlagClear(Text {
-                    text: flag,
-                    index: i as u16,
+                    text: flag.into(),
+                    index: i,
                 }))
             }
-            _ => Err("invalid token after if".to_string()),
+            other => Err(self.unexpected("a condition", other)),
         }
     }
 
-    fn parse_branch(&mut self) -> Result<Option<Cmd>, String> {
-        let next = self.lex.next().unwrap();
-        let next_token = match next {
-            Err(e) => return Err(e),
-            Ok(token) => token,
-        };
+    /// Reject a branch body the VM could not jump over.
+    fn check_block(&self, cmds: &[Cmd]) -> Result<(), ParseError> {
+        match block_len(cmds) {
+            Some(_) => Ok(()),
+            None => {
+                let len = cmds.iter().map(Cmd::byte_len).sum();
+                Err(self.err(ParseErrorKind::BlockTooLarge(len)))
+            }
+        }
+    }
 
-        match next_token {
-            Token::Ident(t) => match t.as_str() {
-                "endif" => Ok(None),
-                _ => {
-                    let branch = self.parse_cmd()?;
-                    Ok(Some(branch))
+    /// Parse commands until the `else` or `endif` closing the branch of the
+    /// `if` at `opened`; returns them together with the keyword that ended
+    /// the branch.
+    fn parse_branch(&mut self, opened: Span) -> Result<(Vec<Cmd>, String), ParseError> {
+        let mut cmds = Vec::new();
+        loop {
+            match self.peek_token() {
+                Some(Ok(Token::Ident(t))) if t == "else" || t == "endif" => {
+                    let keyword = t.clone();
+                    self.next_token();
+                    return Ok((cmds, keyword));
                 }
-            },
-            _ => Err("invalid token after if".to_string()),
+                Some(Ok(Token::Semicolon)) => {
+                    self.next_token();
+                }
+                None | Some(Ok(Token::Eof)) => {
+                    self.next_token();
+                    return Err(self.err(ParseErrorKind::UnclosedIf { line: opened.line }));
+                }
+                _ => cmds.push(self.parse_cmd()?),
+            }
         }
     }
 
-    fn parse_flag_cmd(&mut self, op: String) -> Result<Cmd, String> {
-        let next = self.lex.next().ok_or("expected flag after command")??;
+    fn parse_flag_cmd(&mut self, op: String) -> Result<Cmd, ParseError> {
+        let next = self.expect_token("flag")?;
         let flag = match next {
             Token::Ident(f) if f.starts_with("flag_") => f,
-            other => return Err(format!("invalid flag token: {other:?}")),
+            Token::Ident(f) => return Err(self.err(ParseErrorKind::BadFlagName(f))),
+            other => return Err(self.unexpected("a flag name", other)),
         };
 
-        let i = self.controller.insert_flag(&flag);
+        let i = self
+            .controller
+            .insert_flag(&flag)
+            .map_err(|k| self.err(k))?;
 
         let cmd = match op.as_str() {
             "setflag" => Cmd::SetFlag {
                 flag: Text {
-                    text: flag,
-                    index: i as u16,
+                    text: flag.into(),
+                    index: i,
                 },
             },
             "unsetflag" => Cmd::UnsetFlag {
                 flag: Text {
-                    text: flag,
-                    index: i as u16,
+                    text: flag.into(),
+                    index: i,
                 },
             },
             "readflag" => Cmd::ReadFlag {
                 flag: Text {
-                    text: flag,
-                    index: i as u16,
+                    text: flag.into(),
+                    index: i,
                 },
             },
             _ => unreachable!(),
@@ -373,21 +1166,42 @@ mod tests {
     use crate::model::ScriptEntry;
 
     use super::*;
+    use std::sync::LazyLock;
+
+    static NO_LOCATIONS: LazyLock<LocationTags> = LazyLock::new(LocationTags::new);
+
+    #[test]
+    fn test_parse_script_standalone() {
+        let (cmds, symbols) = parse_script("setflag flag_a; if flag_a then wait 2 endif;").unwrap();
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(symbols.flags["flag_a"], 0);
+        assert!(symbols.texts.is_empty());
+
+        // no locations are known outside a project
+        let err = parse_script("tp @home;").unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::UnknownLocation { .. }));
+    }
 
     #[test]
     fn test_parse_msg() {
-        let test_cases = vec![(
-            "msg {hello world};",
+        let msg = |text: &str| {
             Ok(Cmd::Msg {
                 text: Text {
-                    text: "hello world".into(),
+                    text: text.into(),
                     index: 0,
                 },
-            }),
-        )];
+            })
+        };
+        let test_cases = vec![
+            ("msg {hello world};", msg("hello world")),
+            // an escaped and a literal line break are the same text
+            (r"msg {Line one\nLine two};", msg("Line one\nLine two")),
+            ("msg {Line one\nLine two};", msg("Line one\nLine two")),
+        ];
 
         for (input, expected) in test_cases {
-            let mut parser = Parser::new(input, Controller::new(), HashMap::new());
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &NO_LOCATIONS);
             let result = parser.parse_cmd();
             assert_eq!(result, expected);
         }
@@ -398,30 +1212,144 @@ mod tests {
         let mut locations = LocationTags::new();
         locations.insert("testLoc".into(), (1, 1));
         locations.insert("loc2".into(), (2, 2));
-        let test_cases = vec![(
-            "tmsg @testLoc {hello world};",
-            Ok(Cmd::TMsg {
-                at: Location::Cords(1, 1),
-                text: Text {
-                    text: "hello world".into(),
-                    index: 0,
-                },
-            }),
-        )];
+        let test_cases = vec![
+            (
+                "tmsg @testLoc {hello world};",
+                Ok(Cmd::TMsg {
+                    at: Location::Cords(1, 1),
+                    text: Text {
+                        text: "hello world".into(),
+                        index: 0,
+                    },
+                }),
+            ),
+            (
+                "tmsg 3 4 {hi};",
+                Ok(Cmd::TMsg {
+                    at: Location::Cords(3, 4),
+                    text: Text {
+                        text: "hi".into(),
+                        index: 0,
+                    },
+                }),
+            ),
+        ];
 
         for (input, expected) in test_cases {
-            let mut parser = Parser::new(input, Controller::new(), locations.clone());
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &locations);
             let result = parser.parse_cmd();
             assert_eq!(result, expected);
         }
+
+        // errors name the command, not whichever one was written first
+        for (input, message) in [
+            ("tmsg 3 4;", "1:9: expected text for tmsg, got Semicolon"),
+            (
+                "tmsg 3 {hi};",
+                "1:8: expected a y coordinate for tmsg, got Text(\"hi\")",
+            ),
+            (
+                "tmsg {hi};",
+                "1:6: expected a location for tmsg, got Text(\"hi\")",
+            ),
+        ] {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &locations);
+            assert_eq!(parser.parse_cmd().unwrap_err().to_string(), message);
+        }
     }
     #[test]
-    fn test_parse_tp() {
+    fn test_parse_symbolic_location() {
         let mut locations = LocationTags::new();
         locations.insert("loc1".into(), (1, 1));
         locations.insert("loc2".into(), (2, 2));
-        let test_cases = vec![
-            (
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("tmsg @@loc2 {hi}", &mut controller, &locations);
+        let tag = Location::Tag(Text {
+            text: "loc2".into(),
+            index: 0,
+        });
+
+        let tmsg = parser.parse_cmd().unwrap();
+        assert_eq!(
+            tmsg,
+            Cmd::TMsg {
+                at: tag.clone(),
+                text: Text {
+                    text: "hi".into(),
+                    index: 0,
+                },
+            }
+        );
+        // tag kind, then the tag index
+        assert_eq!(tmsg.to_bytes(), vec![Opcode::TMsg as u8, 2, 0, 0, 0, 0]);
+
+        let mut parser = Parser::new("tpif @loc1 @@loc2", &mut controller, &locations);
+        let tp = parser.parse_cmd().unwrap();
+        assert_eq!(
+            tp,
+            Cmd::TpIf {
+                from: Location::Cords(1, 1),
+                to: tag,
+            }
+        );
+        assert_eq!(
+            tp.to_bytes(),
+            vec![Opcode::TpIf as u8, 0, 1, 0, 1, 0, 2, 0, 0]
+        );
+        assert_eq!(parser.controller.tags["loc1"], 1);
+    }
+
+    #[test]
+    fn test_symbolic_location_must_exist() {
+        let mut locations = LocationTags::new();
+        locations.insert("spawn".into(), (1, 1));
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("tp @@spwan; tp @spwan;", &mut controller, &locations);
+        for _ in 0..2 {
+            let err = parser.parse_cmd().unwrap_err();
+            assert_eq!(
+                err.kind,
+                ParseErrorKind::UnknownLocation {
+                    name: "spwan".into(),
+                    suggestion: Some("spawn".into()),
+                }
+            );
+            parser.next_token();
+        }
+        // a tag that resolves to nothing takes no index
+        assert!(controller.tags.is_empty());
+    }
+
+    #[test]
+    fn test_tp_and_tpif_opcodes() {
+        // `tp` takes one location and `tpif` two; each keeps its own opcode
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("tp 3 4", &mut controller, &NO_LOCATIONS);
+        let tp = parser.parse_cmd().unwrap();
+        assert_eq!(
+            tp,
+            Cmd::Tp {
+                to: Location::Cords(3, 4)
+            }
+        );
+        assert_eq!(tp.to_bytes()[0], Opcode::Tp as u8);
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("tpif 1 2 3 4", &mut controller, &NO_LOCATIONS);
+        let tpif = parser.parse_cmd().unwrap();
+        assert!(matches!(tpif, Cmd::TpIf { .. }));
+        assert_eq!(tpif.to_bytes()[0], Opcode::TpIf as u8);
+    }
+
+    #[test]
+    fn test_parse_tp() {
+        let mut locations = LocationTags::new();
+        locations.insert("loc1".into(), (1, 1));
+        locations.insert("loc2".into(), (2, 2));
+        let test_cases = vec![
+            (
                 "tpif @loc1 @loc2;",
                 Ok(Cmd::TpIf {
                     from: Location::Cords(1, 1),
@@ -452,12 +1380,266 @@ mod tests {
         ];
 
         for (input, expected) in test_cases {
-            let mut parser = Parser::new(input, Controller::new(), locations.clone());
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &locations);
+            let result = parser.parse_cmd();
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_call() {
+        let entry = |x: f32, script: &str, name: Option<&str>| ScriptEntry {
+            script: script.into(),
+            x,
+            name: name.map(String::from),
+            ..Default::default()
+        };
+        let layer = ScriptLayer {
+            objects: vec![
+                entry(0.0, "call @farewell; call @greeting; call @greeting;", None),
+                entry(16.0, "msg {hello}; return;", Some("greeting")),
+                entry(32.0, "msg {bye}; return;", Some("farewell")),
+            ],
+        };
+
+        let parsed = parse_scripts(&layer, &NO_LOCATIONS, &ChunkLayout::default()).unwrap();
+
+        // indices follow the order of first call
+        assert_eq!(parsed.scripts["farewell"], 0);
+        assert_eq!(parsed.scripts["greeting"], 1);
+        let caller = &parsed.chunks[0][0].body;
+        assert_eq!(
+            caller[1],
+            Cmd::Call {
+                script: Text {
+                    text: "greeting".into(),
+                    index: 1,
+                },
+            }
+        );
+        assert_eq!(caller[1].to_bytes(), vec![Opcode::Call as u8, 1, 0]);
+        assert_eq!(parsed.chunks[0][1].body[1], Cmd::Return);
+    }
+
+    #[test]
+    fn test_call_unknown_script() {
+        let names: HashSet<String> = ["greeting".to_string()].into();
+        let mut controller = Controller::new();
+        let mut parser =
+            Parser::new("call @greting;", &mut controller, &NO_LOCATIONS).with_subscripts(&names);
+        assert_eq!(
+            parser.parse().unwrap_err().to_string(),
+            "1:6: script greting not found! did you mean @greeting?"
+        );
+
+        // outside a project nothing can be called
+        let err = parse_script("call @greeting;").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::UnknownScript {
+                name: "greeting".into(),
+                suggestion: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_relative_location() {
+        let mut locations = LocationTags::new();
+        locations.insert("door".into(), (4, 5));
+        let test_cases = vec![
+            (
+                "tp ~2 ~-1;",
+                Ok(Cmd::Tp {
+                    to: Location::Relative(2, -1),
+                }),
+            ),
+            (
+                "tpif ~1 ~1 0 0;",
+                Ok(Cmd::TpIf {
+                    from: Location::Relative(1, 1),
+                    to: Location::Cords(0, 0),
+                }),
+            ),
+            (
+                "tpif ~2 ~-1 @door;",
+                Ok(Cmd::TpIf {
+                    from: Location::Relative(2, -1),
+                    to: Location::Cords(4, 5),
+                }),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &locations);
+            assert_eq!(parser.parse_cmd(), expected, "{input}");
+        }
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("tp ~1 1;", &mut controller, &locations);
+        assert_eq!(
+            parser.parse_cmd().unwrap_err().to_string(),
+            "1:7: expected a ~y offset for tp, got Number(1)"
+        );
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("tpif ~1 ~1 0 0;", &mut controller, &locations);
+        #[rustfmt::skip]
+        assert_eq!(
+            parser.parse_cmd().unwrap().to_bytes(),
+            vec![Opcode::TpIf as u8, 1, 1, 0, 1, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_parse_move() {
+        let mut locations = LocationTags::new();
+        locations.insert("loc1".into(), (1, 1));
+        locations.insert("loc2".into(), (2, 2));
+        let test_cases = vec![
+            (
+                "move @loc1 @loc2;",
+                Ok(Cmd::Move {
+                    from: Location::Cords(1, 1),
+                    to: Location::Cords(2, 2),
+                }),
+            ),
+            (
+                "move @loc1 1 2;",
+                Ok(Cmd::Move {
+                    from: Location::Cords(1, 1),
+                    to: Location::Cords(1, 2),
+                }),
+            ),
+            (
+                "move 1 2 @loc1;",
+                Ok(Cmd::Move {
+                    from: Location::Cords(1, 2),
+                    to: Location::Cords(1, 1),
+                }),
+            ),
+            (
+                "move 256 256 0 0;",
+                Ok(Cmd::Move {
+                    from: Location::Cords(256, 256),
+                    to: Location::Cords(0, 0),
+                }),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &locations);
             let result = parser.parse_cmd();
             assert_eq!(result, expected);
         }
     }
 
+    #[test]
+    fn test_parse_random() {
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "random 50 then msg {rare!}; random 0 then setflag flag_a;",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        let rare = Text {
+            text: "rare!".into(),
+            index: 0,
+        };
+        let flag = Text {
+            text: "flag_a".into(),
+            index: 0,
+        };
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![
+                Cmd::Random {
+                    chance: 50,
+                    then: Box::new(Cmd::Msg { text: rare }),
+                },
+                Cmd::Random {
+                    chance: 0,
+                    then: Box::new(Cmd::SetFlag { flag }),
+                },
+            ])
+        );
+
+        let parse = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            parser.parse().unwrap_err()
+        };
+        assert_eq!(
+            parse("random 101 then end;").kind,
+            ParseErrorKind::BadChance(101)
+        );
+        assert_eq!(
+            parse("random 300 then end;").kind,
+            ParseErrorKind::BadChance(300)
+        );
+        assert_eq!(
+            parse("random 50 msg {a};").to_string(),
+            "1:11: expected 'then' after the chance, got Ident(\"msg\")"
+        );
+        // the `if` fits its own jump offset but not the one skipping it
+        let body = "wait 1; ".repeat(21845);
+        assert_eq!(
+            parse(&format!("random 50 then if flag_a then {body} endif;")).kind,
+            ParseErrorKind::BlockTooLarge(65542)
+        );
+    }
+
+    #[test]
+    fn test_parse_warp() {
+        // `spawn` lives on map 3, not on this one
+        let mut locations = LocationTags::new();
+        locations.insert("loc1".into(), (1, 1));
+        let spawn = |index| Text {
+            text: "spawn".into(),
+            index,
+        };
+        let test_cases = vec![
+            (
+                "warp 3 @spawn;",
+                Ok(Cmd::Warp {
+                    map: 3,
+                    to: Location::Tag(spawn(0)),
+                }),
+            ),
+            (
+                "warp 3 @@spawn;",
+                Ok(Cmd::Warp {
+                    map: 3,
+                    to: Location::Tag(spawn(0)),
+                }),
+            ),
+            (
+                "warp 3 10 20;",
+                Ok(Cmd::Warp {
+                    map: 3,
+                    to: Location::Cords(10, 20),
+                }),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &locations);
+            let result = parser.parse_cmd();
+            assert_eq!(result, expected, "{input}");
+        }
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("warp @spawn;", &mut controller, &locations);
+        assert_eq!(
+            parser.parse_cmd().unwrap_err().to_string(),
+            "1:6: expected a map id for warp, got At(\"spawn\")"
+        );
+    }
+
     #[test]
     fn test_parse_if() {
         let test_cases = vec![
@@ -469,18 +1651,18 @@ mod tests {
                         index: 0,
                     }),
                     branches: Branch::ThenElse(
-                        Box::new(Cmd::SetFlag {
+                        vec![Cmd::SetFlag {
                             flag: Text {
                                 text: "flag_Y".into(),
                                 index: 1,
                             },
-                        }),
-                        Box::new(Cmd::UnsetFlag {
+                        }],
+                        vec![Cmd::UnsetFlag {
                             flag: Text {
                                 text: "flag_Y".into(),
                                 index: 1,
                             },
-                        }),
+                        }],
                     ),
                 }),
             ),
@@ -491,12 +1673,12 @@ mod tests {
                         text: "flag_X".into(),
                         index: 0,
                     }),
-                    branches: Branch::Then(Box::new(Cmd::SetFlag {
+                    branches: Branch::Then(vec![Cmd::SetFlag {
                         flag: Text {
                             text: "flag_Y".into(),
                             index: 1,
                         },
-                    })),
+                    }]),
                 }),
             ),
             (
@@ -506,18 +1688,18 @@ mod tests {
                         text: "flag_X".into(),
                         index: 0,
                     }),
-                    branches: Branch::Then(Box::new(Cmd::If {
+                    branches: Branch::Then(vec![Cmd::If {
                         condition: Condition::FlagSet(Text {
                             text: "flag_Y".into(),
                             index: 1,
                         }),
-                        branches: Branch::Then(Box::new(Cmd::SetFlag {
+                        branches: Branch::Then(vec![Cmd::SetFlag {
                             flag: Text {
                                 text: "flag_Z".into(),
                                 index: 2,
                             },
-                        })),
-                    })),
+                        }]),
+                    }]),
                 }),
             ),
             (
@@ -527,9 +1709,9 @@ mod tests {
                         text: "flag_test1".into(),
                         index: 0,
                     }),
-                    branches: Branch::Then(Box::new(Cmd::Tp {
+                    branches: Branch::Then(vec![Cmd::Tp {
                         to: Location::Cords(1, 1),
-                    })),
+                    }]),
                 }),
             ),
             (
@@ -540,12 +1722,12 @@ mod tests {
                         index: 0,
                     }),
                     branches: Branch::ThenElse(
-                        Box::new(Cmd::Tp {
+                        vec![Cmd::Tp {
                             to: Location::Cords(1, 1),
-                        }),
-                        Box::new(Cmd::Tp {
+                        }],
+                        vec![Cmd::Tp {
                             to: Location::Cords(0, 0),
-                        }),
+                        }],
                     ),
                 }),
             ),
@@ -554,12 +1736,1055 @@ mod tests {
         for (input, expected) in test_cases {
             println!("Testing: {input}");
 
-            let mut parser = Parser::new(input, Controller::new(), HashMap::new());
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &NO_LOCATIONS);
             let result = parser.parse_cmd();
             assert_eq!(result, expected);
         }
     }
 
+    #[test]
+    fn test_parse_end() {
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("end;", &mut controller, &NO_LOCATIONS);
+        assert_eq!(parser.parse(), Ok(vec![Cmd::End]));
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "if flag_X then end endif; msg {unreachable};",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        let cmds = parser.parse().unwrap();
+        assert_eq!(cmds.len(), 2);
+        assert!(
+            matches!(&cmds[0], Cmd::If { branches: Branch::Then(b), .. } if b == &vec![Cmd::End])
+        );
+    }
+
+    #[test]
+    fn test_parse_lock_unlock() {
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("lock; msg {look}; unlock;", &mut controller, &NO_LOCATIONS);
+        let cmds = parser.parse().unwrap();
+        assert_eq!(cmds[0], Cmd::Lock);
+        assert_eq!(cmds[2], Cmd::Unlock);
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("lock 1;", &mut controller, &NO_LOCATIONS);
+        assert_eq!(
+            parser.parse().unwrap_err().to_string(),
+            "1:6: expected a command, got Number(1)"
+        );
+    }
+
+    #[test]
+    fn test_parse_front_matter() {
+        let src = "---\nauthor: snail\n# draft\nversion: 2\n---\nsetflag flag_a;\nmsg {a";
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+        // the body keeps its own line numbers
+        assert_eq!(
+            parser.parse().unwrap_err().to_string(),
+            "7:5: no closing } found"
+        );
+        assert_eq!(parser.meta["author"], "snail");
+        assert_eq!(parser.meta["version"], "2");
+        assert_eq!(parser.meta.len(), 2);
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "---\r\nnpc: old man\r\n---\r\nmsg {hi};",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        let hi = Text {
+            text: "hi".into(),
+            index: 0,
+        };
+        assert_eq!(parser.parse(), Ok(vec![Cmd::Msg { text: hi }]));
+        assert_eq!(
+            parser.meta,
+            Metadata::from([("npc".into(), "old man".into())])
+        );
+
+        let err = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            parser.parse().unwrap_err().to_string()
+        };
+        assert_eq!(
+            err("---\nauthor: snail\nmsg {hi};"),
+            "1:1: front matter opened with '---' is never closed"
+        );
+        assert_eq!(
+            err("---\nauthor snail\n---\n"),
+            "2:1: expected 'key: value' in front matter, got 'author snail'"
+        );
+        assert_eq!(
+            err("---\na: 1\n\na: 2\n---\n"),
+            "4:1: front matter sets 'a' more than once"
+        );
+    }
+
+    #[test]
+    fn test_parse_face() {
+        let parse = |src: &str| {
+            let mut controller = Controller::new();
+            Parser::new(src, &mut controller, &NO_LOCATIONS).parse()
+        };
+        for (src, dir) in [
+            ("face up;", Direction::Up),
+            ("face down;", Direction::Down),
+            ("face left;", Direction::Left),
+            ("face right;", Direction::Right),
+        ] {
+            assert_eq!(parse(src), Ok(vec![Cmd::Face { dir }]), "{src}");
+        }
+
+        let err = parse("face north;").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::BadDirection("north".into()));
+        assert_eq!(
+            err.to_string(),
+            "1:6: expected up, down, left or right, got 'north'"
+        );
+        assert_eq!(
+            parse("face 1;").unwrap_err().to_string(),
+            "1:6: expected a direction for face, got Number(1)"
+        );
+    }
+
+    #[test]
+    fn test_parse_wait() {
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("wait 30; wait 0x10;", &mut controller, &NO_LOCATIONS);
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![Cmd::Wait { frames: 30 }, Cmd::Wait { frames: 16 }])
+        );
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("wait {soon};", &mut controller, &NO_LOCATIONS);
+        assert_eq!(
+            parser.parse().unwrap_err().to_string(),
+            "1:6: expected a frame count for wait, got Text(\"soon\")"
+        );
+    }
+
+    #[test]
+    fn test_parse_give_take() {
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("give 5 1; take 3 2;", &mut controller, &NO_LOCATIONS);
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![
+                Cmd::Give { item: 5, count: 1 },
+                Cmd::Take { item: 3, count: 2 },
+            ])
+        );
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("give 5;", &mut controller, &NO_LOCATIONS);
+        assert_eq!(
+            parser.parse().unwrap_err().to_string(),
+            "1:7: expected an item count for give, got Semicolon"
+        );
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("take 5", &mut controller, &NO_LOCATIONS);
+        assert_eq!(
+            parser.parse().unwrap_err().to_string(),
+            "1:7: unexpected end of input while parsing take"
+        );
+    }
+
+    #[test]
+    fn test_parse_multiple_statements() {
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "msg {a}; setflag flag_X; tp 1 2;",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        let result = parser.parse();
+        assert_eq!(
+            result,
+            Ok(vec![
+                Cmd::Msg {
+                    text: Text {
+                        text: "a".into(),
+                        index: 0,
+                    },
+                },
+                Cmd::SetFlag {
+                    flag: Text {
+                        text: "flag_X".into(),
+                        index: 0,
+                    },
+                },
+                Cmd::Tp {
+                    to: Location::Cords(1, 2),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_empty_script() {
+        for src in ["", "  \n ", "msg {a}"] {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            assert!(parser.parse().is_ok(), "input {src:?}");
+        }
+    }
+
+    #[test]
+    fn test_truncated_scripts_error() {
+        let mut locations = LocationTags::new();
+        locations.insert("loc1".into(), (1, 1));
+        let test_cases = vec![
+            ("msg", "msg"),
+            ("tmsg @loc1", "tmsg"),
+            ("tp 1", "tp"),
+            ("tpif", "tpif"),
+            ("if", "condition"),
+            ("if flag_X", "if"),
+            ("setflag", "flag"),
+        ];
+
+        for (input, context) in test_cases {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &locations);
+            let err = parser.parse().unwrap_err().to_string();
+            assert!(
+                err.ends_with(&format!("unexpected end of input while parsing {context}")),
+                "{input:?} gave {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unbalanced_endif() {
+        let parse = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            parser.parse().unwrap_err()
+        };
+
+        let missing = [
+            "if flag_X then",
+            "msg {a};\nif flag_X then setflag flag_Y;\nmsg {b};",
+            "\nif flag_X then setflag flag_Y else msg {a}",
+            "\nif flag_X then msg {a} else msg {b} else msg {c} endif",
+            // the chain's single `endif` closes the first `if`
+            "\nif flag_A then msg {a} else if flag_B then msg {b}",
+        ];
+        for (src, line) in missing.into_iter().zip([1, 2, 2, 2, 2]) {
+            assert_eq!(
+                parse(src).kind,
+                ParseErrorKind::UnclosedIf { line },
+                "{src:?}"
+            );
+        }
+        assert_eq!(
+            parse("msg {a};\n\nif flag_X then wait 1;").to_string(),
+            "3:23: expected 'endif' to close 'if' opened at line 3"
+        );
+
+        let err = parse("if flag_X then wait 1 endif;\nendif;");
+        assert_eq!(err.to_string(), "2:1: 'endif' without an open 'if'");
+        assert_eq!(
+            parse("if flag_A then msg {a} else if flag_B then msg {b} endif endif;").kind,
+            ParseErrorKind::StrayEndif
+        );
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let kind_of = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            parser.parse().unwrap_err().kind
+        };
+
+        assert_eq!(
+            kind_of("msg {a"),
+            ParseErrorKind::Lex("no closing } found".into())
+        );
+        assert_eq!(
+            kind_of("setflag"),
+            ParseErrorKind::UnexpectedEof {
+                context: "flag".into()
+            }
+        );
+        assert_eq!(
+            kind_of("jump 3"),
+            ParseErrorKind::UnexpectedToken {
+                expected: "a command".into(),
+                found: Token::Ident("jump".into()),
+            }
+        );
+        assert_eq!(
+            kind_of("tp @nowhere"),
+            ParseErrorKind::UnknownLocation {
+                name: "nowhere".into(),
+                suggestion: None,
+            }
+        );
+        assert_eq!(
+            kind_of("setflag seen"),
+            ParseErrorKind::BadFlagName("seen".into())
+        );
+        assert_eq!(
+            kind_of("setvar x 1"),
+            ParseErrorKind::BadVarName("x".into())
+        );
+        assert_eq!(kind_of("if flag_A then endif"), ParseErrorKind::EmptyBranch);
+        assert_eq!(kind_of("if () then end endif"), ParseErrorKind::EmptyParens);
+    }
+
+    #[test]
+    fn test_parse_scripts_error_carries_script_id() {
+        let layer = ScriptLayer {
+            objects: vec![
+                ScriptEntry {
+                    id: 3,
+                    script: "msg {fine};".into(),
+                    ..Default::default()
+                },
+                ScriptEntry {
+                    id: 7,
+                    script: "msg {a};\ntp @nowhere;".into(),
+                    x: 16.0,
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let errors = parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default())
+            .unwrap_err()
+            .0;
+        let [err] = &errors[..] else {
+            panic!("expected one error, got {errors:?}");
+        };
+        assert_eq!(err.script_id, Some(7));
+        assert_eq!(err.pos, Some(Span { line: 2, col: 4 }));
+        assert!(
+            matches!(&err.kind, ParseErrorKind::UnknownLocation { name, .. } if name == "nowhere")
+        );
+        assert_eq!(
+            err.to_string(),
+            "id 7 failed: 2:4: location nowhere not found!"
+        );
+    }
+
+    #[test]
+    fn test_unknown_location_suggestion() {
+        let mut locations = LocationTags::new();
+        locations.insert("spawn".into(), (1, 1));
+        locations.insert("shop".into(), (2, 2));
+        locations.insert("house_door".into(), (3, 3));
+
+        let error_for = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &locations);
+            parser.parse().unwrap_err()
+        };
+
+        let err = error_for("tp @spwan");
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::UnknownLocation {
+                name: "spwan".into(),
+                suggestion: Some("spawn".into()),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "1:4: location spwan not found! did you mean @spawn?"
+        );
+
+        assert!(
+            error_for("tmsg @house_dor {hi}")
+                .to_string()
+                .ends_with("did you mean @house_door?")
+        );
+        // nothing close enough, no suggestion
+        assert!(matches!(
+            error_for("tp @castle").kind,
+            ParseErrorKind::UnknownLocation {
+                suggestion: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("spawn", "spawn"), 0);
+        assert_eq!(levenshtein("spwan", "spawn"), 2);
+        assert_eq!(levenshtein("shop", "shops"), 1);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_parse_scripts_rejects_off_map_objects() {
+        let layer = ScriptLayer {
+            objects: vec![
+                ScriptEntry {
+                    id: 4,
+                    script: "msg {far};".into(),
+                    x: 300.0 * 16.0,
+                    y: 0.0,
+                    ..Default::default()
+                },
+                ScriptEntry {
+                    id: 5,
+                    script: "msg {above};".into(),
+                    x: 0.0,
+                    y: -4.0,
+                    ..Default::default()
+                },
+                ScriptEntry {
+                    id: 6,
+                    script: "msg {corner};".into(),
+                    x: 255.0 * 16.0,
+                    y: 255.0 * 16.0,
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let errors = parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default())
+            .unwrap_err()
+            .0;
+        let kinds: Vec<_> = errors.iter().map(|e| (e.script_id, &e.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (Some(4), &ParseErrorKind::OffMap { x: 300, y: 0 }),
+                (Some(5), &ParseErrorKind::OffMap { x: 0, y: -1 }),
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "id 4 failed: script at tile (300, 0) is outside the 256x256 map"
+        );
+    }
+
+    #[test]
+    fn test_parse_scripts_rejects_scripts_on_one_tile() {
+        let entry = |id: i32, x: f32, y: f32| ScriptEntry {
+            id,
+            script: format!("msg {{from {id}}};"),
+            x,
+            y,
+            ..Default::default()
+        };
+        // 9.5 is still tile 0, 16 is the next tile of the same chunk
+        let layer = ScriptLayer {
+            objects: vec![entry(1, 0.0, 0.0), entry(2, 16.0, 0.0), entry(3, 9.5, 0.0)],
+        };
+
+        let (parsed, errors) =
+            parse_scripts_keep_going(&layer, &HashMap::new(), &ChunkLayout::default());
+        let [err] = &errors[..] else {
+            panic!("expected one error, got {errors:?}");
+        };
+        assert_eq!(err.script_id, Some(3));
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::SameTile {
+                x: 0,
+                y: 0,
+                first: 1
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "id 3 failed: script at tile (0, 0) shares it with id 1"
+        );
+        // the first script on the tile is kept, and its text only
+        assert_eq!(parsed.chunks[0].len(), 2);
+        assert!(!parsed.texts.contains_key("from 3"));
+    }
+
+    #[test]
+    fn test_condition_flags_need_prefix() {
+        for src in ["if flag_ok then end endif", "if !flag_ok then end endif"] {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            assert!(parser.parse().is_ok(), "{src}");
+        }
+
+        for (src, name) in [
+            ("if foo then end endif", "foo"),
+            ("if notaflag then end endif", "notaflag"),
+            ("if !notaflag then end endif", "notaflag"),
+            ("if flag_a and seen then end endif", "seen"),
+        ] {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            let err = parser.parse().unwrap_err();
+            assert_eq!(
+                err.kind,
+                ParseErrorKind::BadConditionFlag(name.into()),
+                "{src}"
+            );
+            assert_eq!(
+                err.kind.to_string(),
+                format!("condition expects a flag_ identifier, got '{name}'")
+            );
+        }
+    }
+
+    #[test]
+    fn test_symbol_limit() {
+        let mut controller = Controller::new();
+        controller.limit = 3;
+        let mut parser = Parser::new(
+            "msg {a}; msg {b}; msg {a}; msg {c}; msg {d};",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+
+        // `a` is reused, so only `d` is one text too many
+        let err = parser.parse().unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::TooManySymbols {
+                what: "texts",
+                limit: 3
+            }
+        );
+        assert_eq!(err.pos, Some(Span { line: 1, col: 41 }));
+        assert_eq!(parser.controller.text.len(), 3);
+    }
+
+    #[test]
+    fn test_symbol_limit_while_merging_keeps_the_position() {
+        let mut merged = Controller::new();
+        merged.limit = 3;
+        for text in ["a", "b", "c"] {
+            merged.insert_text(text).unwrap();
+        }
+
+        // `a` is merged into the full table, `d` is the one too many
+        let mut local = Controller::new();
+        let mut parser = Parser::new("msg {a};\nwait 1; msg {d};", &mut local, &NO_LOCATIONS);
+        parser.parse().unwrap();
+        let err = merged.merge(local).err().unwrap();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::TooManySymbols {
+                what: "texts",
+                limit: 3
+            }
+        );
+        assert_eq!(err.pos, Some(Span { line: 2, col: 13 }));
+    }
+
+    #[test]
+    fn test_symbol_limit_default_covers_u16() {
+        let mut controller = Controller::new();
+        let mut flags = HashMap::new();
+        for i in 0..=u16::MAX {
+            flags.insert(format!("flag_{i}"), i);
+        }
+        controller.flags = flags;
+        assert_eq!(controller.insert_flag("flag_0"), Ok(0));
+        assert_eq!(
+            controller.insert_flag("flag_new"),
+            Err(ParseErrorKind::TooManySymbols {
+                what: "flags",
+                limit: 65536
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_scripts_reports_every_bad_script() {
+        let layer = ScriptLayer {
+            objects: vec![
+                ScriptEntry {
+                    id: 1,
+                    script: "setflag seen;".into(),
+                    ..Default::default()
+                },
+                ScriptEntry {
+                    id: 2,
+                    script: "setflag flag_ok; msg {ok};".into(),
+                    x: 16.0,
+                    ..Default::default()
+                },
+                ScriptEntry {
+                    id: 3,
+                    script: "setflag flag_lost; msg {lost}; wait;".into(),
+                    x: 32.0,
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let errors = parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default()).unwrap_err();
+        let ids: Vec<_> = errors.0.iter().map(|e| e.script_id).collect();
+        assert_eq!(ids, vec![Some(1), Some(3)]);
+        assert_eq!(errors.to_string().lines().count(), 2);
+
+        // keep going still compiles the script in between
+        let (parsed, errors) =
+            parse_scripts_keep_going(&layer, &HashMap::new(), &ChunkLayout::default());
+        assert_eq!(errors.len(), 2);
+        // and leaves out what the failing ones named before their error
+        assert_eq!(parsed.flags.keys().collect::<Vec<_>>(), ["flag_ok"]);
+        assert!(!parsed.texts.contains_key("lost"));
+        let bodies: Vec<_> = parsed.chunks.iter().flatten().map(|s| &s.body).collect();
+        assert_eq!(bodies.len(), 1);
+        assert!(matches!(
+            bodies[0][..],
+            [Cmd::SetFlag { .. }, Cmd::Msg { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_error_reports_position() {
+        let src = "msg {a};\nsetflag flag_A;\nsetflag oops;";
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+        let err = parser.parse().unwrap_err().to_string();
+        assert!(err.starts_with("3:9: "), "got: {err}");
+    }
+
+    #[test]
+    fn test_parse_if_multiple_commands() {
+        let test_cases = vec![
+            (
+                "if flag_X then setflag flag_A msg {hi} endif;",
+                Branch::Then(vec![
+                    Cmd::SetFlag {
+                        flag: Text {
+                            text: "flag_A".into(),
+                            index: 1,
+                        },
+                    },
+                    Cmd::Msg {
+                        text: Text {
+                            text: "hi".into(),
+                            index: 0,
+                        },
+                    },
+                ]),
+            ),
+            (
+                "if flag_X then setflag flag_A; msg {hi}; else unsetflag flag_A; tp 1 2; endif;",
+                Branch::ThenElse(
+                    vec![
+                        Cmd::SetFlag {
+                            flag: Text {
+                                text: "flag_A".into(),
+                                index: 1,
+                            },
+                        },
+                        Cmd::Msg {
+                            text: Text {
+                                text: "hi".into(),
+                                index: 0,
+                            },
+                        },
+                    ],
+                    vec![
+                        Cmd::UnsetFlag {
+                            flag: Text {
+                                text: "flag_A".into(),
+                                index: 1,
+                            },
+                        },
+                        Cmd::Tp {
+                            to: Location::Cords(1, 2),
+                        },
+                    ],
+                ),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(input, &mut controller, &NO_LOCATIONS);
+            let result = parser.parse_cmd();
+            assert_eq!(
+                result,
+                Ok(Cmd::If {
+                    condition: Condition::FlagSet(Text {
+                        text: "flag_X".into(),
+                        index: 0,
+                    }),
+                    branches: expected,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_branch_too_long_to_jump_over() {
+        let parse = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            parser.parse()
+        };
+        // `wait` is 3 bytes: 21845 of them are the longest body a u16 holds
+        let body = |n| "wait 1; ".repeat(n);
+
+        assert!(parse(&format!("if flag_a then {} endif", body(21845))).is_ok());
+        assert_eq!(
+            parse(&format!("if flag_a then {} endif", body(21846)))
+                .unwrap_err()
+                .kind,
+            ParseErrorKind::BlockTooLarge(65538)
+        );
+        assert_eq!(
+            parse(&format!("if flag_a then end; else {} endif", body(21846)))
+                .unwrap_err()
+                .kind,
+            ParseErrorKind::BlockTooLarge(65538)
+        );
+        assert_eq!(
+            parse(&format!(
+                "choice {{Pick}} [ {{Yes}} -> {} ] endchoice",
+                body(21846)
+            ))
+            .unwrap_err()
+            .kind,
+            ParseErrorKind::BlockTooLarge(65538)
+        );
+        // each side of the nested `if` fits, the `if` as a whole does not
+        assert_eq!(
+            parse(&format!(
+                "if flag_a then wait 1; else if flag_b then {} else {} endif;",
+                body(11000),
+                body(11000)
+            ))
+            .unwrap_err()
+            .kind,
+            ParseErrorKind::BlockTooLarge(66009)
+        );
+        assert_eq!(
+            ParseErrorKind::BlockTooLarge(65538).to_string(),
+            "branch is 65538 bytes, more than the 65535 a jump can skip"
+        );
+    }
+
+    #[test]
+    fn test_parse_else_if_chain() {
+        let flag = |text: &str, index| Text {
+            text: text.into(),
+            index,
+        };
+        let msg = |text: &str, index| Cmd::Msg {
+            text: Text {
+                text: text.into(),
+                index,
+            },
+        };
+
+        // two arms
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "if flag_A then msg {a} else if flag_B then msg {b} endif;",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![Cmd::If {
+                condition: Condition::FlagSet(flag("flag_A", 0)),
+                branches: Branch::ThenElse(
+                    vec![msg("a", 0)],
+                    vec![Cmd::If {
+                        condition: Condition::FlagSet(flag("flag_B", 1)),
+                        branches: Branch::Then(vec![msg("b", 1)]),
+                    }],
+                ),
+            }])
+        );
+
+        // three arms plus a final else, still only one `endif`
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "if flag_A then msg {a} else if flag_B then msg {b} else if !flag_C then msg {c} else msg {d} endif;",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![Cmd::If {
+                condition: Condition::FlagSet(flag("flag_A", 0)),
+                branches: Branch::ThenElse(
+                    vec![msg("a", 0)],
+                    vec![Cmd::If {
+                        condition: Condition::FlagSet(flag("flag_B", 1)),
+                        branches: Branch::ThenElse(
+                            vec![msg("b", 1)],
+                            vec![Cmd::If {
+                                condition: Condition::FlagClear(flag("flag_C", 2)),
+                                branches: Branch::ThenElse(vec![msg("c", 2)], vec![msg("d", 3)]),
+                            }],
+                        ),
+                    }],
+                ),
+            }])
+        );
+
+        // a second `endif` no longer belongs to the chain
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "if flag_A then msg {a} else if flag_B then msg {b} endif endif;",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_compound_conditions() {
+        let set = |text: &str, index| {
+            Condition::FlagSet(Text {
+                text: text.into(),
+                index,
+            })
+        };
+        let clear = |text: &str, index| {
+            Condition::FlagClear(Text {
+                text: text.into(),
+                index,
+            })
+        };
+        let and = |a, b| Condition::And(Box::new(a), Box::new(b));
+        let or = |a, b| Condition::Or(Box::new(a), Box::new(b));
+
+        let test_cases = vec![
+            (
+                "flag_A and !flag_B",
+                and(set("flag_A", 0), clear("flag_B", 1)),
+            ),
+            ("flag_A or flag_B", or(set("flag_A", 0), set("flag_B", 1))),
+            (
+                "flag_A or flag_B and flag_C",
+                or(set("flag_A", 0), and(set("flag_B", 1), set("flag_C", 2))),
+            ),
+            (
+                "flag_A and flag_B or flag_C",
+                or(and(set("flag_A", 0), set("flag_B", 1)), set("flag_C", 2)),
+            ),
+            (
+                "flag_A or flag_B or flag_C",
+                or(or(set("flag_A", 0), set("flag_B", 1)), set("flag_C", 2)),
+            ),
+        ];
+
+        for (input, expected) in test_cases {
+            let src = format!("if {input} then setflag flag_Z endif;");
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(&src, &mut controller, &NO_LOCATIONS);
+            match parser.parse_cmd() {
+                Ok(Cmd::If { condition, .. }) => assert_eq!(condition, expected, "{input}"),
+                other => panic!("{input}: unexpected {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let parse = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            parser.parse().map(|_| ())
+        };
+        let parens = |n| {
+            format!(
+                "if {}flag_a{} then end endif;",
+                "(".repeat(n),
+                ")".repeat(n)
+            )
+        };
+        let ifs = |n| format!("{}end{}", "if flag_a then ".repeat(n), " endif".repeat(n));
+        let chain = |n| {
+            format!(
+                "if flag_a then end {}endif",
+                "else if flag_a then end ".repeat(n)
+            )
+        };
+
+        // the outer `if` is a level of its own
+        assert_eq!(parse(&parens(63)), Ok(()));
+        assert_eq!(parse(&ifs(64)), Ok(()));
+        assert_eq!(parse(&chain(63)), Ok(()));
+        for src in [parens(64), ifs(65), chain(64), parens(10_000), ifs(20_000)] {
+            assert_eq!(parse(&src).unwrap_err().kind, ParseErrorKind::TooDeep(64));
+        }
+        assert_eq!(
+            parse(&parens(64)).unwrap_err().to_string(),
+            "1:67: nested more than 64 levels deep"
+        );
+    }
+
+    #[test]
+    fn test_parse_grouped_conditions() {
+        let condition_of = |cond: &str| {
+            let src = format!("if {cond} then setflag flag_Z endif;");
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(&src, &mut controller, &NO_LOCATIONS);
+            match parser.parse_cmd() {
+                Ok(Cmd::If { condition, .. }) => Ok(condition),
+                Ok(other) => panic!("{cond}: unexpected {other:?}"),
+                Err(e) => Err(e.to_string()),
+            }
+        };
+
+        // grouping overrides the default precedence…
+        let grouped = condition_of("(flag_A or flag_B) and flag_C").unwrap();
+        let ungrouped = condition_of("flag_A or flag_B and flag_C").unwrap();
+        assert_ne!(grouped, ungrouped);
+        assert!(matches!(grouped, Condition::And(_, _)));
+        assert!(matches!(ungrouped, Condition::Or(_, _)));
+
+        // …and redundant parentheses change nothing
+        assert_eq!(
+            condition_of("flag_A or (flag_B and flag_C)").unwrap(),
+            ungrouped
+        );
+        assert_eq!(
+            condition_of("((flag_A))").unwrap(),
+            condition_of("flag_A").unwrap()
+        );
+
+        let err = condition_of("(flag_A or flag_B and flag_C").unwrap_err();
+        assert_eq!(
+            err,
+            "1:33: expected ')' to close '(' opened at 1:4, got Ident(\"then\")"
+        );
+        let err = condition_of("() and flag_A").unwrap_err();
+        assert_eq!(err, "1:5: empty parentheses in condition");
+        let err = condition_of("flag_A) and flag_B").unwrap_err();
+        assert!(err.starts_with("1:10: expected 'then'"), "got: {err}");
+    }
+
+    #[test]
+    fn test_parse_has_item() {
+        let condition_of = |cond: &str| {
+            let src = format!("if {cond} then msg {{hi}} endif;");
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(&src, &mut controller, &NO_LOCATIONS);
+            match parser.parse_cmd() {
+                Ok(Cmd::If { condition, .. }) => condition,
+                other => panic!("{cond}: unexpected {other:?}"),
+            }
+        };
+
+        assert_eq!(
+            condition_of("has_item 5"),
+            Condition::HasItem { item: 5, count: 1 }
+        );
+        assert_eq!(
+            condition_of("has_item 5 3"),
+            Condition::HasItem { item: 5, count: 3 }
+        );
+        assert_eq!(
+            condition_of("has_item 5 and !flag_A"),
+            Condition::And(
+                Box::new(Condition::HasItem { item: 5, count: 1 }),
+                Box::new(Condition::FlagClear(Text {
+                    text: "flag_A".into(),
+                    index: 0,
+                })),
+            )
+        );
+        assert_eq!(
+            condition_of("flag_A or has_item 2 10"),
+            Condition::Or(
+                Box::new(Condition::FlagSet(Text {
+                    text: "flag_A".into(),
+                    index: 0,
+                })),
+                Box::new(Condition::HasItem { item: 2, count: 10 }),
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_vars() {
+        let var = |index| Text {
+            text: "var_x".into(),
+            index,
+        };
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "setvar var_x 3; addvar var_x 1;",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![
+                Cmd::SetVar {
+                    var: var(0),
+                    value: 3
+                },
+                Cmd::AddVar {
+                    var: var(0),
+                    value: 1
+                },
+            ])
+        );
+
+        for (op_src, op) in [
+            ("==", CmpOp::Eq),
+            ("!=", CmpOp::Ne),
+            ("<", CmpOp::Lt),
+            (">", CmpOp::Gt),
+        ] {
+            let src = format!("if var_x {op_src} 5 then msg {{hi}} endif;");
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(&src, &mut controller, &NO_LOCATIONS);
+            match parser.parse_cmd() {
+                Ok(Cmd::If { condition, .. }) => assert_eq!(
+                    condition,
+                    Condition::VarCmp {
+                        var: var(0),
+                        op,
+                        value: 5
+                    }
+                ),
+                other => panic!("{src}: unexpected {other:?}"),
+            }
+        }
+
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("setvar flag_x 3;", &mut controller, &NO_LOCATIONS);
+        assert!(parser.parse().is_err());
+        let mut controller = Controller::new();
+        let mut parser = Parser::new("if var_x 5 then end endif;", &mut controller, &NO_LOCATIONS);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn test_collects_vars() {
+        let layer = ScriptLayer {
+            objects: vec![ScriptEntry {
+                id: 0,
+                script: "addvar var_talks 1; if var_talks > 3 then setvar var_mood 0 endif;".into(),
+                x: 0.0,
+                y: 0.0,
+                ..Default::default()
+            }],
+        };
+        let parsed = parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default()).unwrap();
+        assert_eq!(parsed.vars.get("var_talks"), Some(&0));
+        assert_eq!(parsed.vars.get("var_mood"), Some(&1));
+        assert!(parsed.flags.is_empty());
+    }
+
     #[test]
     fn test_collects_tags_and_flags() {
         let script = "if flag_A then setflag flag_B else unsetflag flag_C endif;";
@@ -568,12 +2793,14 @@ mod tests {
             script: script.to_string(),
             x: 0 as f32,
             y: 0 as f32,
+            ..Default::default()
         };
 
         let script_layer = ScriptLayer {
             objects: vec![script_entry],
         };
-        let parsed_scripts = parse_scripts(&script_layer, &HashMap::new()).unwrap();
+        let parsed_scripts =
+            parse_scripts(&script_layer, &HashMap::new(), &ChunkLayout::default()).unwrap();
         assert_eq!(parsed_scripts.tags.len(), 0);
         assert_eq!(parsed_scripts.flags.len(), 3);
     }
@@ -599,23 +2826,27 @@ mod tests {
                 script: "msg {a};".into(),
                 x: 0.0,
                 y: 0.0, //  chunk 0
+                ..Default::default()
             },
             ScriptEntry {
                 id: 0,
                 script: "msg {b};".into(),
-                x: 8.0 * 16 as f32,
+                x: 8.0 * 16.0,
                 y: 0.0, //  chunk 1
+                ..Default::default()
             },
             ScriptEntry {
                 id: 0,
                 script: "msg {c};".into(),
                 x: 0.0,
-                y: 4.0 * 16 as f32, //  first row below → chunk 32
+                y: 4.0 * 16.0, //  first row below → chunk 32
+                ..Default::default()
             },
         ];
 
         let layer = ScriptLayer { objects: scripts };
-        let parsed = parse_scripts(&layer, &HashMap::new()).expect("scripts parsed");
+        let parsed = parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default())
+            .expect("scripts parsed");
 
         // chunk 0 must contain (0,0)
         assert_eq!(parsed.chunks[0].len(), 1, "chunk 0 scripts");
@@ -633,4 +2864,404 @@ mod tests {
         assert_eq!(parsed.chunks[idx_row1_col0][0].x, 0);
         assert_eq!(parsed.chunks[idx_row1_col0][0].y, 4);
     }
+
+    #[test]
+    fn test_chunk_grouping_custom_layout() {
+        use crate::model::ScriptEntry;
+
+        // 16×8 chunks → 16 columns; (20,10) is column 1, row 1 → chunk 17
+        let entry = |x: f32, y: f32| ScriptEntry {
+            script: "msg {a};".into(),
+            x: x * 16.0,
+            y: y * 16.0,
+            ..Default::default()
+        };
+        let layer = ScriptLayer {
+            objects: vec![entry(15.0, 7.0), entry(20.0, 10.0), entry(255.0, 255.0)],
+        };
+        let layout = ChunkLayout::new(16, 8, 128).unwrap();
+        let parsed = parse_scripts(&layer, &HashMap::new(), &layout).expect("scripts parsed");
+
+        assert_eq!(parsed.chunks.len(), 512);
+        let placed: Vec<usize> = (0..512).filter(|&i| !parsed.chunks[i].is_empty()).collect();
+        assert_eq!(placed, vec![0, 17, 511]);
+    }
+
+    #[test]
+    fn test_layout_must_divide_the_map() {
+        assert!(ChunkLayout::new(16, 8, 128).is_ok());
+        assert!(ChunkLayout::new(7, 4, 128).is_err());
+        assert!(ChunkLayout::new(8, 0, 128).is_err());
+        assert!(ChunkLayout::new(8, 4, 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_playsound() {
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "playsound 4; playsound 0x10;",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        assert_eq!(
+            parser.parse(),
+            Ok(vec![Cmd::PlaySound { id: 4 }, Cmd::PlaySound { id: 16 }])
+        );
+
+        let parse = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            parser.parse().unwrap_err().to_string()
+        };
+        assert_eq!(
+            parse("playsound;"),
+            "1:10: expected a sound id for playsound, got Semicolon"
+        );
+        assert_eq!(
+            parse("playsound"),
+            "1:10: unexpected end of input while parsing playsound"
+        );
+        assert_eq!(
+            parse("playsound 70000;"),
+            "1:11: value too large for uint16: 70000"
+        );
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        let mut controller = Controller::new();
+        let mut parser = Parser::new(
+            "choice {Pick one} [ {Yes} -> setflag flag_a ; {No} -> msg {ok} ] endchoice;",
+            &mut controller,
+            &NO_LOCATIONS,
+        );
+        let cmd = parser.parse_cmd().unwrap();
+        let text = |text: &str, index| Text {
+            text: text.into(),
+            index,
+        };
+        assert_eq!(
+            cmd,
+            Cmd::Choice {
+                prompt: text("Pick one", 0),
+                options: vec![
+                    (
+                        text("Yes", 1),
+                        vec![Cmd::SetFlag {
+                            flag: text("flag_a", 0)
+                        }]
+                    ),
+                    (
+                        text("No", 2),
+                        vec![Cmd::Msg {
+                            text: text("ok", 3)
+                        }]
+                    ),
+                ],
+            }
+        );
+        #[rustfmt::skip]
+        assert_eq!(
+            cmd.to_bytes(),
+            vec![
+                Opcode::Choice as u8, 0, 0, 2,
+                1, 0, 3, 0, Opcode::SetFlag as u8, 0, 0,
+                2, 0, 3, 0, Opcode::Msg as u8, 3, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_choice_errors() {
+        let parse = |src: &str| {
+            let mut controller = Controller::new();
+            let mut parser = Parser::new(src, &mut controller, &NO_LOCATIONS);
+            parser.parse().unwrap_err().to_string()
+        };
+        assert_eq!(
+            parse("choice {Pick} [ ] endchoice"),
+            "1:17: expected a choice option, got RBracket"
+        );
+        assert_eq!(
+            parse("choice {Pick} [ {Yes} end ] endchoice"),
+            "1:23: expected '->' after the option, got Ident(\"end\")"
+        );
+        assert_eq!(
+            parse("choice {Pick} [ {Yes} -> end ]"),
+            "1:31: unexpected end of input while parsing choice"
+        );
+        assert_eq!(
+            parse("choice {Pick} [ {Yes} -> end"),
+            "1:29: unexpected end of input while parsing choice option"
+        );
+    }
+
+    /// The one-by-one parse the parallel one must agree with.
+    fn parse_scripts_sequential(
+        scripts: &ScriptLayer,
+        loc_tags: &LocationTags,
+        layout: &ChunkLayout,
+    ) -> (ParsedScripts, Vec<ParseError>) {
+        let mut chunks: Vec<Vec<Script>> = vec![Vec::new(); layout.total()];
+        let mut errors = Vec::new();
+        let mut controller = Controller::new();
+        let subscripts = subscript_names(scripts);
+        let mut tiles = HashMap::new();
+        let mut empty = Vec::new();
+        let mut in_map_order: Vec<_> = scripts.objects.iter().enumerate().collect();
+        in_map_order.sort_by_key(|(_, script)| map_order(script));
+
+        for (pos, script) in in_map_order {
+            let (x_i, y_i) = tile_of(script);
+            if !(0..MAP_W).contains(&x_i) || !(0..MAP_H).contains(&y_i) {
+                let kind = ParseErrorKind::OffMap { x: x_i, y: y_i };
+                errors.push((pos, ParseError::unplaced(kind).in_script(script.id)));
+                continue;
+            }
+            if let Err(kind) = claim_tile(&mut tiles, script.id, x_i, y_i) {
+                errors.push((pos, ParseError::unplaced(kind).in_script(script.id)));
+                continue;
+            }
+            let before = (
+                controller.tags.clone(),
+                controller.flags.clone(),
+                controller.text.clone(),
+                controller.vars.clone(),
+                controller.scripts.clone(),
+            );
+            let mut parser =
+                Parser::new(&script.script, &mut controller, loc_tags).with_subscripts(&subscripts);
+            let parse_res = parser.parse();
+            let meta = std::mem::take(&mut parser.meta);
+            if parse_res.is_err() {
+                (
+                    controller.tags,
+                    controller.flags,
+                    controller.text,
+                    controller.vars,
+                    controller.scripts,
+                ) = before;
+            }
+            match parse_res {
+                Ok(body) if !body.is_empty() => chunks[layout.index(x_i, y_i)].push(Script {
+                    id: script.id,
+                    name: script.name.clone(),
+                    script: script.script.clone(),
+                    meta,
+                    body,
+                    x: x_i,
+                    y: y_i,
+                }),
+                Ok(_) => empty.push(script.id),
+                Err(e) => errors.push((pos, e.in_script(script.id))),
+            }
+        }
+
+        errors.sort_by_key(|&(pos, _)| pos);
+        let errors = errors.into_iter().map(|(_, e)| e).collect();
+
+        let parsed = ParsedScripts {
+            chunks,
+            tags: controller.tags,
+            flags: controller.flags,
+            texts: controller.text,
+            vars: controller.vars,
+            scripts: controller.scripts,
+            empty,
+        };
+        (parsed, errors)
+    }
+
+    #[test]
+    fn test_parse_scripts_reports_empty_scripts() {
+        let entry = |id: i32, script: &str| ScriptEntry {
+            id,
+            script: script.into(),
+            x: id as f32 * 16.0,
+            ..Default::default()
+        };
+        let layer = ScriptLayer {
+            objects: vec![
+                entry(1, "msg {hi};"),
+                entry(2, "/* msg {typo} */\n/* wait 1; */"),
+                entry(3, " ;\n"),
+            ],
+        };
+
+        let parsed = parse_scripts(&layer, &HashMap::new(), &ChunkLayout::default()).unwrap();
+        assert_eq!(parsed.empty, vec![2, 3]);
+        assert_eq!(parsed.chunks[0].len(), 1);
+    }
+
+    #[test]
+    fn test_symbol_indices_ignore_object_order() {
+        let locations: LocationTags = (0..5).map(|i| (format!("loc{i}"), (i, i))).collect();
+        let objects: Vec<ScriptEntry> = (0..40)
+            .map(|i| ScriptEntry {
+                id: i,
+                script: format!(
+                    "setflag flag_{}; if flag_{} then tmsg @@loc{} {{hi {}}} endif;",
+                    i % 7,
+                    (i * 3) % 11,
+                    i % 5,
+                    i % 13
+                ),
+                x: ((i * 7) % 40) as f32 * 16.0,
+                y: (i % 3) as f32 * 16.0,
+                ..Default::default()
+            })
+            .collect();
+        let layout = ChunkLayout::default();
+        let parse = |objects: Vec<ScriptEntry>| {
+            parse_scripts(&ScriptLayer { objects }, &locations, &layout).unwrap()
+        };
+
+        let first = parse(objects.clone());
+        let mut shuffled = objects.clone();
+        shuffled.reverse();
+        shuffled.rotate_left(17);
+        for objects in [objects.iter().rev().cloned().collect(), shuffled] {
+            let other = parse(objects);
+            assert_eq!(other.flags, first.flags);
+            assert_eq!(other.tags, first.tags);
+            assert_eq!(other.texts, first.texts);
+            let bodies = |p: &ParsedScripts| -> Vec<Vec<Cmd>> {
+                p.chunks.iter().flatten().map(|s| s.body.clone()).collect()
+            };
+            assert_eq!(bodies(&other), bodies(&first));
+        }
+        // numbered by first use in map order: tile (0, 0) holds script 0
+        assert_eq!(first.flags["flag_0"], 0);
+        assert_eq!(first.texts["hi 0"], 0);
+    }
+
+    #[test]
+    fn test_parallel_parse_matches_sequential() {
+        let locations: LocationTags = (0..20).map(|i| (format!("loc{i}"), (i, i))).collect();
+        // a few thousand scripts that share symbols in shifting orders,
+        // with the odd broken or off-map one in between
+        let objects: Vec<ScriptEntry> = (0..3000)
+            .map(|i| {
+                let script = match i % 7 {
+                    0 => format!("msg {{hello {}}}; setflag flag_{};", i % 50, i % 13),
+                    1 => format!(
+                        "if flag_{} then tmsg @@loc{} {{hi {}}} endif;",
+                        i % 11,
+                        i % 20,
+                        i % 31
+                    ),
+                    2 => format!(
+                        "setvar var_{} 3; addvar var_{} 1; call @sub{};",
+                        i % 9,
+                        i % 5,
+                        (i * 3) % 20
+                    ),
+                    3 => format!(
+                        "if !flag_{} and var_{} > 2 then tp @loc{} else msg {{no {}}} endif;",
+                        i % 17,
+                        i % 9,
+                        i % 20,
+                        i % 23
+                    ),
+                    4 => format!(
+                        "tpif @loc{} @@loc{}; readflag flag_{};",
+                        i % 20,
+                        (i + 3) % 20,
+                        i % 19
+                    ),
+                    5 => format!("setflag flag_new{i}; msg {{oops {i}}}; tp @nowhere;"),
+                    _ => String::new(),
+                };
+                ScriptEntry {
+                    id: i,
+                    script,
+                    x: ((i * 37) % 300) as f32 * 16.0,
+                    y: ((i * 11) % 256) as f32 * 16.0,
+                    name: (i < 20).then(|| format!("sub{i}")),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let layer = ScriptLayer { objects };
+        let layout = ChunkLayout::default();
+
+        let (parallel, parallel_errors) = parse_scripts_keep_going(&layer, &locations, &layout);
+        let (sequential, sequential_errors) = parse_scripts_sequential(&layer, &locations, &layout);
+
+        assert!(!parallel.texts.is_empty() && !parallel_errors.is_empty());
+        assert_eq!(parallel.tags, sequential.tags);
+        assert_eq!(parallel.flags, sequential.flags);
+        assert_eq!(parallel.texts, sequential.texts);
+        assert_eq!(parallel.vars, sequential.vars);
+        assert_eq!(parallel.scripts, sequential.scripts);
+        assert_eq!(parallel.empty, sequential.empty);
+        assert_eq!(parallel_errors, sequential_errors);
+        let bodies = |p: &ParsedScripts| -> Vec<Vec<(i32, i32, Vec<Cmd>)>> {
+            p.chunks
+                .iter()
+                .map(|c| c.iter().map(|s| (s.x, s.y, s.body.clone())).collect())
+                .collect()
+        };
+        assert_eq!(bodies(&parallel), bodies(&sequential));
+    }
+
+    #[test]
+    fn test_symbol_tables_across_scripts() {
+        let mut locations = LocationTags::new();
+        locations.insert("home".into(), (1, 1));
+        locations.insert("shop".into(), (2, 2));
+        let entry = |x: f32, script: &str| ScriptEntry {
+            script: script.into(),
+            x,
+            ..Default::default()
+        };
+        let layer = ScriptLayer {
+            objects: vec![
+                entry(0.0, "setflag flag_b; tmsg @@shop {hi};"),
+                entry(16.0, "if flag_a then msg {bye} endif; tp @@home;"),
+                entry(32.0, "setflag flag_b; setvar var_n 1; msg {hi};"),
+            ],
+        };
+
+        let parsed = parse_scripts(&layer, &locations, &ChunkLayout::default()).unwrap();
+
+        let table = |names: &[&str]| -> HashMap<String, u16> {
+            (0..).zip(names).map(|(i, n)| (n.to_string(), i)).collect()
+        };
+        assert_eq!(parsed.tags, table(&["shop", "home"]));
+        assert_eq!(parsed.flags, table(&["flag_b", "flag_a"]));
+        assert_eq!(parsed.texts, table(&["hi", "bye"]));
+        assert_eq!(parsed.vars, table(&["var_n"]));
+    }
+
+    #[test]
+    fn test_identical_messages_share_text() {
+        let entry = |x: f32| ScriptEntry {
+            script: "msg {hi}; tmsg 1 1 {hi}; msg {bye};".into(),
+            x,
+            ..Default::default()
+        };
+        let layer = ScriptLayer {
+            objects: vec![entry(0.0), entry(16.0)],
+        };
+        let parsed = parse_scripts(&layer, &NO_LOCATIONS, &ChunkLayout::default()).unwrap();
+
+        let texts: Vec<&Text> = parsed.chunks[0]
+            .iter()
+            .flat_map(|s| &s.body)
+            .map(|cmd| match cmd {
+                Cmd::Msg { text } | Cmd::TMsg { text, .. } => text,
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        let [hi, hi_at, bye, hi_2, hi_at_2, _] = texts[..] else {
+            panic!("expected six messages, got {texts:?}");
+        };
+        // within a script and across scripts parsed on other threads
+        for other in [hi_at, hi_2, hi_at_2] {
+            assert_eq!(other, hi);
+            assert!(Arc::ptr_eq(&other.text, &hi.text));
+        }
+        assert!(!Arc::ptr_eq(&bye.text, &hi.text));
+    }
 }
@@ -0,0 +1,120 @@
+//! Symbols a project defines but never uses, likely typos or dead content.
+
+use crate::model::ParsedScripts;
+use crate::processor::ast::{Branch, Cmd, Condition};
+use crate::processor::locations_parser::LocationTags;
+use std::collections::HashSet;
+
+/// One warning per flag that is set but never read and per location no
+/// script refers to, sorted by name.
+pub fn unused_symbols(parsed: &ParsedScripts, locations: &LocationTags) -> Vec<String> {
+    let mut read = HashSet::new();
+    for script in parsed.chunks.iter().flatten() {
+        for cmd in &script.body {
+            flag_reads(cmd, &mut read);
+        }
+    }
+
+    let mut flags: Vec<&str> = parsed
+        .flags
+        .keys()
+        .map(String::as_str)
+        .filter(|flag| !read.contains(flag))
+        .collect();
+    flags.sort_unstable();
+    let mut unreferenced: Vec<&str> = locations
+        .keys()
+        .map(String::as_str)
+        .filter(|name| !parsed.tags.contains_key(*name))
+        .collect();
+    unreferenced.sort_unstable();
+
+    let flags = flags
+        .into_iter()
+        .map(|flag| format!("flag {flag} is set but never read"));
+    let locations = unreferenced
+        .into_iter()
+        .map(|name| format!("location {name} is never referenced"));
+    flags.chain(locations).collect()
+}
+
+/// Collect every flag `cmd` tests, nested commands included.
+fn flag_reads<'a>(cmd: &'a Cmd, read: &mut HashSet<&'a str>) {
+    match cmd {
+        Cmd::ReadFlag { flag } => {
+            read.insert(&flag.text);
+        }
+        Cmd::If {
+            condition,
+            branches,
+        } => {
+            condition_reads(condition, read);
+            let (then_cmds, else_cmds) = match branches {
+                Branch::ThenElse(then_cmds, else_cmds) => (then_cmds, &else_cmds[..]),
+                Branch::Then(cmds) => (cmds, &[][..]),
+            };
+            for cmd in then_cmds.iter().chain(else_cmds) {
+                flag_reads(cmd, read);
+            }
+        }
+        Cmd::Choice { options, .. } => {
+            for cmd in options.iter().flat_map(|(_, cmds)| cmds) {
+                flag_reads(cmd, read);
+            }
+        }
+        Cmd::Random { then, .. } => flag_reads(then, read),
+        _ => {}
+    }
+}
+
+fn condition_reads<'a>(condition: &'a Condition, read: &mut HashSet<&'a str>) {
+    match condition {
+        Condition::FlagSet(flag) | Condition::FlagClear(flag) => {
+            read.insert(&flag.text);
+        }
+        Condition::And(lhs, rhs) | Condition::Or(lhs, rhs) => {
+            condition_reads(lhs, read);
+            condition_reads(rhs, read);
+        }
+        Condition::HasItem { .. } | Condition::VarCmp { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{ChunkLayout, ScriptEntry, ScriptLayer};
+    use crate::processor::script_parser::parse_scripts;
+
+    #[test]
+    fn test_reports_unused_flags_and_locations() {
+        let mut locations = LocationTags::new();
+        locations.insert("home".into(), (1, 1));
+        locations.insert("shop".into(), (2, 2));
+        locations.insert("cave".into(), (3, 3));
+        let entry = |x: f32, script: &str| ScriptEntry {
+            script: script.into(),
+            x,
+            ..Default::default()
+        };
+        let layer = ScriptLayer {
+            objects: vec![
+                entry(0.0, "setflag flag_met; setflag flag_typo; tp @home;"),
+                entry(16.0, "if flag_met then tmsg @@shop {hi} endif;"),
+                entry(
+                    32.0,
+                    "random 10 then if !flag_rare then unsetflag flag_rare endif;",
+                ),
+            ],
+        };
+        let parsed = parse_scripts(&layer, &locations, &ChunkLayout::default()).unwrap();
+
+        assert_eq!(
+            unused_symbols(&parsed, &locations),
+            vec![
+                "flag flag_typo is set but never read",
+                "location cave is never referenced",
+            ]
+        );
+    }
+}
@@ -1,50 +1,369 @@
 //! Dump raw VM bytecode blob (stub).
 
-use crate::model::ProcessedProject;
+use crate::checksum::crc32;
+use crate::model::{Endianness, ProcessedProject};
+use crate::processor::blob::ScriptBlob;
+use crate::writer::{by_index, file_name};
 use std::fs::File;
 use std::io::{self, BufWriter, Write};
 use std::path::Path;
 
-pub fn emit(project: &ProcessedProject, out_dir: &Path) -> io::Result<()> {
-    scripts(project, out_dir)?;
-    map(project, out_dir)?;
+/// First bytes of `scripts.bin`.
+pub const MAGIC: &[u8; 4] = b"CGVM";
+/// Layout version of the binary artifacts; bump it, and the one
+/// `docs/binary_format.md` describes, whenever a layout changes.
+pub const FORMAT_VERSION: u16 = 5;
+/// Size of the `scripts.bin` header. The chunk → blob table follows it,
+/// then the call table of `(chunk, offset)` pairs, then the blob slots:
+/// blob `b` starts at
+/// `HEADER_LEN + 2 * chunk_count + 4 * script_count + b * byte_limit`.
+pub const HEADER_LEN: usize = 22;
+/// Header flag: `map.bin` is run-length encoded.
+pub const FLAG_MAP_RLE: u16 = 1 << 0;
+/// Header flag: every artifact is big-endian.
+pub const FLAG_BIG_ENDIAN: u16 = 1 << 1;
+/// Header flag: `scripts.bin` and `map.bin` end in a little-endian `u32`
+/// CRC-32 (the gzip/zlib one) of all bytes before it, whatever the
+/// `--endianness`, so flashing tools can check it without the header.
+pub const FLAG_CRC32: u16 = 1 << 2;
+
+/// How the binary artifacts are laid out, filled in from the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Run-length encode `map.bin`.
+    pub compress_map: bool,
+    /// End `scripts.bin` and `map.bin` in a CRC-32.
+    pub checksum: bool,
+    /// Put in front of every file name, see `writer::file_name`.
+    pub prefix: String,
+}
+
+pub fn emit(project: &ProcessedProject, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    scripts(project, out_dir, opts)?;
+    offsets(project, out_dir, opts)?;
+    texts(project, out_dir, opts)?;
+    map(project, out_dir, opts)?;
     Ok(())
 }
 
-fn scripts(project: &ProcessedProject, out_dir: &Path) -> io::Result<()> {
-    let path = out_dir.join("scripts.bin");
-    let file = File::create(&path)?;
-    let mut writer = BufWriter::new(file);
+fn scripts(project: &ProcessedProject, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    write_header(project, opts, &mut bytes)?;
+    for &blob in &project.blob.index {
+        bytes.extend_from_slice(&project.endianness.u16_bytes(u16_field(blob, "blob index")?));
+    }
+    write_calls(&project.blob.calls, project.endianness, &mut bytes)?;
+    write_chunks(&project.blob.blob, project.layout.byte_limit, &mut bytes)?;
+    let path = out_dir.join(file_name(&opts.prefix, "scripts.bin"));
+    write_checked(&path, bytes, opts)
+}
 
-    for blob_chunk in &project.blob.blob {
-        // Write the actual blob data
-        writer.write_all(&blob_chunk.blob)?;
+/// Write `bytes` to `path`, with `--checksum` followed by their CRC-32,
+/// little-endian like gzip's.
+fn write_checked(path: &Path, mut bytes: Vec<u8>, opts: &Options) -> io::Result<()> {
+    if opts.checksum {
+        bytes.extend_from_slice(&crc32(&bytes).to_le_bytes());
+    }
+    std::fs::write(path, bytes)
+}
 
-        // Calculate padding needed for 128-byte alignment
-        let padding = 128 - (blob_chunk.blob.len() % 128);
-        if padding < 128 {
-            // Write all padding bytes at once instead of one by one
-            let padding_bytes = vec![0u8; padding];
-            writer.write_all(&padding_bytes)?;
+/// `n` as a `u16` header or table field.
+fn u16_field(n: usize, what: &str) -> io::Result<u16> {
+    u16::try_from(n).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{what} {n} does not fit a u16 field"),
+        )
+    })
+}
+
+/// For every `call` index the chunk the script sits in and its start,
+/// relative to that chunk's blob; `call N` runs entry `N`.
+fn write_calls(calls: &[(usize, u16)], order: Endianness, out: &mut impl Write) -> io::Result<()> {
+    for &(chunk, offset) in calls {
+        out.write_all(&order.u16_bytes(u16_field(chunk, "chunk index")?))?;
+        out.write_all(&order.u16_bytes(offset))?;
+    }
+    Ok(())
+}
+
+/// Every blob in a slot of exactly `stride` bytes, zero padded, so the VM
+/// finds blob `i` at `i * stride` – empty ones included.
+fn write_chunks(chunks: &[ScriptBlob], stride: usize, out: &mut impl Write) -> io::Result<()> {
+    for (i, chunk) in chunks.iter().enumerate() {
+        if chunk.blob.len() > stride {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "blob {i} is {} bytes, more than its {stride}-byte slot",
+                    chunk.blob.len()
+                ),
+            ));
         }
+        out.write_all(&chunk.blob)?;
+        out.write_all(&vec![0u8; stride - chunk.blob.len()])?;
     }
+    Ok(())
+}
 
-    writer.flush()?;
+/// `scripts.bin` header, all fields in the project's byte order (the
+/// version reads back wrong in the other one):
+///
+/// | bytes | field                  |
+/// |-------|------------------------|
+/// | 0‥4   | magic `CGVM`           |
+/// | 4‥6   | format version         |
+/// | 6‥8   | chunk count            |
+/// | 8‥10  | blob count             |
+/// | 10‥12 | bytes per blob slot    |
+/// | 12‥14 | flag count             |
+/// | 14‥16 | location count         |
+/// | 16‥18 | text count             |
+/// | 18‥20 | called script count    |
+/// | 20‥22 | flags (`FLAG_*`)       |
+fn write_header(
+    project: &ProcessedProject,
+    opts: &Options,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let order = project.endianness;
+    out.write_all(MAGIC)?;
+    out.write_all(&order.u16_bytes(FORMAT_VERSION))?;
+    for (count, what) in [
+        (project.blob.index.len(), "chunk count"),
+        (project.blob.blob.len(), "blob count"),
+        (project.layout.byte_limit, "chunk byte limit"),
+        (project.flags.len(), "flag count"),
+        (project.locations.len(), "location count"),
+        (project.texts.len(), "text count"),
+        (project.blob.calls.len(), "script count"),
+    ] {
+        out.write_all(&order.u16_bytes(u16_field(count, what)?))?;
+    }
+    let mut flags = 0;
+    if opts.compress_map {
+        flags |= FLAG_MAP_RLE;
+    }
+    if order == Endianness::Big {
+        flags |= FLAG_BIG_ENDIAN;
+    }
+    if opts.checksum {
+        flags |= FLAG_CRC32;
+    }
+    out.write_all(&order.u16_bytes(flags))?;
     Ok(())
 }
 
-fn map(project: &ProcessedProject, out_dir: &Path) -> io::Result<()> {
-    let path = out_dir.join("map.bin");
+/// `offsets.bin`: for every blob, a `u16` script count followed by that
+/// many `u16` script starts, relative to the blob's slot in `scripts.bin`.
+fn offsets(project: &ProcessedProject, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let path = out_dir.join(file_name(&opts.prefix, "offsets.bin"));
     let file = File::create(&path)?;
     let mut writer = BufWriter::new(file);
+    write_offsets(&project.blob.blob, project.endianness, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
 
-    for chunk in &project.map {
-        // Write the actual blob data
-        for &value in chunk {
-            writer.write_all(&value.to_be_bytes())?;
+fn write_offsets(chunks: &[ScriptBlob], order: Endianness, out: &mut impl Write) -> io::Result<()> {
+    for chunk in chunks {
+        out.write_all(&order.u16_bytes(chunk.offsets.len() as u16))?;
+        for &offset in &chunk.offsets {
+            out.write_all(&order.u16_bytes(offset))?;
         }
     }
+    Ok(())
+}
 
+/// `texts.bin`: every string in index order as a `u16` byte length
+/// followed by its UTF-8 bytes.
+fn texts(project: &ProcessedProject, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let path = out_dir.join(file_name(&opts.prefix, "texts.bin"));
+    let file = File::create(&path)?;
+    let mut writer = BufWriter::new(file);
+    let texts: Vec<&str> = by_index(&project.texts)
+        .into_iter()
+        .map(|(s, _)| s)
+        .collect();
+    write_texts(&texts, project.endianness, &mut writer)?;
     writer.flush()?;
     Ok(())
 }
+
+fn write_texts(texts: &[&str], order: Endianness, out: &mut impl Write) -> io::Result<()> {
+    for text in texts {
+        let len = u16::try_from(text.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("text of {} bytes does not fit a u16 length", text.len()),
+            )
+        })?;
+        out.write_all(&order.u16_bytes(len))?;
+        out.write_all(text.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// `map.bin`: every chunk's tiles in chunk order as `u16`s, empty cells
+/// `EMPTY_TILE` once the project lists tilesets, or with `--compress-map`
+/// the same tile sequence as RLE pairs; with `--checksum` a CRC-32 follows.
+fn map(project: &ProcessedProject, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let order = project.endianness;
+    let tiles: Vec<u16> = project.map.iter().flatten().copied().collect();
+    let bytes = if opts.compress_map {
+        rle_encode(&tiles, order)
+    } else {
+        tiles
+            .into_iter()
+            .flat_map(|tile| order.u16_bytes(tile))
+            .collect()
+    };
+
+    let path = out_dir.join(file_name(&opts.prefix, "map.bin"));
+    write_checked(&path, bytes, opts)
+}
+
+/// Run-length encode tiles as `(count: u8, tile: u16)` triples; runs
+/// longer than 255 tiles are split.
+pub fn rle_encode(tiles: &[u16], order: Endianness) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut rest = tiles;
+    while let Some(&tile) = rest.first() {
+        let run = rest
+            .iter()
+            .take(u8::MAX as usize)
+            .take_while(|&&t| t == tile)
+            .count();
+        out.push(run as u8);
+        out.extend_from_slice(&order.u16_bytes(tile));
+        rest = &rest[run..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_texts_length_prefixed_in_index_order() {
+        let mut table = HashMap::new();
+        table.insert("yo".to_string(), 1);
+        table.insert("hey".to_string(), 0);
+
+        let mut out = Vec::new();
+        let texts: Vec<&str> = by_index(&table).into_iter().map(|(s, _)| s).collect();
+        write_texts(&texts, Endianness::Big, &mut out).unwrap();
+        assert_eq!(out, b"\x00\x03hey\x00\x02yo");
+
+        let mut out = Vec::new();
+        write_texts(&texts, Endianness::Little, &mut out).unwrap();
+        assert_eq!(out, b"\x03\x00hey\x02\x00yo");
+    }
+
+    fn chunk(len: usize) -> ScriptBlob {
+        ScriptBlob {
+            blob: vec![0xff; len],
+            script: String::new(),
+            offsets: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_format_doc_describes_this_version() {
+        let doc = include_str!("../../docs/binary_format.md");
+        assert!(doc.contains(&format!("*Format version {FORMAT_VERSION}*")));
+        assert!(doc.contains(&format!("format version, `{FORMAT_VERSION}`")));
+    }
+
+    #[test]
+    fn test_checksum_footer() {
+        let path = std::env::temp_dir().join(format!("cgt-{}-crc.bin", std::process::id()));
+        let opts = Options {
+            checksum: true,
+            ..Default::default()
+        };
+        // the standard check value of CRC-32 is 0xcbf43926
+        write_checked(&path, b"123456789".to_vec(), &opts).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes[..9], *b"123456789");
+        assert_eq!(bytes[9..], [0x26, 0x39, 0xf4, 0xcb]);
+
+        write_checked(&path, b"123456789".to_vec(), &Options::default()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"123456789");
+    }
+
+    #[test]
+    fn test_chunks_fill_fixed_slots() {
+        let mut out = Vec::new();
+        write_chunks(&[chunk(0), chunk(128), chunk(1)], 128, &mut out).unwrap();
+        assert_eq!(out.len(), 3 * 128);
+        // the empty chunk still takes a whole slot
+        assert!(out[..128].iter().all(|&b| b == 0));
+        assert!(out[128..256].iter().all(|&b| b == 0xff));
+        assert_eq!(out[256], 0xff);
+        assert!(out[257..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_chunk_larger_than_its_slot_is_rejected() {
+        let err = write_chunks(&[chunk(130)], 128, &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "blob 0 is 130 bytes, more than its 128-byte slot"
+        );
+    }
+
+    fn rle_decode(bytes: &[u8], order: Endianness) -> Vec<u16> {
+        bytes
+            .chunks(3)
+            .flat_map(|run| std::iter::repeat_n(order.read_u16([run[1], run[2]]), run[0] as usize))
+            .collect()
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        // long runs of grass with a few paths, like a real map
+        let mut tiles = vec![7u16; 65536];
+        for i in (0..65536).step_by(97) {
+            tiles[i] = 0x0102;
+        }
+        tiles[65535] = 3;
+
+        for order in [Endianness::Little, Endianness::Big] {
+            let packed = rle_encode(&tiles, order);
+            assert!(packed.len() < 65536 * 2 / 10, "{} bytes", packed.len());
+            assert_eq!(rle_decode(&packed, order), tiles);
+        }
+    }
+
+    #[test]
+    fn test_rle_splits_long_runs() {
+        assert_eq!(
+            rle_encode(&[5; 300], Endianness::Big),
+            [255, 0, 5, 45, 0, 5],
+            "300 = 255 + 45"
+        );
+        assert_eq!(rle_encode(&[], Endianness::Big), Vec::<u8>::new());
+        assert_eq!(
+            rle_encode(&[1, 2, 2], Endianness::Little),
+            [1, 1, 0, 2, 2, 0]
+        );
+    }
+
+    #[test]
+    fn test_offsets_count_then_starts_per_chunk() {
+        let chunk = |offsets: Vec<u16>| ScriptBlob {
+            blob: Vec::new(),
+            script: String::new(),
+            offsets,
+            notes: Vec::new(),
+        };
+        let mut out = Vec::new();
+        let chunks = [chunk(vec![0, 0x103]), chunk(vec![])];
+        write_offsets(&chunks, Endianness::Little, &mut out).unwrap();
+        assert_eq!(out, [2, 0, 0, 0, 3, 1, 0, 0]);
+    }
+}
@@ -1,117 +1,909 @@
-//! Emit C++ header/source for the VM part without using external crates.
+//! Emit C++ (or plain C) headers for the VM part without using external crates.
+use crate::info;
 use crate::model::ProcessedProject;
 use crate::processor::ast::Cmd;
 use crate::processor::blob::ProcessedScripts;
-use std::collections::HashMap;
+use crate::writer::{by_index, file_name};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, Write};
 use std::path::Path;
 
-pub fn emit(project: &ProcessedProject, out_dir: &Path) -> io::Result<()> {
-    println!("writing opcodes");
-    opcode_header(out_dir)?;
-    println!("writing flags");
-    flags(&project.flags, out_dir)?;
-    println!("writing locations");
-    locations(&project.locations, out_dir)?;
-    println!("writing scripts");
-    scripts(&project.blob, out_dir)?;
+/// Device the headers are compiled for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Target {
+    /// Plain declarations, no attributes
+    #[default]
+    Generic,
+    /// Script blobs and texts live in flash, read with `pgm_read_*`
+    Avr,
+}
+
+/// Language the headers are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Lang {
+    /// `enum class`, `constexpr` and optional namespaces
+    #[default]
+    Cpp,
+    /// Plain C: `#define` constants, include guards, no namespaces
+    C,
+}
+
+/// How the headers are generated.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Namespace every generated symbol is declared in, e.g. `cg`.
+    pub namespace: Option<String>,
+    pub target: Target,
+    pub lang: Lang,
+    /// Put in front of every file name, see `writer::file_name`.
+    pub prefix: String,
+}
+
+impl Options {
+    /// What goes in front of an array that belongs in flash.
+    fn flash(&self) -> &'static str {
+        match self.target {
+            Target::Generic => "",
+            Target::Avr => "PROGMEM const ",
+        }
+    }
+
+    /// Makes a file-scope `const` table private to every file that
+    /// includes its header: implicit in C++, `static` in C.
+    fn linkage(&self) -> &'static str {
+        match self.lang {
+            Lang::Cpp => "",
+            Lang::C => "static ",
+        }
+    }
+
+    /// How code refers to the integer constant `name`; C macros get a
+    /// `CG_` prefix so they cannot rewrite identifiers of their own.
+    fn symbol(&self, name: &str) -> String {
+        match self.lang {
+            Lang::Cpp => name.to_string(),
+            Lang::C => format!("CG_{name}"),
+        }
+    }
+
+    /// Declaration of the integer constant `name`.
+    fn constant(&self, name: &str, value: impl std::fmt::Display) -> String {
+        match self.lang {
+            Lang::Cpp => format!("static constexpr uint16_t {name} = {value};"),
+            Lang::C => format!("#define {} {value}", self.symbol(name)),
+        }
+    }
+
+    /// The null pointer literal.
+    fn null(&self) -> &'static str {
+        match self.lang {
+            Lang::Cpp => "nullptr",
+            Lang::C => "NULL",
+        }
+    }
+}
+
+pub fn emit(project: &ProcessedProject, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    info!("writing opcodes");
+    opcode_header(out_dir, opts)?;
+    info!("writing flags");
+    flags(&project.flags, out_dir, opts)?;
+    info!("writing locations");
+    locations(&project.locations, &project.location_tiles, out_dir, opts)?;
+    info!("writing texts");
+    texts(&project.texts, out_dir, opts)?;
+    info!("writing scripts");
+    scripts(&project.blob, &project.scripts, out_dir, opts)?;
+    all_header(out_dir, opts)?;
+    Ok(())
+}
+
+/// Every header `emit` writes, in the order `all.hpp` includes them.
+const HEADERS: [&str; 6] = [
+    "opcodes.hpp",
+    "flags.hpp",
+    "flag_bit_array.hpp",
+    "locations.hpp",
+    "texts.hpp",
+    "scripts.hpp",
+];
+
+/// `all.hpp`, so firmware needs a single include. Each header opens its
+/// own namespace, this one declares nothing itself.
+fn all_header(out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let file = file_name(&opts.prefix, "all.hpp");
+    let mut h = File::create(out_dir.join(&file))?;
+    guard(&mut h, &file, opts)?;
+    writeln!(h, "// Auto-generated – DO NOT EDIT\n")?;
+    for header in HEADERS {
+        writeln!(h, "#include \"{}\"", file_name(&opts.prefix, header))?;
+    }
+    end_guard(&mut h, opts)?;
     Ok(())
 }
 
-fn opcode_header(out_dir: &Path) -> io::Result<()> {
-    let mut h = File::create(out_dir.join("opcodes.hpp"))?;
+/// Include guard macro for `file`, e.g. `CG_OPCODES_HPP`.
+fn guard_macro(file: &str) -> String {
+    let file: String = file
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("CG_{file}")
+}
 
-    writeln!(h, "#pragma once")?;
+/// Make sure `file` is only included once: `#pragma once` in C++, a
+/// classic include guard in C.
+fn guard(h: &mut impl Write, file: &str, opts: &Options) -> io::Result<()> {
+    match opts.lang {
+        Lang::Cpp => writeln!(h, "#pragma once"),
+        Lang::C => {
+            let guard = guard_macro(file);
+            writeln!(h, "#ifndef {guard}")?;
+            writeln!(h, "#define {guard}")
+        }
+    }
+}
+
+/// Close what `guard` opened.
+fn end_guard(h: &mut impl Write, opts: &Options) -> io::Result<()> {
+    match opts.lang {
+        Lang::Cpp => Ok(()),
+        Lang::C => writeln!(h, "\n#endif"),
+    }
+}
+
+/// Create the header `name` and write its preamble.
+fn header(out_dir: &Path, name: &str, opts: &Options) -> io::Result<File> {
+    let file = file_name(&opts.prefix, name);
+    let mut h = File::create(out_dir.join(&file))?;
+    preamble(&mut h, &file, opts)?;
+    Ok(h)
+}
+
+/// Start the generated `file`, opening the namespace if there is one.
+fn preamble(h: &mut impl Write, file: &str, opts: &Options) -> io::Result<()> {
+    guard(h, file, opts)?;
     writeln!(h, "#include <stdint.h>")?;
+    if opts.lang == Lang::C {
+        writeln!(h, "#include <stddef.h>")?;
+    }
+    if opts.target == Target::Avr {
+        writeln!(h, "#include <avr/pgmspace.h>")?;
+    }
     writeln!(h, "// Auto-generated – DO NOT EDIT\n")?;
+    if let Some(ns) = &opts.namespace {
+        writeln!(h, "namespace {ns} {{\n")?;
+    }
+    Ok(())
+}
+
+/// Close what `preamble` opened.
+fn close(h: &mut impl Write, opts: &Options) -> io::Result<()> {
+    if let Some(ns) = &opts.namespace {
+        writeln!(h, "\n}} // namespace {ns}")?;
+    }
+    end_guard(h, opts)
+}
+
+fn opcode_header(out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let mut h = header(out_dir, "opcodes.hpp", opts)?;
 
     // ---------------------------------------------------------------
-    // 1. Enum-class for opcodes – derived from Cmd::VARIANT_NAMES
+    // 1. Enum for opcodes – derived from Cmd::VARIANT_NAMES
     // ---------------------------------------------------------------
-    writeln!(h, "enum class VmOpcode : uint8_t {{")?;
+    // C enumerators share one scope, so they carry the enum's name
+    let (open, prefix) = match opts.lang {
+        Lang::Cpp => ("enum class VmOpcode : uint8_t {", ""),
+        Lang::C => ("typedef enum {", "VmOpcode_"),
+    };
+    writeln!(h, "{open}")?;
     for (idx, name) in Cmd::VARIANT_NAMES.iter().enumerate() {
         if *name == "End" {
-            writeln!(h, "    {} = 255,", name)?;
+            writeln!(h, "    {prefix}{} = 255,", name)?;
         } else {
-            writeln!(h, "    {} = {},", name, idx)?;
+            writeln!(h, "    {prefix}{} = {},", name, idx)?;
         }
     }
-    writeln!(h, "}};\n")?;
+    match opts.lang {
+        Lang::Cpp => writeln!(h, "}};")?,
+        Lang::C => writeln!(h, "}} VmOpcode;")?,
+    }
+    close(&mut h, opts)?;
 
     Ok(())
 }
-fn flags(flags: &HashMap<String, u16>, out_dir: &Path) -> io::Result<()> {
-    flag_bit_arr(flags, out_dir)?;
-    flag_names(flags, out_dir)?;
+fn flags(flags: &HashMap<String, u16>, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    flag_bit_arr(flags, out_dir, opts)?;
+    flag_names(flags, out_dir, opts)?;
     Ok(())
 }
 
-fn flag_bit_arr(flags: &HashMap<String, u16>, out_dir: &Path) -> io::Result<()> {
-    let mut h = File::create(out_dir.join("flag_bit_array.hpp"))?;
-    let bits = flags.len() as u16;
-    let bytes = bits / 8 + (bits % 8) as u16;
+fn flag_bit_arr(flags: &HashMap<String, u16>, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let mut h = header(out_dir, "flag_bit_array.hpp", opts)?;
+    writeln!(
+        h,
+        "static uint8_t FLAG_BIT_ARRAY[{}] = {{0}};",
+        flag_bytes(flags.len())
+    )?;
+    close(&mut h, opts)?;
 
-    writeln!(h, "#pragma once")?;
-    writeln!(h, "#include <stdint.h>")?;
-    writeln!(h, "// Auto-generated – DO NOT EDIT\n")?;
-    writeln!(h, "extern uint8_t FLAG_BIT_ARRAY[{}];", bytes)?;
+    Ok(())
+}
 
-    let mut h = File::create(out_dir.join("flag_bit_array.cpp"))?;
-    let bits = flags.len() as u16;
-    let bytes = bits / 8 + (bits % 8) as u16;
+/// Bytes holding one bit per flag; at least one, C++ has no empty arrays.
+fn flag_bytes(flags: usize) -> usize {
+    flags.div_ceil(8).max(1)
+}
 
-    writeln!(h, "#pragma once")?;
-    writeln!(h, "#include <stdint.h>")?;
-    writeln!(h, "// Auto-generated – DO NOT EDIT\n")?;
-    writeln!(h, "uint8_t FLAG_BIT_ARRAY[{}] = {{0}};", bytes)?;
+fn flag_names(flags: &HashMap<String, u16>, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let mut h = header(out_dir, "flags.hpp", opts)?;
+
+    for (name, i) in c_symbols(flags) {
+        writeln!(h, "{}", opts.constant(&name, i))?;
+    }
+    close(&mut h, opts)?;
 
     Ok(())
 }
 
-fn flag_names(flags: &HashMap<String, u16>, out_dir: &Path) -> io::Result<()> {
-    let mut h = File::create(out_dir.join("flags.hpp"))?;
-    writeln!(h, "#pragma once")?;
-    writeln!(h, "#include <stdint.h>")?;
-    writeln!(h, "// Auto-generated – DO NOT EDIT\n")?;
+/// Marks a location with no tile on this map, e.g. a `warp` target.
+const NO_TILE: &str = "0xFFFF";
+
+/// Location indices and `LOCATIONS`, the tile of each, indexed the same.
+fn locations(
+    locs: &HashMap<String, u16>,
+    tiles: &HashMap<String, (u16, u16)>,
+    out_dir: &Path,
+    opts: &Options,
+) -> io::Result<()> {
+    let mut h = header(out_dir, "locations.hpp", opts)?;
+    let symbols = c_symbols(locs);
+    for (name, i) in &symbols {
+        writeln!(h, "{}", opts.constant(name, i))?;
+    }
 
-    for (name, i) in flags {
-        writeln!(h, "uint16_t {name} = {i};")?;
+    match opts.lang {
+        Lang::Cpp => writeln!(h, "\nstruct LocationTile {{ uint16_t x, y; }};")?,
+        Lang::C => writeln!(h, "\ntypedef struct {{ uint16_t x, y; }} LocationTile;")?,
+    }
+    writeln!(h, "{}const LocationTile LOCATIONS[] = {{", opts.linkage())?;
+    for ((name, _), (symbol, _)) in by_index(locs).into_iter().zip(&symbols) {
+        match tiles.get(name) {
+            Some((x, y)) => writeln!(h, "    {{ {x}, {y} }}, // {symbol}")?,
+            None => writeln!(
+                h,
+                "    {{ {NO_TILE}, {NO_TILE} }}, // {symbol}, not on this map"
+            )?,
+        }
     }
+    writeln!(h, "}};")?;
+    close(&mut h, opts)?;
 
     Ok(())
 }
 
-fn locations(locs: &HashMap<String, u16>, out_dir: &Path) -> io::Result<()> {
-    let mut h = File::create(out_dir.join("locations.hpp"))?;
-    writeln!(h, "#pragma once")?;
-    writeln!(h, "#include <stdint.h>")?;
-    writeln!(h, "// Auto-generated – DO NOT EDIT\n")?;
-    for (name, i) in locs {
-        writeln!(h, "uint16_t {name} = {i};")?;
+/// Turn `name` into a valid C identifier: anything outside `[A-Za-z0-9_]`
+/// becomes `_` and a leading digit gets a `_` prefix. Names already in
+/// `taken` are suffixed with `_2`, `_3`, … until unique.
+fn sanitize_ident(name: &str, taken: &mut HashSet<String>) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
     }
 
+    let mut unique = ident.clone();
+    let mut n = 2;
+    while taken.contains(&unique) {
+        unique = format!("{ident}_{n}");
+        n += 1;
+    }
+    taken.insert(unique.clone());
+    unique
+}
+
+/// A name table as C symbols, in index order.
+fn c_symbols(table: &HashMap<String, u16>) -> Vec<(String, u16)> {
+    let mut taken = HashSet::new();
+    by_index(table)
+        .into_iter()
+        .map(|(name, i)| (sanitize_ident(name, &mut taken), i))
+        .collect()
+}
+
+/// Split `s` into lines that are safe behind `//`: `*/` is broken up and a
+/// trailing backslash, which would splice the next source line into the
+/// comment, gets a space after it.
+fn comment_lines(s: &str) -> Vec<String> {
+    s.lines()
+        .map(|line| {
+            let mut line = line.replace("*/", "* /");
+            if line.ends_with('\\') {
+                line.push(' ');
+            }
+            line
+        })
+        .collect()
+}
+
+/// Quote `s` as a C string literal.
+fn c_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            // `??x` would otherwise be read as a trigraph
+            '?' => out.push_str("\\?"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn texts(texts: &HashMap<String, u16>, out_dir: &Path, opts: &Options) -> io::Result<()> {
+    let mut h = header(out_dir, "texts.hpp", opts)?;
+
+    let sorted = by_index(texts);
+    writeln!(h, "{}", opts.constant("TEXT_COUNT", sorted.len()))?;
+    if opts.target == Target::Avr {
+        // the table only holds pointers, every string needs its own array
+        for (text, i) in &sorted {
+            writeln!(
+                h,
+                "{}PROGMEM const char TEXT_{i}[] = {};",
+                opts.linkage(),
+                c_string(text)
+            )?;
+        }
+        writeln!(
+            h,
+            "{}PROGMEM const char* const TEXTS[] = {{",
+            opts.linkage()
+        )?;
+        for (_, i) in sorted {
+            writeln!(h, "    TEXT_{i},")?;
+        }
+    } else {
+        writeln!(h, "{}const char* const TEXTS[] = {{", opts.linkage())?;
+        for (text, i) in sorted {
+            writeln!(h, "    {}, // {i}", c_string(text))?;
+        }
+    }
+    writeln!(h, "}};")?;
+    close(&mut h, opts)?;
+
     Ok(())
 }
 
-fn scripts(blob: &ProcessedScripts, out_dir: &Path) -> io::Result<()> {
-    let mut h = File::create(out_dir.join("scripts.hpp"))?;
-    writeln!(h, "#pragma once")?;
-    writeln!(h, "#include <stdint.h>")?;
-    writeln!(h, "// Auto-generated – DO NOT EDIT\n")?;
+fn scripts(
+    blob: &ProcessedScripts,
+    called: &HashMap<String, u16>,
+    out_dir: &Path,
+    opts: &Options,
+) -> io::Result<()> {
+    let mut h = header(out_dir, "scripts.hpp", opts)?;
     for (i, blob) in blob.blob.iter().enumerate() {
-        if blob.blob.len() <= 1 {
-            continue;
-        }
         let str_nums: Vec<String> = blob
             .blob
             .iter()
             .map(|n| n.to_string()) // Convert each u8 to a string
             .collect(); // Collect into a Vec<String>
         let joined = str_nums.join(",");
-        writeln!(h, "// {}", blob.script)?;
-        writeln!(h, "uint8_t blob{i}[] = {{ {} }};", joined)?;
+        for line in comment_lines(&blob.script) {
+            writeln!(h, "// {line}")?;
+        }
+        writeln!(
+            h,
+            "static {}uint8_t blob{i}[] = {{ {} }};",
+            opts.flash(),
+            joined
+        )?;
     }
 
+    // the tables are read-only, so AVR keeps them in flash with the blobs
+    let attr = match opts.target {
+        Target::Generic => "",
+        Target::Avr => "PROGMEM ",
+    };
+
+    // chunks with identical scripts share a blob
+    let index: Vec<String> = blob.index.iter().map(|b| b.to_string()).collect();
+    writeln!(
+        h,
+        "\n{}{attr}const uint16_t CHUNK_BLOB[] = {{ {} }};",
+        opts.linkage(),
+        index.join(",")
+    )?;
+
+    // what the VM runs for each chunk, null where it runs nothing
+    let pointers: Vec<String> = (0..blob.index.len())
+        .map(|chunk| {
+            if blob.chunk(chunk).offsets.is_empty() {
+                opts.null().to_string()
+            } else {
+                format!("blob{}", blob.index[chunk])
+            }
+        })
+        .collect();
+    writeln!(h, "\n{}", opts.constant("TOTAL_CHUNKS", blob.index.len()))?;
+    writeln!(
+        h,
+        "{}{attr}const uint8_t* const CHUNK_SCRIPTS[{}] = {{ {} }};",
+        opts.linkage(),
+        opts.symbol("TOTAL_CHUNKS"),
+        pointers.join(",")
+    )?;
+
+    // where `call N` jumps: entry N's chunk and offset into that chunk
+    writeln!(h, "\n{}", opts.constant("SCRIPT_COUNT", blob.calls.len()))?;
+    if !blob.calls.is_empty() {
+        match opts.lang {
+            Lang::Cpp => writeln!(h, "struct ScriptStart {{ uint16_t chunk, offset; }};")?,
+            Lang::C => writeln!(
+                h,
+                "typedef struct {{ uint16_t chunk, offset; }} ScriptStart;"
+            )?,
+        }
+        writeln!(
+            h,
+            "{}{attr}const ScriptStart SCRIPT_STARTS[{}] = {{",
+            opts.linkage(),
+            opts.symbol("SCRIPT_COUNT")
+        )?;
+        for ((name, _), (chunk, offset)) in by_index(called).into_iter().zip(&blob.calls) {
+            writeln!(h, "    {{ {chunk}, {offset} }}, // {name}")?;
+        }
+        writeln!(h, "}};")?;
+    }
+    close(&mut h, opts)?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::ast::Opcode;
+    use std::path::PathBuf;
+
+    /// Fresh per-test output directory under the system temp dir.
+    fn out_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cgt-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_texts_emitted_in_index_order() {
+        let dir = out_dir("texts");
+        let mut table = HashMap::new();
+        table.insert("second".to_string(), 1);
+        table.insert("first".to_string(), 0);
+
+        texts(&table, &dir, &Options::default()).unwrap();
+        let hpp = std::fs::read_to_string(dir.join("texts.hpp")).unwrap();
+
+        let entries: Vec<&str> = hpp.lines().filter(|l| l.starts_with("    \"")).collect();
+        assert_eq!(entries, vec!["    \"first\", // 0", "    \"second\", // 1"]);
+        assert!(hpp.contains("static constexpr uint16_t TEXT_COUNT = 2;"));
+    }
+
+    #[test]
+    fn test_flags_emit_is_stable_and_ordered() {
+        let mut table = HashMap::new();
+        for (i, name) in ["flag_g", "flag_a", "flag_z", "flag_m", "flag_b"]
+            .iter()
+            .enumerate()
+        {
+            table.insert(name.to_string(), i as u16);
+        }
+
+        let first = out_dir("flags-1");
+        let second = out_dir("flags-2");
+        flags(&table, &first, &Options::default()).unwrap();
+        flags(&table.clone(), &second, &Options::default()).unwrap();
+
+        let a = std::fs::read(first.join("flags.hpp")).unwrap();
+        let b = std::fs::read(second.join("flags.hpp")).unwrap();
+        assert_eq!(a, b);
+
+        let hpp = String::from_utf8(a).unwrap();
+        let decls: Vec<&str> = hpp.lines().filter(|l| l.contains("uint16_t")).collect();
+        assert_eq!(
+            decls,
+            vec![
+                "static constexpr uint16_t flag_g = 0;",
+                "static constexpr uint16_t flag_a = 1;",
+                "static constexpr uint16_t flag_z = 2;",
+                "static constexpr uint16_t flag_m = 3;",
+                "static constexpr uint16_t flag_b = 4;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flag_bit_array_length() {
+        for (count, bytes) in [(0, 1), (8, 1), (9, 2), (14, 2), (16, 2)] {
+            let dir = out_dir(&format!("flag-bits-{count}"));
+            let table: HashMap<String, u16> =
+                (0..count).map(|i| (format!("flag_{i}"), i)).collect();
+
+            flag_bit_arr(&table, &dir, &Options::default()).unwrap();
+            let hpp = std::fs::read_to_string(dir.join("flag_bit_array.hpp")).unwrap();
+            let decl = format!("static uint8_t FLAG_BIT_ARRAY[{bytes}] = {{0}};");
+            assert!(hpp.contains(&decl), "{count} flags:\n{hpp}");
+        }
+    }
+
+    #[test]
+    fn test_symbol_headers_are_link_safe() {
+        let dir = out_dir("link-safe");
+        let mut table = HashMap::new();
+        table.insert("flag_a".to_string(), 0);
+        table.insert("flag_b".to_string(), 1);
+
+        flag_names(&table, &dir, &Options::default()).unwrap();
+        locations(&table, &HashMap::new(), &dir, &Options::default()).unwrap();
+        for file in ["flags.hpp", "locations.hpp"] {
+            let hpp = std::fs::read_to_string(dir.join(file)).unwrap();
+            // scalar constants; in C++ the const `LOCATIONS` is link-safe as
+            // well, C needs it `static`, see `test_c_lang_is_plain_c`
+            let decls: Vec<&str> = hpp
+                .lines()
+                .filter(|l| l.contains(" = ") && l.ends_with(';'))
+                .collect();
+            assert_eq!(decls.len(), 2, "{file}");
+            for line in decls {
+                assert!(line.starts_with("static constexpr uint16_t "), "{line}");
+            }
+        }
+        let hpp = std::fs::read_to_string(dir.join("locations.hpp")).unwrap();
+        assert!(
+            hpp.contains("\nconst LocationTile LOCATIONS[] = {"),
+            "{hpp}"
+        );
+
+        let opts = Options {
+            lang: Lang::C,
+            ..Default::default()
+        };
+        locations(&table, &HashMap::new(), &dir, &opts).unwrap();
+        let h = std::fs::read_to_string(dir.join("locations.hpp")).unwrap();
+        assert!(
+            h.contains("\nstatic const LocationTile LOCATIONS[] = {"),
+            "{h}"
+        );
+    }
+
+    #[test]
+    fn test_namespace_wraps_symbols() {
+        let dir = out_dir("namespace");
+        let opts = Options {
+            namespace: Some("cg".into()),
+            ..Default::default()
+        };
+        let mut table = HashMap::new();
+        table.insert("flag_a".to_string(), 0);
+
+        flags(&table, &dir, &opts).unwrap();
+        locations(&table, &HashMap::new(), &dir, &opts).unwrap();
+        texts(&table, &dir, &opts).unwrap();
+        for file in [
+            "flags.hpp",
+            "flag_bit_array.hpp",
+            "locations.hpp",
+            "texts.hpp",
+        ] {
+            let hpp = std::fs::read_to_string(dir.join(file)).unwrap();
+            assert!(hpp.starts_with("#pragma once\n"), "{file}");
+            let open = hpp.find("namespace cg {").expect(file);
+            let close = hpp.rfind("} // namespace cg").expect(file);
+            let decl = hpp.find(" = ").or_else(|| hpp.find("extern")).expect(file);
+            assert!(open < decl && decl < close, "{file}:\n{hpp}");
+        }
+
+        flag_names(&table, &dir, &Options::default()).unwrap();
+        let hpp = std::fs::read_to_string(dir.join("flags.hpp")).unwrap();
+        assert!(!hpp.contains("namespace"));
+    }
+
+    #[test]
+    fn test_sanitize_ident() {
+        let mut taken = HashSet::new();
+        assert_eq!(sanitize_ident("shop 2", &mut taken), "shop_2");
+        assert_eq!(sanitize_ident("@spawn-point", &mut taken), "_spawn_point");
+        assert_eq!(sanitize_ident("2nd_floor", &mut taken), "_2nd_floor");
+        assert_eq!(sanitize_ident("", &mut taken), "_");
+        // `shop-2` and `shop_2` both map to the `shop_2` already taken
+        assert_eq!(sanitize_ident("shop-2", &mut taken), "shop_2_2");
+        assert_eq!(sanitize_ident("shop_2", &mut taken), "shop_2_3");
+    }
+
+    #[test]
+    fn test_locations_emit_valid_symbols() {
+        let dir = out_dir("locations");
+        let mut table = HashMap::new();
+        table.insert("shop 2".to_string(), 0);
+        table.insert("shop-2".to_string(), 1);
+        table.insert("spawn".to_string(), 2);
+
+        locations(&table, &HashMap::new(), &dir, &Options::default()).unwrap();
+        let hpp = std::fs::read_to_string(dir.join("locations.hpp")).unwrap();
+        let decls: Vec<&str> = hpp.lines().filter(|l| l.starts_with("static")).collect();
+        assert_eq!(
+            decls,
+            vec![
+                "static constexpr uint16_t shop_2 = 0;",
+                "static constexpr uint16_t shop_2_2 = 1;",
+                "static constexpr uint16_t spawn = 2;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_locations_emit_tiles() {
+        let dir = out_dir("location-tiles");
+        let mut table = HashMap::new();
+        table.insert("spawn".to_string(), 0);
+        table.insert("elsewhere".to_string(), 1);
+        let mut tiles = HashMap::new();
+        tiles.insert("spawn".to_string(), (2, 3));
+        tiles.insert("unused".to_string(), (4, 4));
+
+        locations(&table, &tiles, &dir, &Options::default()).unwrap();
+        let hpp = std::fs::read_to_string(dir.join("locations.hpp")).unwrap();
+        let table = hpp
+            .split_once("const LocationTile LOCATIONS[] = {\n")
+            .and_then(|(_, rest)| rest.split_once("};"))
+            .expect(&hpp)
+            .0;
+        assert_eq!(
+            table,
+            "    { 2, 3 }, // spawn\n    { 0xFFFF, 0xFFFF }, // elsewhere, not on this map\n"
+        );
+        assert!(hpp.contains("struct LocationTile { uint16_t x, y; };"));
+    }
+
+    #[test]
+    fn test_script_comments_stay_on_comment_lines() {
+        use crate::processor::blob::ScriptBlob;
+
+        let dir = out_dir("scripts");
+        let blob = ProcessedScripts {
+            blob: vec![ScriptBlob {
+                blob: vec![Opcode::End as u8, 0xff],
+                script: "msg {hi}\r\n/* note */ end \\\nwait 1".into(),
+                offsets: vec![0],
+                notes: Vec::new(),
+            }],
+            index: vec![0],
+            calls: Vec::new(),
+        };
+
+        scripts(&blob, &HashMap::new(), &dir, &Options::default()).unwrap();
+        let hpp = std::fs::read_to_string(dir.join("scripts.hpp")).unwrap();
+        let body: Vec<&str> = hpp
+            .lines()
+            .skip_while(|l| !l.starts_with("// msg"))
+            .collect();
+        assert_eq!(
+            body,
+            vec![
+                "// msg {hi}",
+                "// /* note * / end \\ ",
+                "// wait 1",
+                "static uint8_t blob0[] = { 255,255 };",
+                "",
+                "const uint16_t CHUNK_BLOB[] = { 0 };",
+                "",
+                "static constexpr uint16_t TOTAL_CHUNKS = 1;",
+                "const uint8_t* const CHUNK_SCRIPTS[TOTAL_CHUNKS] = { blob0 };",
+                "",
+                "static constexpr uint16_t SCRIPT_COUNT = 0;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_avr_target_puts_arrays_in_flash() {
+        use crate::processor::blob::ScriptBlob;
+
+        let blob = ProcessedScripts {
+            blob: vec![ScriptBlob {
+                blob: vec![Opcode::End as u8],
+                script: String::new(),
+                offsets: vec![],
+                notes: Vec::new(),
+            }],
+            index: vec![0],
+            calls: Vec::new(),
+        };
+        let mut table = HashMap::new();
+        table.insert("hi".to_string(), 0);
+
+        let generic = out_dir("target-generic");
+        scripts(&blob, &HashMap::new(), &generic, &Options::default()).unwrap();
+        texts(&table, &generic, &Options::default()).unwrap();
+        let avr = out_dir("target-avr");
+        let opts = Options {
+            target: Target::Avr,
+            ..Default::default()
+        };
+        scripts(&blob, &HashMap::new(), &avr, &opts).unwrap();
+        texts(&table, &avr, &opts).unwrap();
+
+        for file in ["scripts.hpp", "texts.hpp"] {
+            let hpp = std::fs::read_to_string(generic.join(file)).unwrap();
+            assert!(!hpp.contains("PROGMEM"), "{file}:\n{hpp}");
+            assert!(!hpp.contains("pgmspace"), "{file}:\n{hpp}");
+            let hpp = std::fs::read_to_string(avr.join(file)).unwrap();
+            assert!(hpp.contains("#include <avr/pgmspace.h>"), "{file}:\n{hpp}");
+        }
+        let hpp = std::fs::read_to_string(avr.join("scripts.hpp")).unwrap();
+        assert!(
+            hpp.contains("static PROGMEM const uint8_t blob0[] = { 255 };"),
+            "{hpp}"
+        );
+        assert!(
+            hpp.contains("\nPROGMEM const uint16_t CHUNK_BLOB[] = { 0 };"),
+            "{hpp}"
+        );
+        let hpp = std::fs::read_to_string(avr.join("texts.hpp")).unwrap();
+        assert!(
+            hpp.contains("PROGMEM const char TEXT_0[] = \"hi\";"),
+            "{hpp}"
+        );
+        assert!(
+            hpp.contains("PROGMEM const char* const TEXTS[] = {\n    TEXT_0,\n};"),
+            "{hpp}"
+        );
+    }
+
+    #[test]
+    fn test_all_header_includes_every_header() {
+        let dir = out_dir("all");
+        let project = crate::compile_str(&crate::project_with_scripts(&[])).unwrap();
+        let opts = Options {
+            namespace: Some("cg".into()),
+            ..Default::default()
+        };
+        emit(&project, &dir, &opts).unwrap();
+
+        let all = std::fs::read_to_string(dir.join("all.hpp")).unwrap();
+        let mut emitted: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|f| f.ends_with(".hpp") && f != "all.hpp")
+            .collect();
+        emitted.sort();
+        assert_eq!(emitted.len(), HEADERS.len());
+        for header in emitted {
+            assert!(
+                all.contains(&format!("#include \"{header}\"\n")),
+                "{header}"
+            );
+        }
+        // the included headers are namespaced already
+        assert!(!all.contains("namespace"));
+    }
+
+    #[test]
+    fn test_c_lang_is_plain_c() {
+        let dir = out_dir("lang-c");
+        let json = crate::project_with_locations(
+            &[(0.0, 0.0, "setflag flag_a; tp @@home; msg {hi};")],
+            &[("home", 16.0, 16.0)],
+        );
+        let project = crate::compile_str(&json).unwrap();
+        let opts = Options {
+            lang: Lang::C,
+            ..Default::default()
+        };
+        emit(&project, &dir, &opts).unwrap();
+
+        for file in HEADERS.iter().chain(&["all.hpp"]) {
+            let hpp = std::fs::read_to_string(dir.join(file)).unwrap();
+            for cpp in [
+                "enum class",
+                "constexpr",
+                "::",
+                "namespace",
+                "nullptr",
+                "#pragma once",
+            ] {
+                assert!(!hpp.contains(cpp), "{file} has {cpp:?}:\n{hpp}");
+            }
+            let guard = guard_macro(file);
+            assert!(
+                hpp.starts_with(&format!("#ifndef {guard}\n#define {guard}\n")),
+                "{file}:\n{hpp}"
+            );
+            assert!(hpp.ends_with("\n#endif\n"), "{file}:\n{hpp}");
+        }
+        let read = |file: &str| std::fs::read_to_string(dir.join(file)).unwrap();
+        assert!(read("opcodes.hpp").contains("typedef enum {\n    VmOpcode_"));
+        assert!(read("opcodes.hpp").contains("    VmOpcode_End = 255,\n} VmOpcode;"));
+        assert!(read("flags.hpp").contains("#define CG_flag_a 0\n"));
+        assert!(read("locations.hpp").contains("#define CG_home 0\n"));
+        assert!(read("locations.hpp").contains(
+            "} LocationTile;\nstatic const LocationTile LOCATIONS[] = {\n    { 1, 1 }, // home\n"
+        ));
+        assert!(read("texts.hpp").contains("#define CG_TEXT_COUNT 1\n"));
+        assert!(read("texts.hpp").contains("static const char* const TEXTS[] = {"));
+        let scripts = read("scripts.hpp");
+        assert!(
+            scripts.contains("#define CG_TOTAL_CHUNKS 2048\n"),
+            "{scripts}"
+        );
+        assert!(
+            scripts.contains("static const uint16_t CHUNK_BLOB[] = {"),
+            "{scripts}"
+        );
+        assert!(
+            scripts.contains(
+                "static const uint8_t* const CHUNK_SCRIPTS[CG_TOTAL_CHUNKS] = { blob0,NULL,"
+            ),
+            "{scripts}"
+        );
+    }
+
+    #[test]
+    fn test_chunk_scripts_point_at_populated_chunks() {
+        let dir = out_dir("chunk-scripts");
+        // tiles (9, 1) and (9, 5): chunks 1 and 33
+        let json =
+            crate::project_with_scripts(&[(144.0, 16.0, "wait 1;"), (144.0, 80.0, "wait 2;")]);
+        let project = crate::compile_str(&json).unwrap();
+        scripts(&project.blob, &project.scripts, &dir, &Options::default()).unwrap();
+
+        let hpp = std::fs::read_to_string(dir.join("scripts.hpp")).unwrap();
+        assert!(hpp.contains("static constexpr uint16_t TOTAL_CHUNKS = 2048;"));
+        let table = hpp
+            .lines()
+            .find_map(|l| l.strip_prefix("const uint8_t* const CHUNK_SCRIPTS[TOTAL_CHUNKS] = { "))
+            .expect(&hpp)
+            .strip_suffix(" };")
+            .unwrap();
+        let entries: Vec<&str> = table.split(',').collect();
+        assert_eq!(entries.len(), 2048);
+        let populated: Vec<(usize, &str)> = entries
+            .into_iter()
+            .enumerate()
+            .filter(|(_, e)| *e != "nullptr")
+            .collect();
+        let blob = |chunk: usize| format!("blob{}", project.blob.index[chunk]);
+        assert_eq!(
+            populated,
+            vec![(1, blob(1).as_str()), (33, blob(33).as_str())]
+        );
+    }
+
+    #[test]
+    fn test_c_string_escapes() {
+        assert_eq!(c_string(r#"say "hi"\n"#), r#""say \"hi\"\\n""#);
+        assert_eq!(c_string("a\nb"), r#""a\nb""#);
+        assert_eq!(c_string("what??!"), r#""what\?\?!""#);
+    }
+}
@@ -0,0 +1,112 @@
+//! `--emit-listing`: a disassembly of every chunk's bytecode, `scripts.lst`.
+
+use crate::model::ProcessedProject;
+use crate::processor::ast::{Cmd, SymbolKind, decode};
+use crate::writer::{by_index, file_name};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Write `scripts.lst`, behind `prefix` if there is one.
+pub fn emit(project: &ProcessedProject, out_dir: &Path, prefix: &str) -> io::Result<()> {
+    let path = out_dir.join(file_name(prefix, "scripts.lst"));
+    let mut out = BufWriter::new(File::create(path)?);
+    write_listing(project, &mut out)?;
+    out.flush()
+}
+
+/// For every chunk that runs something: its source, then one line per
+/// command with the chunk-relative offset, the encoded bytes and the
+/// decoded command, texts, flags and locations named again.
+pub fn write_listing(project: &ProcessedProject, out: &mut impl Write) -> io::Result<()> {
+    let names = |table| -> Vec<&str> { by_index(table).into_iter().map(|(s, _)| s).collect() };
+    let texts = names(&project.texts);
+    let flags = names(&project.flags);
+    let locations = names(&project.locations);
+
+    let scripts = &project.blob;
+    for chunk in 0..scripts.index.len() {
+        let blob = scripts.chunk(chunk);
+        if blob.offsets.is_empty() {
+            continue;
+        }
+        writeln!(out, "; chunk {chunk}, blob {}", scripts.index[chunk])?;
+        writeln!(out, "; {}", blob.script)?;
+
+        // the terminator is not part of any script
+        let end = blob.blob.len() - 1;
+        let mut pos = 0;
+        let mut notes = blob.notes.iter().peekable();
+        while pos < end {
+            while let Some((_, note)) = notes.next_if(|&&(at, _)| usize::from(at) <= pos) {
+                writeln!(out, "; note: {note}")?;
+            }
+            let (mut cmd, len) = decode(&blob.blob[pos..end], project.endianness)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            name_symbols(&mut cmd, &texts, &flags, &locations);
+            let hex: Vec<String> = blob.blob[pos..pos + len]
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            writeln!(out, "{pos:04x}  {:<18}  {cmd:?}", hex.join(" "))?;
+            pos += len;
+        }
+        for (_, note) in notes {
+            writeln!(out, "; note: {note}")?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Put back the names decoding dropped; vars and called scripts are not
+/// kept past parsing.
+fn name_symbols(cmd: &mut Cmd, texts: &[&str], flags: &[&str], locations: &[&str]) {
+    cmd.for_each_symbol_mut(&mut |kind, t| {
+        let table = match kind {
+            SymbolKind::Text => texts,
+            SymbolKind::Flag => flags,
+            SymbolKind::Tag => locations,
+            SymbolKind::Var | SymbolKind::Script => return,
+        };
+        if let Some(name) = table.get(t.index as usize) {
+            t.text = (*name).into();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing_shows_offsets_and_mnemonics() {
+        let json = crate::project_with_scripts(&[(16.0, 16.0, "msg {hi}; wait 3;")]);
+        let project = crate::compile_str(&json).unwrap();
+
+        let mut out = Vec::new();
+        write_listing(&project, &mut out).unwrap();
+        let listing = String::from_utf8(out).unwrap();
+
+        assert!(listing.starts_with("; chunk 0, blob 0\n; msg {hi}; wait 3;\n"));
+        assert!(listing.contains("\n0000  00 00 00"), "{listing}");
+        assert!(
+            listing.contains("Msg { text: Text { text: \"hi\""),
+            "{listing}"
+        );
+        assert!(listing.contains("\n0003  08 03 00"), "{listing}");
+        assert!(listing.contains("Wait { frames: 3 }"), "{listing}");
+        // chunks without scripts are left out
+        assert!(!listing.contains("; chunk 1,"));
+
+        let json = json.replace("msg {hi}; wait 3;", "msg {hi}; note {slow} wait 3;");
+        let project = crate::compile_str(&json).unwrap();
+        let mut out = Vec::new();
+        write_listing(&project, &mut out).unwrap();
+        let listing = String::from_utf8(out).unwrap();
+        assert!(
+            listing.contains("\n; note: slow\n0003  08 03 00"),
+            "{listing}"
+        );
+    }
+}
@@ -1,4 +1,26 @@
 //! Component 2 – all file generation.
 
+pub mod bin;
 pub mod c;
-pub mod bin;
\ No newline at end of file
+pub mod listing;
+pub mod report;
+
+use std::collections::HashMap;
+
+/// Entries of a name → index table sorted by index, so generated output is
+/// stable across runs and position `i` is the symbol the bytecode calls `i`.
+pub(crate) fn by_index(table: &HashMap<String, u16>) -> Vec<(&str, u16)> {
+    let mut sorted: Vec<(&str, u16)> = table.iter().map(|(s, &i)| (s.as_str(), i)).collect();
+    sorted.sort_by_key(|&(_, i)| i);
+    sorted
+}
+
+/// File `name` of the output directory, behind `prefix` and an underscore
+/// when there is a prefix, so several maps can share one directory.
+pub(crate) fn file_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}_{name}")
+    }
+}
@@ -0,0 +1,71 @@
+//! `--report`: a JSON summary of the build for editors and CI.
+
+use crate::model::{Metadata, ProcessedProject, RawProject};
+use crate::writer::by_index;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub fn emit(
+    raw: &RawProject,
+    project: &ProcessedProject,
+    warnings: &[String],
+    path: &Path,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, &report(raw, project, warnings))?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+/// The report document. `chunk_bytes[i]` is the size of chunk `i`,
+/// terminator included; `total_bytes` counts shared blobs once.
+pub fn report(raw: &RawProject, project: &ProcessedProject, warnings: &[String]) -> Value {
+    let scripts = &project.blob;
+    let chunk_bytes: Vec<usize> = (0..scripts.index.len())
+        .map(|i| scripts.chunk(i).blob.len())
+        .collect();
+    json!({
+        "scripts": raw.scripts.objects.len(),
+        "chunk_byte_limit": project.layout.byte_limit,
+        "chunk_bytes": chunk_bytes,
+        "blobs": scripts.blob.len(),
+        "total_bytes": project.total_size(),
+        "flags": table(&project.flags),
+        "locations": table(&project.locations),
+        "texts": table(&project.texts),
+        "calls": calls(project),
+        "metadata": metadata(&project.metadata),
+        "warnings": warnings,
+    })
+}
+
+/// `[{"name": .., "index": ..}, ..]` in index order.
+fn table(table: &HashMap<String, u16>) -> Value {
+    by_index(table)
+        .into_iter()
+        .map(|(name, index)| json!({ "name": name, "index": index }))
+        .collect()
+}
+
+/// `[{"name": .., "index": .., "chunk": .., "offset": ..}, ..]`, where
+/// each `call` index starts, in index order.
+fn calls(project: &ProcessedProject) -> Value {
+    by_index(&project.scripts)
+        .into_iter()
+        .zip(&project.blob.calls)
+        .map(|((name, index), (chunk, offset))| {
+            json!({ "name": name, "index": index, "chunk": chunk, "offset": offset })
+        })
+        .collect()
+}
+
+/// `[{"id": .., "meta": {"key": "value", ..}}, ..]` by script id.
+fn metadata(metadata: &BTreeMap<i32, Metadata>) -> Value {
+    metadata
+        .iter()
+        .map(|(id, meta)| json!({ "id": id, "meta": meta }))
+        .collect()
+}
@@ -1,11 +1,14 @@
 use std::fs;
 
-use pokervm_rust::parser::load_from_json;
+use clap::Parser;
+use pokervm_rust::cli::Cli;
+use pokervm_rust::model::{ChunkLayout, EMPTY_TILE};
+use pokervm_rust::parser::{LayerNames, load_from_json};
 
 #[test]
 fn parses_script_objects() {
     let json = fs::read_to_string("tests/world_map.json").unwrap();
-    let proj = load_from_json(&json).expect("valid json");
+    let proj = load_from_json(&json, &LayerNames::default()).expect("valid json");
 
     // sample file has two script objects
     assert_eq!(proj.scripts.objects.len(), 3);
@@ -15,3 +18,407 @@ fn parses_script_objects() {
     assert!((first.x - 7.16146).abs() < 1e-1);
     assert!((first.y - 75.6717).abs() < 1e-1);
 }
+
+#[test]
+fn rejects_duplicate_script_ids() {
+    let json = r#"{
+        "layers": [
+            { "name": "map", "data": [0, 1, 2] },
+            { "name": "scripts", "objects": [
+                { "id": 5, "x": 16.0, "y": 32.0,
+                  "properties": [{ "name": "script", "value": "msg {a};" }] },
+                { "id": 5, "x": 48.0, "y": 64.0,
+                  "properties": [{ "name": "script", "value": "msg {b};" }] }
+            ]},
+            { "name": "locations", "objects": [] }
+        ]
+    }"#;
+
+    let err = load_from_json(json, &LayerNames::default())
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("duplicate script id 5"), "got: {err}");
+    assert!(err.contains("(16, 32)"), "first occurrence missing: {err}");
+    assert!(err.contains("(48, 64)"), "second occurrence missing: {err}");
+}
+
+#[test]
+fn tile_objects_are_placed_by_their_top_left_corner() {
+    // a point on tile (2, 3), and tile objects of one and two tiles whose
+    // bottom-left corners Tiled reports; all three cover tile (2, 3)
+    let json = r#"{
+        "layers": [
+            { "name": "map", "data": [0, 1, 2] },
+            { "name": "scripts", "objects": [
+                { "id": 1, "x": 32.0, "y": 48.0, "point": true,
+                  "properties": [{ "name": "script", "value": "msg {a};" }] }
+            ]},
+            { "name": "locations", "objects": [
+                { "id": 2, "name": "tile", "gid": 5, "x": 32.0, "y": 64.0,
+                  "width": 16.0, "height": 16.0 },
+                { "id": 3, "name": "tall", "gid": 5, "x": 32.0, "y": 80.0,
+                  "width": 16.0, "height": 32.0 }
+            ]}
+        ]
+    }"#;
+
+    let proj = load_from_json(json, &LayerNames::default()).unwrap();
+    let layout = ChunkLayout::default();
+    let chunk = |x: f32, y: f32| {
+        let tile = ((x / 16.0) as i32, (y / 16.0) as i32);
+        assert_eq!(tile, (2, 3));
+        layout.index(tile.0, tile.1)
+    };
+    let point = &proj.scripts.objects[0];
+    for location in &proj.locations.objects {
+        assert_eq!(chunk(location.x, location.y), chunk(point.x, point.y));
+    }
+
+    let json = json.replace(r#""width": 16.0, "height": 32.0"#, r#""width": 16.0"#);
+    let err = load_from_json(&json, &LayerNames::default()).unwrap_err();
+    assert_eq!(err.to_string(), "tile object 1 missing `height`");
+}
+
+#[test]
+fn loads_layers_with_overridden_names() {
+    let json = r#"{
+        "layers": [
+            { "name": "tiles", "data": [0, 1, 2] },
+            { "name": "events", "objects": [
+                { "id": 1, "x": 0.0, "y": 0.0,
+                  "properties": [{ "name": "script", "value": "msg {a};" }] }
+            ]},
+            { "name": "markers", "objects": [
+                { "id": 2, "name": "@spawn", "x": 16.0, "y": 16.0 }
+            ]}
+        ]
+    }"#;
+
+    let args = Cli::try_parse_from([
+        "pokervm-rust",
+        "in.json",
+        "out",
+        "--map-layer",
+        "tiles",
+        "--scripts-layer",
+        "events",
+        "--locations-layer",
+        "markers",
+    ])
+    .unwrap();
+    let proj = load_from_json(json, &args.layer_names()).expect("overridden names load");
+
+    assert_eq!(proj.map, vec![0, 1, 2]);
+    assert_eq!(proj.scripts.objects.len(), 1);
+    assert_eq!(proj.locations.objects[0].name, "@spawn");
+
+    // the defaults no longer match, so the same file is rejected
+    let err = load_from_json(json, &LayerNames::default()).unwrap_err();
+    assert_eq!(err.to_string(), "`map` layer missing");
+}
+
+#[test]
+fn map_layer_data_is_typed() {
+    let layers = |data: &str| {
+        format!(
+            r#"{{ "layers": [
+                {{ "name": "map", "data": {data} }},
+                {{ "name": "scripts", "objects": [] }},
+                {{ "name": "locations", "objects": [] }}
+            ]}}"#
+        )
+    };
+
+    let proj = load_from_json(&layers("[1, 2, 65535, 0]"), &LayerNames::default()).unwrap();
+    assert_eq!(proj.map, vec![1, 2, 65535, 0]);
+
+    let err = load_from_json(&layers("[1, 65536]"), &LayerNames::default()).unwrap_err();
+    assert!(
+        err.to_string().contains("map tile 1 has gid 65536"),
+        "got: {err}"
+    );
+
+    let err = load_from_json(&layers(r#"[1, "x"]"#), &LayerNames::default()).unwrap_err();
+    assert!(err.to_string().contains("map tile 1"), "got: {err}");
+}
+
+#[test]
+fn map_layer_data_may_be_encoded() {
+    let layers = |extra: &str| {
+        format!(
+            r#"{{ "layers": [
+                {{ "name": "map", "encoding": "base64", {extra} }},
+                {{ "name": "scripts", "objects": [] }},
+                {{ "name": "locations", "objects": [] }}
+            ]}}"#
+        )
+    };
+    // the gids 1, 2, 3, 300 as little-endian u32s
+    for extra in [
+        r#""data": "AQAAAAIAAAADAAAALAEAAA==""#,
+        r#""compression": "", "data": "AQAAAAIAAAADAAAALAEAAA==""#,
+        r#""compression": "zlib", "data": "eJxjZGBgYAJiZiDWYWRgAAABAwA0""#,
+        r#""compression": "gzip", "data": "H4sIAAAAAAACA2NkYGBgAmJmINZhZGAAAAk5QcsQAAAA""#,
+    ] {
+        let proj = load_from_json(&layers(extra), &LayerNames::default()).expect(extra);
+        assert_eq!(proj.map, vec![1, 2, 3, 300], "{extra}");
+    }
+
+    for (extra, message) in [
+        (r#""data": "AQAAAAIA""#, "not a whole number of tiles"),
+        (r#""data": "AAAAAAAAAQA=""#, "map tile 1 has gid 65536"),
+        (
+            r#""compression": "zstd", "data": """#,
+            "unsupported compression `zstd`",
+        ),
+        (r#""compression": "zlib", "data": "AQAAAA==""#, "Inflating"),
+        (
+            &format!(r#""compression": "zlib", "data": "{ZEROS}""#),
+            "inflates past 262144 bytes",
+        ),
+        (r#""data": [1, 2]"#, "no `data` string"),
+    ] {
+        let err = load_from_json(&layers(extra), &LayerNames::default()).unwrap_err();
+        assert!(format!("{err:#}").contains(message), "{extra}: {err:#}");
+    }
+}
+
+/// 262148 zero bytes, zlib compressed: one tile more than the map holds.
+const ZEROS: &str = "\
+    eNrtwYEAAAAAw6D5Ux/gClUBAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\
+    AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\
+    AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\
+    AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\
+    AAAAAAAAALwBAEAAAQ==";
+
+#[test]
+fn map_gids_are_offset_by_firstgid() {
+    let project = |tilesets: &str, data: &str| {
+        format!(
+            r#"{{ "tilesets": {tilesets}, "layers": [
+                {{ "name": "map", "data": {data} }},
+                {{ "name": "scripts", "objects": [] }},
+                {{ "name": "locations", "objects": [] }}
+            ]}}"#
+        )
+    };
+    let one = r#"[{ "firstgid": 1, "source": "tiles.tsx" }]"#;
+    let proj = load_from_json(&project(one, "[1, 2, 300, 0]"), &LayerNames::default()).unwrap();
+    // an empty cell is not the tileset's first tile
+    assert_eq!(proj.map, vec![0, 1, 299, EMPTY_TILE]);
+
+    // gids from any tileset after the first are not the VM's tiles
+    let two =
+        r#"[{ "firstgid": 65, "source": "more.tsx" }, { "firstgid": 1, "source": "tiles.tsx" }]"#;
+    let proj = load_from_json(&project(two, "[1, 64]"), &LayerNames::default()).unwrap();
+    assert_eq!(proj.map, vec![0, 63]);
+    let err = load_from_json(&project(two, "[1, 65]"), &LayerNames::default()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("map tile 1 has gid 65, which is not in the first tileset"),
+        "got: {err}"
+    );
+
+    let err = load_from_json(&project("[{}]", "[1]"), &LayerNames::default()).unwrap_err();
+    assert!(
+        err.to_string().contains("tileset 0 has no `firstgid`"),
+        "got: {err}"
+    );
+}
+
+#[test]
+fn accepts_script_and_scripts_layer_names() {
+    for spelling in ["script", "scripts"] {
+        let json = format!(
+            r#"{{ "layers": [
+                {{ "name": "map", "data": [0] }},
+                {{ "name": "{spelling}", "objects": [
+                    {{ "id": 1, "x": 0.0, "y": 0.0,
+                      "properties": [{{ "name": "script", "value": "end;" }}] }}
+                ]}},
+                {{ "name": "locations", "objects": [] }}
+            ]}}"#
+        );
+        let proj = load_from_json(&json, &LayerNames::default())
+            .unwrap_or_else(|e| panic!("`{spelling}` rejected: {e}"));
+        assert_eq!(proj.scripts.objects.len(), 1);
+    }
+
+    let json = r#"{ "layers": [
+        { "name": "map", "data": [0] },
+        { "name": "locations", "objects": [] }
+    ]}"#;
+    let err = load_from_json(json, &LayerNames::default()).unwrap_err();
+    assert_eq!(err.to_string(), "`scripts` layer missing");
+}
+
+#[test]
+fn skips_unknown_layers() {
+    let json = r#"{
+        "layers": [
+            { "name": "decor", "data": [9, 9, 9] },
+            { "name": "map", "data": [0, 1, 2] },
+            { "name": "scripts", "objects": [] },
+            { "name": "collision", "objects": [{ "id": 3 }] },
+            { "name": "locations", "objects": [] }
+        ]
+    }"#;
+
+    let proj = load_from_json(json, &LayerNames::default()).expect("extra layers are skipped");
+    assert_eq!(proj.map, vec![0, 1, 2]);
+    assert!(proj.scripts.objects.is_empty());
+    assert!(proj.locations.objects.is_empty());
+}
+
+#[test]
+fn finds_layers_inside_groups() {
+    let json = r#"{
+        "layers": [
+            { "name": "world", "type": "group", "layers": [
+                { "name": "map", "type": "tilelayer", "data": [0, 1, 2] },
+                { "name": "logic", "type": "group", "layers": [
+                    { "name": "scripts", "type": "objectgroup", "objects": [
+                        { "id": 1, "x": 0.0, "y": 0.0,
+                          "properties": [{ "name": "script", "value": "end;" }] }
+                    ]}
+                ]}
+            ]},
+            { "name": "locations", "type": "objectgroup", "objects": [] }
+        ]
+    }"#;
+
+    let proj = load_from_json(json, &LayerNames::default()).expect("grouped layers load");
+    assert_eq!(proj.map, vec![0, 1, 2]);
+    assert_eq!(proj.scripts.objects.len(), 1);
+    assert!(proj.locations.objects.is_empty());
+}
+
+#[test]
+fn reads_typed_script_properties() {
+    let json = r#"{
+        "layers": [
+            { "name": "map", "data": [0] },
+            { "name": "scripts", "objects": [
+                { "id": 1, "x": 0.0, "y": 0.0, "properties": [
+                    { "name": "script", "type": "string", "value": "end;" },
+                    { "name": "priority", "type": "int", "value": 3 },
+                    { "name": "oneshot", "type": "bool", "value": true },
+                    { "name": "name", "type": "string", "value": "greeting" }
+                ]},
+                { "id": 2, "x": 16.0, "y": 0.0, "properties": [
+                    { "name": "script", "type": "string", "value": "end;" }
+                ]}
+            ]},
+            { "name": "locations", "objects": [] }
+        ]
+    }"#;
+
+    let proj = load_from_json(json, &LayerNames::default()).expect("typed properties load");
+    let [typed, plain] = &proj.scripts.objects[..] else {
+        panic!("expected two scripts");
+    };
+    assert_eq!(typed.script, "end;");
+    assert_eq!(typed.priority, 3);
+    assert!(typed.oneshot);
+    assert_eq!(typed.name.as_deref(), Some("greeting"));
+    // absent properties fall back to their defaults
+    assert_eq!(plain.priority, 0);
+    assert!(!plain.oneshot);
+    assert_eq!(plain.name, None);
+}
+
+#[test]
+fn rejects_duplicate_script_names() {
+    let json = r#"{
+        "layers": [
+            { "name": "map", "data": [0] },
+            { "name": "scripts", "objects": [
+                { "id": 1, "x": 0.0, "y": 0.0, "properties": [
+                    { "name": "script", "value": "end;" },
+                    { "name": "name", "type": "string", "value": "greeting" }
+                ]},
+                { "id": 2, "x": 16.0, "y": 0.0, "properties": [
+                    { "name": "script", "value": "end;" },
+                    { "name": "name", "type": "string", "value": "greeting" }
+                ]}
+            ]},
+            { "name": "locations", "objects": [] }
+        ]
+    }"#;
+
+    let err = load_from_json(json, &LayerNames::default()).unwrap_err();
+    assert!(
+        format!("{err:#}").contains("duplicate script name greeting"),
+        "got: {err:#}"
+    );
+}
+
+#[test]
+fn rejects_mistyped_script_properties() {
+    let json = r#"{
+        "layers": [
+            { "name": "map", "data": [0] },
+            { "name": "scripts", "objects": [
+                { "id": 1, "x": 0.0, "y": 0.0, "properties": [
+                    { "name": "script", "type": "string", "value": "end;" },
+                    { "name": "oneshot", "type": "string", "value": "yes" }
+                ]}
+            ]},
+            { "name": "locations", "objects": [] }
+        ]
+    }"#;
+
+    let err = load_from_json(json, &LayerNames::default()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("property `oneshot` must be of type bool, got string"),
+        "got: {err}"
+    );
+}
+
+#[test]
+fn world_map_loads_as_before() {
+    // captured from the `Value`-based loader the typed one replaced
+    let json = fs::read_to_string("tests/world_map.json").unwrap();
+    let proj = load_from_json(&json, &LayerNames::default()).expect("valid json");
+
+    let scripts: Vec<(i32, &str)> = proj
+        .scripts
+        .objects
+        .iter()
+        .map(|o| (o.id, o.script.as_str()))
+        .collect();
+    assert_eq!(
+        scripts,
+        [
+            (5, "tp @test_teleport @test_house;"),
+            (6, "tp @test_house @test_teleport;"),
+            (11, "if flag_test1 then tp 1 1 0 0 endif;"),
+        ]
+    );
+    let second = &proj.scripts.objects[1];
+    assert!((second.x - 135.686).abs() < 1e-3 && (second.y - 75.0188).abs() < 1e-3);
+
+    let locations: Vec<(&str, f32, f32)> = proj
+        .locations
+        .objects
+        .iter()
+        .map(|o| (o.name.as_str(), o.x, o.y))
+        .collect();
+    assert_eq!(
+        locations,
+        [
+            ("@test_house", 198.893, 120.768),
+            ("@test_teleport", 74.2188, 72.9167)
+        ]
+    );
+
+    assert_eq!(proj.map.len(), 65536);
+    assert_eq!(proj.map.iter().filter(|&&t| t != EMPTY_TILE).count(), 128);
+    let weighted: u64 = (1..)
+        .zip(&proj.map)
+        .filter(|&(_, &t)| t != EMPTY_TILE)
+        .map(|(i, &t)| i * u64::from(t))
+        .sum();
+    assert_eq!(weighted, 17147684);
+}
@@ -0,0 +1,965 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use clap::Parser;
+use pokervm_rust::checksum::crc32;
+use pokervm_rust::cli::Cli;
+use pokervm_rust::model::Endianness;
+use pokervm_rust::parser::{LayerNames, load};
+use pokervm_rust::writer::bin::HEADER_LEN;
+use pokervm_rust::{ast, compile_str, processor, read_input, run_with, tokens, writer};
+
+/// Fresh directory under the system temp dir.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cgt-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Tiled JSON whose map tile at (x, y) has gid `y * 256 + x`.
+fn world_json() -> String {
+    project_json(&[(16.0, 16.0, "msg {hi};")], &[])
+}
+
+/// Project JSON whose tile `i` holds gid `i`, with one script object per
+/// `(x, y, source)` and one location per `(name, x, y)`; objects are
+/// numbered from 1, scripts first.
+fn project_json(scripts: &[(f32, f32, &str)], locations: &[(&str, f32, f32)]) -> String {
+    let data: Vec<String> = (0..256 * 256).map(|i| i.to_string()).collect();
+    let scripts: Vec<String> = (1..)
+        .zip(scripts)
+        .map(|(id, (x, y, src))| {
+            format!(
+                r#"{{ "id": {id}, "x": {x:?}, "y": {y:?},
+                  "properties": [{{ "name": "script", "value": {} }}] }}"#,
+                serde_json::to_string(src).unwrap()
+            )
+        })
+        .collect();
+    let locations: Vec<String> = (scripts.len() + 1..)
+        .zip(locations)
+        .map(|(id, (name, x, y))| {
+            format!(r#"{{ "id": {id}, "name": "{name}", "x": {x:?}, "y": {y:?} }}"#)
+        })
+        .collect();
+    format!(
+        r#"{{
+        "layers": [
+            {{ "name": "map", "data": [{}] }},
+            {{ "name": "scripts", "objects": [
+                {}
+            ]}},
+            {{ "name": "locations", "objects": [{}] }}
+        ]
+    }}"#,
+        data.join(","),
+        scripts.join(",\n                "),
+        locations.join(", ")
+    )
+}
+
+#[test]
+fn map_layer_ends_up_chunked_in_map_bin() {
+    let raw = load(&world_json(), &LayerNames::default()).unwrap();
+    let processed = processor::run(&raw, &processor::Options::default()).unwrap();
+
+    let out = temp_dir("pipeline");
+    writer::bin::emit(&processed, &out, &Default::default()).unwrap();
+
+    let bytes = fs::read(out.join("map.bin")).unwrap();
+    // 2048 chunks of 8×4 little-endian u16 tiles
+    assert_eq!(bytes.len(), 2048 * 32 * 2);
+    let tile = |i: usize| u16::from_le_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+
+    // chunk 0: rows 0‥3 of columns 0‥7
+    assert_eq!(tile(0), 0);
+    assert_eq!(tile(7), 7);
+    assert_eq!(tile(8), 256);
+    assert_eq!(tile(31), 3 * 256 + 7);
+    // chunk 1 starts at column 8
+    assert_eq!(tile(32), 8);
+    // chunk 32 is the first one of the second chunk row (y = 4)
+    assert_eq!(tile(32 * 32), 4 * 256);
+}
+
+#[test]
+fn format_selects_the_artifacts() {
+    let dir = temp_dir("format");
+    let input = dir.join("world.json");
+    fs::write(&input, world_json()).unwrap();
+
+    for (format, c, bin) in [
+        ("c", true, false),
+        ("bin", false, true),
+        ("both", true, true),
+    ] {
+        let out = dir.join(format);
+        let args = Cli::try_parse_from([
+            "pokervm-rust".as_ref(),
+            input.as_os_str(),
+            out.as_os_str(),
+            "--format".as_ref(),
+            format.as_ref(),
+        ])
+        .unwrap();
+        run_with(args).unwrap();
+
+        assert_eq!(out.join("scripts.hpp").exists(), c, "{format}");
+        assert_eq!(out.join("opcodes.hpp").exists(), c, "{format}");
+        assert_eq!(out.join("scripts.bin").exists(), bin, "{format}");
+        assert_eq!(out.join("map.bin").exists(), bin, "{format}");
+    }
+
+    // `-f` is the short form, `both` the default
+    let args = Cli::try_parse_from(["pokervm-rust", "in.json", "out", "-f", "bin"]).unwrap();
+    assert!(!args.format.c() && args.format.bin());
+    let args = Cli::try_parse_from(["pokervm-rust", "in.json", "out"]).unwrap();
+    assert!(args.format.c() && args.format.bin());
+}
+
+#[test]
+fn output_prefix_names_every_file() {
+    let dir = temp_dir("prefix");
+    let input = dir.join("world.json");
+    fs::write(&input, world_json()).unwrap();
+    let out = dir.join("out");
+
+    let args = Cli::try_parse_from([
+        "pokervm-rust".as_ref(),
+        input.as_os_str(),
+        out.as_os_str(),
+        "--output-prefix".as_ref(),
+        "town".as_ref(),
+        "--emit-listing".as_ref(),
+    ])
+    .unwrap();
+    run_with(args).unwrap();
+
+    let mut files: Vec<String> = fs::read_dir(&out)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "town_all.hpp",
+            "town_flag_bit_array.hpp",
+            "town_flags.hpp",
+            "town_locations.hpp",
+            "town_map.bin",
+            "town_offsets.bin",
+            "town_opcodes.hpp",
+            "town_scripts.bin",
+            "town_scripts.hpp",
+            "town_scripts.lst",
+            "town_texts.bin",
+            "town_texts.hpp",
+        ]
+    );
+    let all = fs::read_to_string(out.join("town_all.hpp")).unwrap();
+    assert!(all.contains("#include \"town_scripts.hpp\"\n"), "{all}");
+}
+
+#[test]
+fn output_is_silent_unless_verbose() {
+    let dir = temp_dir("verbosity");
+    let input = dir.join("world.json");
+    fs::write(&input, world_json()).unwrap();
+
+    let run_bin = |extra: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_pokervm-rust"))
+            .arg(&input)
+            .arg(dir.join("out"))
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        (
+            String::from_utf8(out.stdout).unwrap(),
+            String::from_utf8(out.stderr).unwrap(),
+        )
+    };
+
+    let (stdout, stderr) = run_bin(&[]);
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "");
+
+    let (stdout, _) = run_bin(&["--verbose"]);
+    assert!(stdout.contains("Processing layer: map"), "{stdout}");
+    assert!(stdout.contains("Processing layer: scripts"), "{stdout}");
+}
+
+#[test]
+fn no_warn_unused_silences_unused_symbol_warnings() {
+    let dir = temp_dir("no-warn-unused");
+    let input = dir.join("world.json");
+    let json = world_json().replace("msg {hi};", "setflag flag_lost; msg {hi};");
+    fs::write(&input, json).unwrap();
+
+    let stderr = |extra: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_pokervm-rust"))
+            .arg(&input)
+            .arg(dir.join("out"))
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8(out.stderr).unwrap()
+    };
+
+    assert_eq!(
+        stderr(&[]),
+        "Warning: flag flag_lost is set but never read\n"
+    );
+    assert_eq!(stderr(&["--no-warn-unused"]), "");
+}
+
+#[test]
+fn dash_reads_input_from_stdin() {
+    let path = std::path::Path::new("tests/world_map.json");
+    let from_file = read_input(path, std::io::empty()).unwrap();
+
+    let from_stdin = read_input("-".as_ref(), from_file.as_bytes()).unwrap();
+    assert_eq!(from_stdin, from_file);
+}
+
+#[test]
+fn validate_writes_nothing() {
+    let dir = temp_dir("validate");
+    let good = dir.join("good.json");
+    fs::write(&good, world_json()).unwrap();
+
+    let out = dir.join("out");
+    let args = Cli::try_parse_from([
+        "pokervm-rust".as_ref(),
+        good.as_os_str(),
+        out.as_os_str(),
+        "--validate".as_ref(),
+    ])
+    .unwrap();
+    run_with(args).unwrap();
+    assert!(!out.exists());
+
+    // the output directory may be left out entirely
+    let args = Cli::try_parse_from([
+        "pokervm-rust".as_ref(),
+        good.as_os_str(),
+        "--validate".as_ref(),
+    ])
+    .unwrap();
+    run_with(args).unwrap();
+
+    // 50 three-byte messages in one chunk exceed its 128 bytes
+    let bad = dir.join("bad.json");
+    let body = "msg {x};".repeat(50);
+    fs::write(&bad, world_json().replace("msg {hi};", &body)).unwrap();
+    let args = Cli::try_parse_from([
+        "pokervm-rust".as_ref(),
+        bad.as_os_str(),
+        out.as_os_str(),
+        "--validate".as_ref(),
+    ])
+    .unwrap();
+    let err = run_with(args).unwrap_err();
+    assert!(format!("{err:#}").contains("too large"), "{err:#}");
+    assert!(!out.exists());
+
+    let summary = Command::new(env!("CARGO_BIN_EXE_pokervm-rust"))
+        .args([good.as_os_str(), "--validate".as_ref()])
+        .output()
+        .unwrap();
+    assert!(summary.status.success());
+    assert_eq!(
+        String::from_utf8(summary.stdout).unwrap(),
+        "1 scripts, 0 flags, 0 locations, 1 texts; largest chunk 4 of 128 bytes\n"
+    );
+}
+
+#[test]
+fn custom_chunk_layout() {
+    // tile (20, 10) is chunk column 1, row 1 of a 16×8 grid with 16 columns
+    let json = world_json().replace(r#""x": 16.0, "y": 16.0"#, r#""x": 320.0, "y": 160.0"#);
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let args = Cli::try_parse_from([
+        "pokervm-rust",
+        "in.json",
+        "out",
+        "--chunk-width",
+        "16",
+        "--chunk-height",
+        "8",
+    ])
+    .unwrap();
+    let opts = args.processor_options().unwrap();
+
+    let parsed =
+        processor::script_parser::parse_scripts(&raw.scripts, &HashMap::new(), &opts.layout)
+            .unwrap();
+    assert_eq!(parsed.chunks.len(), 16 * 32);
+    let placed: Vec<usize> = (0..parsed.chunks.len())
+        .filter(|&i| !parsed.chunks[i].is_empty())
+        .collect();
+    assert_eq!(placed, vec![17]);
+
+    let processed = processor::run(&raw, &opts).unwrap();
+    assert_eq!(processed.map.len(), 16 * 32);
+    assert_eq!(processed.map[17][0], 8 * 256 + 16);
+
+    for bad in [["--chunk-width", "7"], ["--chunk-height", "0"]] {
+        let args = Cli::try_parse_from(["pokervm-rust", "in.json", "out", bad[0], bad[1]]).unwrap();
+        assert!(args.processor_options().is_err(), "{bad:?}");
+    }
+}
+
+#[test]
+fn cpp_namespace_flag() {
+    let parse = |extra: &[&str]| {
+        let args = ["pokervm-rust", "in.json", "out"].iter().chain(extra);
+        Cli::try_parse_from(args).unwrap().c_options()
+    };
+    assert_eq!(parse(&[]).unwrap().namespace, None);
+    assert_eq!(
+        parse(&["--cpp-namespace"]).unwrap().namespace.as_deref(),
+        Some("cg")
+    );
+    assert_eq!(
+        parse(&["--cpp-namespace=game::vm"])
+            .unwrap()
+            .namespace
+            .as_deref(),
+        Some("game::vm")
+    );
+    for bad in [
+        "--cpp-namespace=2d",
+        "--cpp-namespace=a::",
+        "--cpp-namespace=a-b",
+    ] {
+        assert!(parse(&[bad]).is_err(), "{bad}");
+    }
+    // C has no namespaces to put the symbols in
+    assert_eq!(parse(&["--lang", "c"]).unwrap().lang, writer::c::Lang::C);
+    assert!(parse(&["--lang", "c", "--cpp-namespace"]).is_err());
+}
+
+#[test]
+fn max_script_len_is_a_hard_limit() {
+    let raw = load(&world_json(), &LayerNames::default()).unwrap();
+    let opts = |max: &str| {
+        Cli::try_parse_from(["pokervm-rust", "in.json", "out", "--max-script-len", max])
+            .unwrap()
+            .processor_options()
+            .unwrap()
+    };
+
+    // `msg {hi};` is 9 bytes
+    processor::run(&raw, &opts("9")).unwrap();
+    let Err(err) = processor::run(&raw, &opts("8")) else {
+        panic!("a 9-byte script passed a limit of 8");
+    };
+    assert_eq!(
+        err.to_string(),
+        "script object 1 at (16, 16) is 9 bytes long, over the limit of 8"
+    );
+}
+
+#[test]
+fn max_text_bytes_counts_utf8_bytes() {
+    // two characters, three bytes
+    let json = world_json().replace("msg {hi};", "msg {hé};");
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let opts = |max: &str| {
+        Cli::try_parse_from(["pokervm-rust", "in.json", "out", "--max-text-bytes", max])
+            .unwrap()
+            .processor_options()
+            .unwrap()
+    };
+
+    processor::run(&raw, &opts("3")).unwrap();
+    let Err(err) = processor::run(&raw, &opts("2")) else {
+        panic!("a 3-byte message passed a limit of 2");
+    };
+    assert_eq!(
+        err.to_string(),
+        "message \"hé\" is 3 bytes long, over the limit of 2"
+    );
+}
+
+#[test]
+fn no_empty_scripts_rejects_comment_only_scripts() {
+    let json = world_json().replace("msg {hi};", "/* msg {hi}; */");
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let opts = |extra: &[&str]| {
+        let args = ["pokervm-rust", "in.json", "out"].iter().chain(extra);
+        Cli::try_parse_from(args)
+            .unwrap()
+            .processor_options()
+            .unwrap()
+    };
+
+    // dropped by default
+    processor::run(&raw, &opts(&[])).unwrap();
+    let Err(err) = processor::run(&raw, &opts(&["--no-empty-scripts"])) else {
+        panic!("a comment-only script passed --no-empty-scripts");
+    };
+    assert_eq!(err.to_string(), "1 script has no commands: id 1");
+
+    let json = json.replace(
+        r#"{ "id": 1,"#,
+        r#"{ "id": 2, "x": 32.0, "y": 16.0,
+             "properties": [{ "name": "script", "value": "/* bye */" }] },
+           { "id": 1,"#,
+    );
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let Err(err) = processor::run(&raw, &opts(&["--no-empty-scripts"])) else {
+        panic!("two comment-only scripts passed --no-empty-scripts");
+    };
+    assert_eq!(err.to_string(), "2 scripts have no commands: ids 1, 2");
+}
+
+#[test]
+fn report_lists_counts_and_tables() {
+    let dir = temp_dir("report");
+    let input = dir.join("world.json");
+    let json = world_json().replace("msg {hi};", "setflag flag_seen; msg {hi};");
+    fs::write(&input, json).unwrap();
+    let report = dir.join("report.json");
+
+    let args = Cli::try_parse_from([
+        "pokervm-rust".as_ref(),
+        input.as_os_str(),
+        "--validate".as_ref(),
+        "--report".as_ref(),
+        report.as_os_str(),
+    ])
+    .unwrap();
+    run_with(args).unwrap();
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(report).unwrap()).unwrap();
+    assert_eq!(report["scripts"], 1);
+    assert_eq!(
+        report["flags"],
+        serde_json::json!([{ "name": "flag_seen", "index": 0 }])
+    );
+    assert_eq!(report["texts"][0]["name"], "hi");
+    assert_eq!(report["chunk_bytes"].as_array().unwrap().len(), 2048);
+    // setflag (3) + msg (3) + terminator
+    assert_eq!(report["chunk_bytes"][0], 7);
+    assert_eq!(report["chunk_bytes"][1], 1);
+    // the empty chunks share one terminator
+    assert_eq!(report["blobs"], 2);
+    assert_eq!(report["total_bytes"], 7 + 1);
+    assert_eq!(report["calls"], serde_json::json!([]));
+    assert!(report["warnings"].is_array());
+}
+
+#[test]
+fn report_lists_script_metadata() {
+    let dir = temp_dir("report-meta");
+    let input = dir.join("world.json");
+    let json = world_json().replace(
+        "msg {hi};",
+        "---\\nauthor: snail\\nversion: 2\\n---\\nmsg {hi};",
+    );
+    fs::write(&input, json).unwrap();
+    let report = dir.join("report.json");
+
+    let args = Cli::try_parse_from([
+        "pokervm-rust".as_ref(),
+        input.as_os_str(),
+        "--validate".as_ref(),
+        "--report".as_ref(),
+        report.as_os_str(),
+    ])
+    .unwrap();
+    run_with(args).unwrap();
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(report).unwrap()).unwrap();
+    assert_eq!(
+        report["metadata"],
+        serde_json::json!([{ "id": 1, "meta": { "author": "snail", "version": "2" } }])
+    );
+    // the front matter compiles to nothing: msg (3) + terminator
+    assert_eq!(report["chunk_bytes"][0], 4);
+}
+
+/// Compile `units` with `compiler` against the headers in `include` and
+/// link them into one program; `false` when there is no such compiler.
+fn links(compiler: &str, include: &std::path::Path, units: &[(&str, &str)]) -> bool {
+    let dir = include.join("units");
+    fs::create_dir_all(&dir).unwrap();
+    let mut cmd = Command::new(compiler);
+    cmd.arg("-I").arg(include).arg("-o").arg(dir.join("prog"));
+    for (name, src) in units {
+        fs::write(dir.join(name), src).unwrap();
+        cmd.arg(dir.join(name));
+    }
+    let Ok(out) = cmd.output() else {
+        eprintln!("no {compiler}, skipping");
+        return false;
+    };
+    assert!(
+        out.status.success(),
+        "{compiler} failed:\n{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    true
+}
+
+#[test]
+fn headers_link_into_two_translation_units() {
+    let json = world_json()
+        .replace(
+            r#""msg {hi};" }] }"#,
+            r#""setflag flag_a; tp @@home; msg {hi}; call @greet;" }] },
+                { "id": 3, "x": 144.0, "y": 0.0,
+                  "properties": [{ "name": "script", "value": "msg {hello};" },
+                                 { "name": "name", "type": "string", "value": "greet" }] }"#,
+        )
+        .replace(
+            r#"{ "name": "locations", "objects": [] }"#,
+            r#"{ "name": "locations", "objects": [
+                { "id": 2, "name": "home", "x": 32.0, "y": 32.0 } ] }"#,
+        );
+    let dir = temp_dir("two-units");
+    let input = dir.join("world.json");
+    fs::write(&input, json).unwrap();
+
+    for (lang, compiler, ext) in [("cpp", "c++", "cpp"), ("c", "cc", "c")] {
+        let out = dir.join(lang);
+        let args = Cli::try_parse_from([
+            "pokervm-rust".as_ref(),
+            input.as_os_str(),
+            out.as_os_str(),
+            "--lang".as_ref(),
+            lang.as_ref(),
+        ])
+        .unwrap();
+        run_with(args).unwrap();
+
+        // in C a local `home` must survive the location constant of that name
+        let (home, count) = match lang {
+            "c" => ("int home = CG_home;", "CG_TEXT_COUNT"),
+            _ => ("int at = home;", "TEXT_COUNT"),
+        };
+        let main = format!(
+            "#include \"all.hpp\"\nint other(void);\n\
+             int main(void) {{ {home} return TEXTS[0][0] + {count} + other(); }}\n"
+        );
+        let other = "#include \"all.hpp\"\n\
+             int other(void) { return LOCATIONS[0].x + SCRIPT_STARTS[0].offset\n\
+             + CHUNK_BLOB[0] + (CHUNK_SCRIPTS[0] != 0) + FLAG_BIT_ARRAY[0]; }\n";
+        links(
+            compiler,
+            &out,
+            &[
+                (&format!("main.{ext}"), &main),
+                (&format!("other.{ext}"), other),
+            ],
+        );
+    }
+}
+
+#[test]
+fn stats_report_chunk_sizes() {
+    // a second script in chunk 1, tile (9, 1)
+    let json = world_json().replace(
+        r#""msg {hi};" }] }"#,
+        r#""msg {hi};" }] },
+                { "id": 2, "x": 144.0, "y": 16.0,
+                  "properties": [{ "name": "script", "value": "wait 3; wait 4;" }] }"#,
+    );
+    let processed = compile_str(&json).unwrap();
+
+    // msg (3) + terminator, two waits (3 each) + terminator, and the
+    // terminator every other chunk shares
+    assert_eq!(processed.chunk_sizes(), vec![(0, 4), (1, 7)]);
+    assert_eq!(processed.largest_chunk(), Some((1, 7)));
+    assert_eq!(processed.total_size(), 4 + 7 + 1);
+
+    let dir = temp_dir("stats");
+    let input = dir.join("world.json");
+    fs::write(&input, &json).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_pokervm-rust"))
+        .args([input.as_os_str(), "--validate".as_ref(), "--stats".as_ref()])
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{out:?}");
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "12 script bytes in 3 blobs\n\
+         largest: chunk 1, 7 of 128 bytes\n\
+         chunk 0: 4 bytes\n\
+         chunk 1: 7 bytes\n\
+         2 scripts, 0 flags, 0 locations, 1 texts; largest chunk 7 of 128 bytes\n"
+    );
+}
+
+#[test]
+fn exit_codes_tell_failures_apart() {
+    let dir = temp_dir("exit");
+    let write = |name: &str, json: String| {
+        let path = dir.join(name);
+        fs::write(&path, json).unwrap();
+        path
+    };
+    let good = write("good.json", world_json());
+    let not_json = write("not_json.json", "{ layers: ".into());
+    let bad_script = write("bad_script.json", world_json().replace("msg {hi};", "msg;"));
+    let overflow = write(
+        "overflow.json",
+        world_json().replace("msg {hi};", &"msg {x};".repeat(50)),
+    );
+    // a file where the output directory should go
+    let blocked = write("blocked", String::new());
+
+    let code = |input: &PathBuf, output: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_pokervm-rust"))
+            .arg(input)
+            .arg(output)
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+    let out = dir.join("out");
+    assert_eq!(code(&good, &out), Some(0));
+    assert_eq!(code(&not_json, &out), Some(2));
+    assert_eq!(code(&bad_script, &out), Some(3));
+    assert_eq!(code(&overflow, &out), Some(4));
+    assert_eq!(code(&dir.join("missing.json"), &out), Some(5));
+    assert_eq!(code(&good, &blocked), Some(5));
+}
+
+#[test]
+fn bad_script_is_an_error_not_a_panic() {
+    let json = world_json().replace("msg {hi};", "msg;");
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let Err(err) = processor::run(&raw, &processor::Options::default()) else {
+        panic!("bad script compiled");
+    };
+    let msg = format!("{err:#}");
+    assert!(
+        msg.starts_with("Error parsing scripts: id 1 failed: "),
+        "{msg}"
+    );
+}
+
+#[test]
+fn scripts_bin_holds_the_assembled_chunks() {
+    let raw = load(&world_json(), &LayerNames::default()).unwrap();
+    let processed = processor::run(&raw, &processor::Options::default()).unwrap();
+    let scripts = &processed.blob;
+    assert_eq!(scripts.index.len(), 2048);
+    assert_eq!(scripts.chunk(0).script, "msg {hi};");
+    // every other chunk shares the lone terminator
+    assert_eq!(scripts.blob.len(), 2);
+
+    let out = temp_dir("scripts-bin");
+    writer::bin::emit(&processed, &out, &Default::default()).unwrap();
+    let bytes = fs::read(out.join("scripts.bin")).unwrap();
+
+    // header, the chunk → blob table, then every blob padded to 128 bytes
+    let table = &bytes[HEADER_LEN..HEADER_LEN + 2 * 2048];
+    assert_eq!(&table[..4], [0, 0, 1, 0]);
+    assert_eq!(&table[2 * 2047..], [1, 0]);
+    let slots = &bytes[HEADER_LEN + 2 * 2048..];
+    assert_eq!(slots.len(), 2 * 128);
+    let first = &scripts.blob[0].blob;
+    assert_eq!(&slots[..first.len()], first.as_slice());
+    assert_eq!(slots[128], 0xff);
+}
+
+#[test]
+fn offsets_bin_points_into_the_padded_scripts() {
+    // two scripts in chunk 1, the second one after a 3-byte msg
+    let json = world_json()
+        .replace(r#""x": 16.0, "y": 16.0"#, r#""x": 128.0, "y": 0.0"#)
+        .replace(
+            r#""msg {hi};" }] }"#,
+            r#""msg {hi};" }] },
+                { "id": 2, "x": 144.0, "y": 0.0,
+                  "properties": [{ "name": "script", "value": "wait 7;" }] }"#,
+        );
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let processed = processor::run(&raw, &processor::Options::default()).unwrap();
+    let out = temp_dir("offsets");
+    writer::bin::emit(&processed, &out, &Default::default()).unwrap();
+
+    let scripts = fs::read(out.join("scripts.bin")).unwrap();
+    let offsets = fs::read(out.join("offsets.bin")).unwrap();
+    let word = |i: usize| u16::from_le_bytes([offsets[2 * i], offsets[2 * i + 1]]) as usize;
+
+    // blob 0 (every chunk but 1): no scripts; blob 1 (chunk 1): two
+    assert_eq!(processed.blob.index[1], 1);
+    assert_eq!(word(0), 0);
+    assert_eq!(word(1), 2);
+    let slot1 = HEADER_LEN + 2 * 2048 + 128;
+    let starts = [slot1 + word(2), slot1 + word(3)];
+    assert_eq!(starts, [slot1, slot1 + 3]);
+    assert_eq!(scripts[starts[0]], 0); // msg
+    assert_eq!(scripts[starts[1]], 8); // wait
+    assert_eq!(offsets.len(), 2 * 4);
+}
+
+#[test]
+fn call_resolves_to_the_called_scripts_start() {
+    // `greet` is the second script of chunk 1, after a 3-byte wait
+    let json = world_json().replace(
+        r#""msg {hi};" }] }"#,
+        r#""call @greet;" }] },
+                { "id": 2, "x": 128.0, "y": 0.0,
+                  "properties": [{ "name": "script", "value": "wait 7;" }] },
+                { "id": 3, "x": 144.0, "y": 0.0,
+                  "properties": [{ "name": "script", "value": "msg {hi};" },
+                                 { "name": "name", "type": "string", "value": "greet" }] }"#,
+    );
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let processed = processor::run(&raw, &processor::Options::default()).unwrap();
+    assert_eq!(processed.scripts["greet"], 0);
+    assert_eq!(processed.blob.calls, [(1, 3)]);
+
+    let out = temp_dir("calls");
+    writer::bin::emit(&processed, &out, &Default::default()).unwrap();
+    writer::c::emit(&processed, &out, &Default::default()).unwrap();
+
+    let bytes = fs::read(out.join("scripts.bin")).unwrap();
+    assert_eq!(&bytes[18..20], [1, 0], "called script count");
+    let calls = HEADER_LEN + 2 * 2048;
+    assert_eq!(&bytes[calls..calls + 4], [1, 0, 3, 0]);
+    // follow the entry: chunk 1's blob slot, then the offset
+    let blob = usize::from(u16::from_le_bytes([
+        bytes[HEADER_LEN + 2],
+        bytes[HEADER_LEN + 3],
+    ]));
+    let start = calls + 4 + blob * 128 + 3;
+    assert_eq!(bytes[start], 0, "msg");
+
+    let hpp = fs::read_to_string(out.join("scripts.hpp")).unwrap();
+    assert!(
+        hpp.contains("static constexpr uint16_t SCRIPT_COUNT = 1;"),
+        "{hpp}"
+    );
+    assert!(
+        hpp.contains("SCRIPT_STARTS[SCRIPT_COUNT] = {\n    { 1, 3 }, // greet\n};"),
+        "{hpp}"
+    );
+}
+
+#[test]
+fn call_of_a_script_without_commands_is_rejected() {
+    let json = world_json().replace(
+        r#""msg {hi};" }] }"#,
+        r#""call @greet;" }] },
+                { "id": 2, "x": 144.0, "y": 0.0,
+                  "properties": [{ "name": "script", "value": "/* later */" },
+                                 { "name": "name", "type": "string", "value": "greet" }] }"#,
+    );
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let Err(err) = processor::run(&raw, &processor::Options::default()) else {
+        panic!("a call of an empty script compiled");
+    };
+    assert_eq!(
+        err.to_string(),
+        "script greet is called but assembles to no commands"
+    );
+}
+
+#[test]
+fn scripts_bin_starts_with_a_header() {
+    let json = world_json().replace("msg {hi};", "setflag flag_a; msg {hi}; msg {yo};");
+    let raw = load(&json, &LayerNames::default()).unwrap();
+    let processed = processor::run(&raw, &processor::Options::default()).unwrap();
+    let out = temp_dir("header");
+    writer::bin::emit(&processed, &out, &Default::default()).unwrap();
+
+    let bytes = fs::read(out.join("scripts.bin")).unwrap();
+    assert_eq!(
+        &bytes[..HEADER_LEN],
+        [
+            b'C', b'G', b'V', b'M', //
+            5, 0, // version
+            0x00, 0x08, // 2048 chunks
+            2, 0, // blobs
+            128, 0, // bytes per blob
+            1, 0, // flags
+            0, 0, // locations
+            2, 0, // texts
+            0, 0, // called scripts
+            0, 0, // flags
+        ]
+    );
+
+    let opts = writer::bin::Options {
+        compress_map: true,
+        ..Default::default()
+    };
+    writer::bin::emit(&processed, &out, &opts).unwrap();
+    let bytes = fs::read(out.join("scripts.bin")).unwrap();
+    assert_eq!(&bytes[20..22], [1, 0], "map.bin RLE flag");
+    // map tiles are all distinct, so every tile is a run of one
+    assert_eq!(fs::read(out.join("map.bin")).unwrap().len(), 65536 * 3);
+}
+
+#[test]
+fn checksum_ends_scripts_and_map_bin() {
+    let raw = load(&world_json(), &LayerNames::default()).unwrap();
+    let processed = processor::run(&raw, &processor::Options::default()).unwrap();
+    let out = temp_dir("checksum");
+    writer::bin::emit(&processed, &out, &Default::default()).unwrap();
+    let plain = fs::read(out.join("scripts.bin")).unwrap();
+    let plain_map = fs::read(out.join("map.bin")).unwrap();
+
+    let opts = writer::bin::Options {
+        checksum: true,
+        ..Default::default()
+    };
+    writer::bin::emit(&processed, &out, &opts).unwrap();
+    let scripts = fs::read(out.join("scripts.bin")).unwrap();
+    let map = fs::read(out.join("map.bin")).unwrap();
+
+    // the same bytes but for the flag, then 4 more
+    assert_eq!(&scripts[20..22], [4, 0], "CRC-32 flag");
+    assert_eq!(scripts[..20], plain[..20]);
+    assert_eq!(scripts[22..plain.len()], plain[22..]);
+    assert_eq!(scripts.len(), plain.len() + 4);
+    assert_eq!(map[..plain_map.len()], plain_map);
+    assert_eq!(map.len(), plain_map.len() + 4);
+    let footer = |bytes: &[u8]| {
+        let (body, crc) = bytes.split_at(bytes.len() - 4);
+        assert_eq!(crc, crc32(body).to_le_bytes());
+    };
+    footer(&scripts);
+    footer(&map);
+
+    // the footer stays little-endian in big-endian output
+    let big = processor::Options {
+        endianness: Endianness::Big,
+        ..Default::default()
+    };
+    let processed = processor::run(&raw, &big).unwrap();
+    writer::bin::emit(&processed, &out, &opts).unwrap();
+    footer(&fs::read(out.join("scripts.bin")).unwrap());
+    footer(&fs::read(out.join("map.bin")).unwrap());
+}
+
+#[test]
+fn endianness_applies_to_every_artifact() {
+    // tile (1, 0) has gid 1; `wait 0x1234` puts a known u16 in the bytecode
+    let json = world_json().replace("msg {hi};", "wait 0x1234; msg {hi};");
+    let dir = temp_dir("endianness");
+    let input = dir.join("world.json");
+    fs::write(&input, json).unwrap();
+
+    let emit = |order: &str| {
+        let out = dir.join(order);
+        let args = Cli::try_parse_from([
+            "pokervm-rust".as_ref(),
+            input.as_os_str(),
+            out.as_os_str(),
+            "--endianness".as_ref(),
+            order.as_ref(),
+        ])
+        .unwrap();
+        run_with(args).unwrap();
+        let read = |name: &str| fs::read(out.join(name)).unwrap();
+        (read("scripts.bin"), read("map.bin"), read("texts.bin"))
+    };
+
+    let (scripts, map, texts) = emit("little");
+    let code = HEADER_LEN + 2 * 2048;
+    assert_eq!(&scripts[4..6], [5, 0]);
+    assert_eq!(&scripts[HEADER_LEN..HEADER_LEN + 4], [0, 0, 1, 0]);
+    assert_eq!(&scripts[code..code + 3], [8, 0x34, 0x12]);
+    assert_eq!(&map[2..4], [1, 0]);
+    assert_eq!(&texts[..2], [2, 0]);
+
+    let (scripts, map, texts) = emit("big");
+    assert_eq!(&scripts[4..6], [0, 5]);
+    assert_eq!(&scripts[20..22], [0, 2], "big-endian flag");
+    assert_eq!(&scripts[HEADER_LEN..HEADER_LEN + 4], [0, 0, 0, 1]);
+    assert_eq!(&scripts[code..code + 3], [8, 0x12, 0x34]);
+    assert_eq!(&map[2..4], [0, 1]);
+    assert_eq!(&texts[..2], [0, 2]);
+}
+
+#[test]
+fn compile_str_builds_the_tables_in_memory() {
+    let json = project_json(
+        &[
+            (
+                0.0,
+                0.0,
+                "if flag_met then msg {again} endif; setflag flag_met; msg {hello};",
+            ),
+            (32.0, 0.0, "tp @home;"),
+        ],
+        &[("home", 64.0, 64.0)],
+    );
+    let processed = compile_str(&json).unwrap();
+
+    assert_eq!(processed.flags.len(), 1);
+    assert_eq!(processed.flags["flag_met"], 0);
+    assert_eq!(processed.texts["again"], 0);
+    assert_eq!(processed.texts["hello"], 1);
+    assert_eq!(processed.locations["home"], 0);
+    assert_eq!(processed.map.len(), 2048);
+
+    assert!(compile_str("{}").is_err());
+}
+
+#[test]
+fn tokens_subcommand_prints_one_token_per_line() {
+    let args = Cli::try_parse_from(["pokervm-rust", "tokens", "msg @loc {hi};"]).unwrap();
+    let Some(pokervm_rust::cli::Command::Tokens(source)) = &args.command else {
+        panic!("expected the tokens subcommand, got {args:?}");
+    };
+    assert_eq!(
+        tokens(&source.read().unwrap()).unwrap(),
+        "1:1 Ident(\"msg\")\n\
+         1:5 At(\"loc\")\n\
+         1:10 Text(\"hi\")\n\
+         1:14 Semicolon\n\
+         1:15 Eof\n"
+    );
+
+    let err = tokens("msg {hi").unwrap_err();
+    assert_eq!(err.to_string(), "1:5: no closing } found");
+
+    // a project is neither needed nor allowed next to the subcommand
+    assert!(Cli::try_parse_from(["pokervm-rust", "tokens"]).is_err());
+    assert!(Cli::try_parse_from(["pokervm-rust", "in.json", "out", "tokens", "end;"]).is_err());
+}
+
+#[test]
+fn ast_subcommand_indents_nested_ifs() {
+    let src = "if flag_a then msg {a} else if !flag_b then setflag flag_b; wait 2 endif; end;";
+    let args = Cli::try_parse_from(["pokervm-rust", "ast", src]).unwrap();
+    let Some(pokervm_rust::cli::Command::Ast(source)) = &args.command else {
+        panic!("expected the ast subcommand, got {args:?}");
+    };
+    // the chain's `else if` is an `if` inside the first one's else branch
+    assert_eq!(
+        ast(&source.read().unwrap()).unwrap(),
+        "If flag_a\n\
+        \x20 Then\n\
+        \x20   Msg \"a\"\n\
+        \x20 Else\n\
+        \x20   If !flag_b\n\
+        \x20     Then\n\
+        \x20       SetFlag flag_b\n\
+        \x20       Wait 2\n\
+         End\n"
+    );
+
+    let err = ast("if flag_a then msg {a}").unwrap_err();
+    assert!(
+        err.to_string().contains("close 'if' opened at line 1"),
+        "{err}"
+    );
+}